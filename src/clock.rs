@@ -0,0 +1,40 @@
+extern crate time;
+
+use std::sync::{Arc, Mutex};
+use std::time::duration::Duration;
+
+pub trait Clock {
+    fn now(&self) -> time::Timespec;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> time::Timespec {
+        time::now().to_timespec()
+    }
+}
+
+// Time moves only when told to. Clones share the same time, so a test keeps
+// one and hands the other to the node.
+#[deriving(Clone)]
+pub struct ManualClock {
+    now: Arc < Mutex < time::Timespec > >,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock { now: Arc::new(Mutex::new(time::now().to_timespec())) }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock();
+        *now = *now + duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> time::Timespec {
+        *self.now.lock()
+    }
+}
@@ -0,0 +1,441 @@
+use std::io::{IoResult, IoError, InvalidInput, MemWriter};
+use std::{char, mem, str};
+
+use serialize::{Encodable, Decodable, Encoder, Decoder, json};
+
+use super::intercommunication::Package;
+use super::replication::Committable;
+
+// Version of the binary wire format, written as the first byte of every
+// package. Variants and fields are encoded in their declaration order, so
+// new package variants go to the end of PackageDetails; any other change of
+// a packaged type needs a new version.
+pub static PROTOCOL_VERSION: u8 = 1;
+
+pub trait Codec < T: Committable + Send > {
+    fn encode(&self, package: &Package < T >) -> Vec < u8 >;
+    fn decode(&self, bytes: &[u8]) -> IoResult < Package < T > >;
+}
+
+// Codec by name, for settings passed around between tasks
+#[deriving(Clone, Show, PartialEq)]
+pub enum WireFormat {
+    BinaryFormat,
+    JsonFormat,
+}
+
+// Readable, but slow and large; meant for debugging
+#[deriving(Clone, Show)]
+pub struct JsonCodec;
+
+// package layout: version (u8) | package encoded with BinaryEncoder
+#[deriving(Clone, Show)]
+pub struct BinaryCodec;
+
+impl < 'a, T: Committable + Send + Encodable < json::Encoder < 'a >, IoError > + Decodable < json::Decoder, json::DecoderError > > Codec < T > for JsonCodec {
+    fn encode(&self, package: &Package < T >) -> Vec < u8 > {
+        json::encode(package).into_bytes()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> IoResult < Package < T > > {
+        let text = match str::from_utf8(bytes) {
+            Some(text) => text,
+            None => return Err(malformed("package is not utf-8".to_string())),
+        };
+
+        match json::decode(text) {
+            Ok(package) => Ok(package),
+            Err(error) => Err(malformed(error.to_string())),
+        }
+    }
+}
+
+impl < T: Committable + Send + Encodable < BinaryEncoder, IoError > + Decodable < BinaryDecoder, IoError > > Codec < T > for BinaryCodec {
+    fn encode(&self, package: &Package < T >) -> Vec < u8 > {
+        let mut encoder = BinaryEncoder::new();
+        encoder.writer.write_u8(PROTOCOL_VERSION).unwrap();
+
+        // writing to memory does not fail
+        package.encode(&mut encoder).unwrap();
+        encoder.unwrap()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> IoResult < Package < T > > {
+        let mut decoder = BinaryDecoder::new(bytes);
+
+        let version = try!(decoder.read_u8());
+        if version != PROTOCOL_VERSION {
+            return Err(IoError {
+                kind: InvalidInput,
+                desc: "unsupported protocol version",
+                detail: Some(format!("{}", version)),
+            });
+        }
+
+        let package = try!(Decodable::decode(&mut decoder));
+
+        if decoder.position != decoder.bytes.len() {
+            return Err(malformed("trailing bytes after package".to_string()));
+        }
+
+        Ok(package)
+    }
+}
+
+// Unsigned numbers, lengths and variant ids are LEB128 varints, signed
+// numbers are zigzag encoded varints. Field names are not written.
+pub struct BinaryEncoder {
+    writer: MemWriter,
+}
+
+impl BinaryEncoder {
+    pub fn new() -> BinaryEncoder {
+        BinaryEncoder { writer: MemWriter::new() }
+    }
+
+    pub fn unwrap(self) -> Vec < u8 > {
+        self.writer.unwrap()
+    }
+
+    fn emit_varint(&mut self, mut v: u64) -> IoResult < () > {
+        loop {
+            let byte = (v & 0x7f) as u8;
+            v = v >> 7;
+
+            if v == 0 {
+                return self.writer.write_u8(byte);
+            }
+
+            try!(self.writer.write_u8(byte | 0x80));
+        }
+    }
+
+    fn emit_zigzag(&mut self, v: i64) -> IoResult < () > {
+        self.emit_varint(((v << 1) ^ (v >> 63)) as u64)
+    }
+}
+
+impl Encoder < IoError > for BinaryEncoder {
+    fn emit_nil(&mut self) -> IoResult < () > { Ok(()) }
+
+    fn emit_uint(&mut self, v: uint) -> IoResult < () > { self.emit_varint(v as u64) }
+    fn emit_u64(&mut self, v: u64) -> IoResult < () > { self.emit_varint(v) }
+    fn emit_u32(&mut self, v: u32) -> IoResult < () > { self.emit_varint(v as u64) }
+    fn emit_u16(&mut self, v: u16) -> IoResult < () > { self.emit_varint(v as u64) }
+    fn emit_u8(&mut self, v: u8) -> IoResult < () > { self.writer.write_u8(v) }
+
+    fn emit_int(&mut self, v: int) -> IoResult < () > { self.emit_zigzag(v as i64) }
+    fn emit_i64(&mut self, v: i64) -> IoResult < () > { self.emit_zigzag(v) }
+    fn emit_i32(&mut self, v: i32) -> IoResult < () > { self.emit_zigzag(v as i64) }
+    fn emit_i16(&mut self, v: i16) -> IoResult < () > { self.emit_zigzag(v as i64) }
+    fn emit_i8(&mut self, v: i8) -> IoResult < () > { self.writer.write_i8(v) }
+
+    fn emit_bool(&mut self, v: bool) -> IoResult < () > { self.writer.write_u8(if v { 1 } else { 0 }) }
+
+    fn emit_f64(&mut self, v: f64) -> IoResult < () > {
+        self.writer.write_be_u64(unsafe { mem::transmute(v) })
+    }
+
+    fn emit_f32(&mut self, v: f32) -> IoResult < () > {
+        self.writer.write_be_u32(unsafe { mem::transmute(v) })
+    }
+
+    fn emit_char(&mut self, v: char) -> IoResult < () > { self.emit_varint(v as u64) }
+
+    fn emit_str(&mut self, v: &str) -> IoResult < () > {
+        try!(self.emit_varint(v.len() as u64));
+        self.writer.write(v.as_bytes())
+    }
+
+    fn emit_enum(&mut self, _: &str, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        f(self)
+    }
+
+    fn emit_enum_variant(&mut self, _: &str, id: uint, _: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        try!(self.emit_varint(id as u64));
+        f(self)
+    }
+
+    fn emit_enum_variant_arg(&mut self, _: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        f(self)
+    }
+
+    fn emit_enum_struct_variant(&mut self, name: &str, id: uint, len: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        self.emit_enum_variant(name, id, len, f)
+    }
+
+    fn emit_enum_struct_variant_field(&mut self, _: &str, _: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        f(self)
+    }
+
+    fn emit_struct(&mut self, _: &str, _: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        f(self)
+    }
+
+    fn emit_struct_field(&mut self, _: &str, _: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        f(self)
+    }
+
+    fn emit_tuple(&mut self, len: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        self.emit_seq(len, f)
+    }
+
+    fn emit_tuple_arg(&mut self, idx: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        self.emit_seq_elt(idx, f)
+    }
+
+    fn emit_tuple_struct(&mut self, _: &str, len: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        self.emit_seq(len, f)
+    }
+
+    fn emit_tuple_struct_arg(&mut self, idx: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        self.emit_seq_elt(idx, f)
+    }
+
+    fn emit_option(&mut self, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        f(self)
+    }
+
+    fn emit_option_none(&mut self) -> IoResult < () > {
+        self.writer.write_u8(0)
+    }
+
+    fn emit_option_some(&mut self, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        try!(self.writer.write_u8(1));
+        f(self)
+    }
+
+    fn emit_seq(&mut self, len: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        try!(self.emit_varint(len as u64));
+        f(self)
+    }
+
+    fn emit_seq_elt(&mut self, _: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        f(self)
+    }
+
+    fn emit_map(&mut self, len: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        self.emit_seq(len, f)
+    }
+
+    fn emit_map_elt_key(&mut self, _: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        f(self)
+    }
+
+    fn emit_map_elt_val(&mut self, _: uint, f: |&mut BinaryEncoder| -> IoResult < () >) -> IoResult < () > {
+        f(self)
+    }
+}
+
+pub struct BinaryDecoder {
+    bytes: Vec < u8 >,
+    position: uint,
+}
+
+impl BinaryDecoder {
+    pub fn new(bytes: &[u8]) -> BinaryDecoder {
+        BinaryDecoder {
+            bytes: bytes.to_vec(),
+            position: 0,
+        }
+    }
+
+    fn read_byte(&mut self) -> IoResult < u8 > {
+        if self.position >= self.bytes.len() {
+            return Err(malformed("package is truncated".to_string()));
+        }
+
+        self.position += 1;
+        Ok(self.bytes[self.position - 1])
+    }
+
+    fn read_bytes(&mut self, count: uint) -> IoResult < &[u8] > {
+        if self.bytes.len() - self.position < count {
+            return Err(malformed("package is truncated".to_string()));
+        }
+
+        self.position += count;
+        Ok(self.bytes.slice(self.position - count, self.position))
+    }
+
+    fn read_varint(&mut self) -> IoResult < u64 > {
+        let mut v = 0u64;
+
+        for shift in range(0, 10u) {
+            let byte = try!(self.read_byte());
+            v = v | ((byte & 0x7f) as u64 << (shift * 7));
+
+            if byte & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+
+        Err(malformed("varint is too long".to_string()))
+    }
+
+    fn read_zigzag(&mut self) -> IoResult < i64 > {
+        let v = try!(self.read_varint());
+        Ok((v >> 1) as i64 ^ -((v & 1) as i64))
+    }
+
+    // length of a sequence can not be larger than what is left, so a damaged
+    // length does not allocate all the memory
+    fn read_len(&mut self) -> IoResult < uint > {
+        let len = try!(self.read_varint()) as uint;
+
+        if len > self.bytes.len() - self.position {
+            return Err(malformed(format!("length {} is past the end of package", len)));
+        }
+
+        Ok(len)
+    }
+}
+
+impl Decoder < IoError > for BinaryDecoder {
+    fn read_nil(&mut self) -> IoResult < () > { Ok(()) }
+
+    fn read_uint(&mut self) -> IoResult < uint > { Ok(try!(self.read_varint()) as uint) }
+    fn read_u64(&mut self) -> IoResult < u64 > { self.read_varint() }
+    fn read_u32(&mut self) -> IoResult < u32 > { Ok(try!(self.read_varint()) as u32) }
+    fn read_u16(&mut self) -> IoResult < u16 > { Ok(try!(self.read_varint()) as u16) }
+    fn read_u8(&mut self) -> IoResult < u8 > { self.read_byte() }
+
+    fn read_int(&mut self) -> IoResult < int > { Ok(try!(self.read_zigzag()) as int) }
+    fn read_i64(&mut self) -> IoResult < i64 > { self.read_zigzag() }
+    fn read_i32(&mut self) -> IoResult < i32 > { Ok(try!(self.read_zigzag()) as i32) }
+    fn read_i16(&mut self) -> IoResult < i16 > { Ok(try!(self.read_zigzag()) as i16) }
+    fn read_i8(&mut self) -> IoResult < i8 > { Ok(try!(self.read_byte()) as i8) }
+
+    fn read_bool(&mut self) -> IoResult < bool > {
+        match try!(self.read_byte()) {
+            0 => Ok(false),
+            1 => Ok(true),
+            x => Err(malformed(format!("{} is not a bool", x))),
+        }
+    }
+
+    fn read_f64(&mut self) -> IoResult < f64 > {
+        let bytes = try!(self.read_bytes(8));
+        let mut v = 0u64;
+        for &byte in bytes.iter() {
+            v = (v << 8) | byte as u64;
+        }
+        Ok(unsafe { mem::transmute(v) })
+    }
+
+    fn read_f32(&mut self) -> IoResult < f32 > {
+        let bytes = try!(self.read_bytes(4));
+        let mut v = 0u32;
+        for &byte in bytes.iter() {
+            v = (v << 8) | byte as u32;
+        }
+        Ok(unsafe { mem::transmute(v) })
+    }
+
+    fn read_char(&mut self) -> IoResult < char > {
+        let v = try!(self.read_varint());
+
+        match char::from_u32(v as u32) {
+            Some(c) if v <= 0x10ffff => Ok(c),
+            _ => Err(malformed(format!("{} is not a char", v))),
+        }
+    }
+
+    fn read_str(&mut self) -> IoResult < String > {
+        let len = try!(self.read_len());
+        let bytes = try!(self.read_bytes(len));
+
+        match str::from_utf8(bytes) {
+            Some(text) => Ok(text.to_string()),
+            None => Err(malformed("string is not utf-8".to_string())),
+        }
+    }
+
+    fn read_enum < X >(&mut self, _: &str, f: |&mut BinaryDecoder| -> IoResult < X >) -> IoResult < X > {
+        f(self)
+    }
+
+    fn read_enum_variant < X >(&mut self, names: &[&str], f: |&mut BinaryDecoder, uint| -> IoResult < X >) -> IoResult < X > {
+        let id = try!(self.read_varint()) as uint;
+
+        if id >= names.len() {
+            return Err(malformed(format!("unknown variant {}", id)));
+        }
+
+        f(self, id)
+    }
+
+    fn read_enum_variant_arg < X >(&mut self, _: uint, f: |&mut BinaryDecoder| -> IoResult < X >) -> IoResult < X > {
+        f(self)
+    }
+
+    fn read_enum_struct_variant < X >(&mut self, names: &[&str], f: |&mut BinaryDecoder, uint| -> IoResult < X >) -> IoResult < X > {
+        self.read_enum_variant(names, f)
+    }
+
+    fn read_enum_struct_variant_field < X >(&mut self, _: &str, _: uint, f: |&mut BinaryDecoder| -> IoResult < X >) -> IoResult < X > {
+        f(self)
+    }
+
+    fn read_struct < X >(&mut self, _: &str, _: uint, f: |&mut BinaryDecoder| -> IoResult < X >) -> IoResult < X > {
+        f(self)
+    }
+
+    fn read_struct_field < X >(&mut self, _: &str, _: uint, f: |&mut BinaryDecoder| -> IoResult < X >) -> IoResult < X > {
+        f(self)
+    }
+
+    fn read_tuple < X >(&mut self, f: |&mut BinaryDecoder, uint| -> IoResult < X >) -> IoResult < X > {
+        self.read_seq(f)
+    }
+
+    fn read_tuple_arg < X >(&mut self, idx: uint, f: |&mut BinaryDecoder| -> IoResult < X >) -> IoResult < X > {
+        self.read_seq_elt(idx, f)
+    }
+
+    fn read_tuple_struct < X >(&mut self, _: &str, f: |&mut BinaryDecoder, uint| -> IoResult < X >) -> IoResult < X > {
+        self.read_seq(f)
+    }
+
+    fn read_tuple_struct_arg < X >(&mut self, idx: uint, f: |&mut BinaryDecoder| -> IoResult < X >) -> IoResult < X > {
+        self.read_seq_elt(idx, f)
+    }
+
+    fn read_option < X >(&mut self, f: |&mut BinaryDecoder, bool| -> IoResult < X >) -> IoResult < X > {
+        let is_some = try!(self.read_bool());
+        f(self, is_some)
+    }
+
+    fn read_seq < X >(&mut self, f: |&mut BinaryDecoder, uint| -> IoResult < X >) -> IoResult < X > {
+        let len = try!(self.read_len());
+        f(self, len)
+    }
+
+    fn read_seq_elt < X >(&mut self, _: uint, f: |&mut BinaryDecoder| -> IoResult < X >) -> IoResult < X > {
+        f(self)
+    }
+
+    fn read_map < X >(&mut self, f: |&mut BinaryDecoder, uint| -> IoResult < X >) -> IoResult < X > {
+        self.read_seq(f)
+    }
+
+    fn read_map_elt_key < X >(&mut self, _: uint, f: |&mut BinaryDecoder| -> IoResult < X >) -> IoResult < X > {
+        f(self)
+    }
+
+    fn read_map_elt_val < X >(&mut self, _: uint, f: |&mut BinaryDecoder| -> IoResult < X >) -> IoResult < X > {
+        f(self)
+    }
+
+    fn error(&mut self, error: &str) -> IoError {
+        malformed(error.to_string())
+    }
+}
+
+fn malformed(detail: String) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: "malformed package",
+        detail: Some(detail),
+    }
+}
@@ -0,0 +1,710 @@
+use std::io;
+use std::io::{File, Open, Append, Write, SeekSet, MemWriter, BufReader, IoResult, IoError};
+use std::io::fs;
+use std::io::fs::PathExtensions;
+
+use std::default::Default;
+use std::cell::RefCell;
+use std::{cmp, mem, str};
+
+use serialize::{Encodable, Decodable, json};
+
+use super::replication::{ReplicationLog, Committable, Receivable, Queriable, LogPersistence, PersistenceQuery, SnapshotPersistence, Snapshot};
+use super::replication::{Entry, CommandEntry, ConfigurationEntry, RegisterSessionEntry, SessionCommandEntry};
+use super::session::{Sessions, FreshRequest, DuplicateRequest, UnknownSession, encode_response, decode_response};
+use super::storage::sync_directory;
+
+static DEFAULT_DIRECTORY: &'static str = "raft-log";
+static SEGMENT_EXTENSION: &'static str = "log";
+static SNAPSHOT_FILENAME: &'static str = "snapshot";
+
+// record layout: body_size (u32) | crc32 of body (u32) | body
+// body layout: term (u64) | entry encoded as json
+static HEADER_SIZE: uint = 8;
+static TERM_SIZE: uint = 8;
+
+#[deriving(Clone, Show, PartialEq)]
+pub enum SyncPolicy {
+    // fsync after every appended entry
+    SyncAlways,
+
+    // SyncEvery(entries) - fsync once per that many appended entries
+    SyncEvery(uint),
+
+    // leave flushing to the operating system
+    SyncNever,
+}
+
+#[deriving(Clone, Show)]
+pub struct FileLogOptions {
+    pub segment_size: u64,
+    pub sync: SyncPolicy,
+}
+
+impl Default for FileLogOptions {
+    fn default() -> FileLogOptions {
+        FileLogOptions {
+            segment_size: 64 * 1024 * 1024,
+            sync: SyncAlways,
+        }
+    }
+}
+
+struct Segment {
+    first_offset: uint,
+    path: Path,
+    size: u64,
+    // opened on the first read and kept for every read after it
+    reader: RefCell < Option < File > >,
+}
+
+impl Segment {
+    fn read_record_at(&self, position: u64) -> IoResult < Vec < u8 > > {
+        let mut reader = self.reader.borrow_mut();
+
+        if reader.is_none() {
+            *reader = Some(try!(File::open(&self.path)));
+        }
+
+        let file = reader.as_mut().unwrap();
+        try!(file.seek(position as i64, SeekSet));
+
+        let mut record = try!(file.read_exact(HEADER_SIZE));
+        let body_size = try!(BufReader::new(record.as_slice()).read_be_u32()) as uint;
+        record.push_all(try!(file.read_exact(body_size)).as_slice());
+
+        Ok(record)
+    }
+}
+
+struct RecordLocation {
+    segment: uint,
+    position: u64,
+    term: uint,
+}
+
+pub struct FileReplicationLog < T, Q, R, P > {
+    directory: Path,
+    options: FileLogOptions,
+
+    segments: Vec < Segment >,
+    locations: Vec < RecordLocation >,
+    writer: File,
+    unsynced: uint,
+    // failed write left bytes behind that could not be truncated, nothing
+    // is appended after them
+    damaged: bool,
+
+    first_offset: uint,
+    last_snapshot: Option < Snapshot >,
+
+    offset: uint,
+    responses: Vec < (uint, R) >,
+    sessions: Sessions,
+
+    pub persistence: P,
+}
+
+impl < 'a, T: Committable + Encodable < json::Encoder < 'a >, IoError > + Decodable < json::Decoder, json::DecoderError >, Q: Queriable, R: Receivable + Encodable < json::Encoder < 'a >, IoError > + Decodable < json::Decoder, json::DecoderError >, P: LogPersistence < T, R > + PersistenceQuery < Q, R > + SnapshotPersistence > FileReplicationLog < T, Q, R, P > {
+    // Opens the log stored in directory, creating it when missing. Records
+    // are validated on the way: a torn write at the tail of the last segment
+    // is truncated, while damage anywhere else is reported as an error.
+    pub fn open(directory: &Path, options: FileLogOptions, persistence: P) -> IoResult < FileReplicationLog < T, Q, R, P > > {
+        if !directory.exists() {
+            try!(fs::mkdir_recursive(directory, io::USER_RWX));
+        }
+
+        let last_snapshot = try!(read_snapshot(directory));
+        let first_offset = match last_snapshot {
+            Some(ref snapshot) => snapshot.last_included_index,
+            None => 0,
+        };
+
+        let mut segments = try!(find_segments(directory));
+        let mut locations = vec![];
+
+        let base_offset = match segments.first() {
+            Some(segment) => segment.first_offset,
+            None => first_offset,
+        };
+        let segments_count = segments.len();
+
+        for (index, segment) in segments.iter_mut().enumerate() {
+            if segment.first_offset != base_offset + locations.len() {
+                return Err(corrupted("log segments are not contiguous", &segment.path));
+            }
+
+            let valid_size = try!(recover_segment(index, segment, &mut locations));
+
+            if valid_size < segment.size {
+                if index + 1 < segments_count {
+                    return Err(corrupted("corrupted record in the middle of the log", &segment.path));
+                }
+
+                try!(truncate_file(&segment.path, valid_size));
+                segment.size = valid_size;
+            }
+        }
+
+        // records already covered by the snapshot are not part of the log;
+        // when the snapshot is past the end of the log nothing is left
+        if base_offset + locations.len() < first_offset {
+            for segment in segments.iter() {
+                try!(fs::unlink(&segment.path));
+            }
+
+            segments.clear();
+            locations.clear();
+        } else if base_offset < first_offset {
+            drop_prefix(&mut locations, first_offset - base_offset);
+        } else if base_offset > first_offset {
+            return Err(corrupted("log entries are missing after the snapshot", directory));
+        }
+
+        if segments.is_empty() {
+            segments.push(try!(create_segment(directory, first_offset)));
+        }
+
+        let writer = try!(File::open_mode(&segments.last().unwrap().path, Append, Write));
+
+        // state machine starts from the snapshot, entries after it are
+        // applied again as they get committed
+        let sessions = match last_snapshot {
+            Some(ref snapshot) => {
+                try!(persistence.restore(snapshot.data.as_slice()));
+                snapshot.sessions.clone()
+            },
+            None => Default::default(),
+        };
+
+        Ok(FileReplicationLog {
+            directory: directory.clone(),
+            options: options,
+
+            segments: segments,
+            locations: locations,
+            writer: writer,
+            unsynced: 0,
+            damaged: false,
+
+            first_offset: first_offset,
+            last_snapshot: last_snapshot,
+
+            offset: first_offset,
+            responses: vec![],
+            sessions: sessions,
+
+            persistence: persistence,
+        })
+    }
+
+    pub fn segments_count(&self) -> uint {
+        self.segments.len()
+    }
+
+    fn append(&mut self, entry: &Entry < T >, term: uint) -> IoResult < uint > {
+        if self.damaged {
+            return Err(corrupted("log tail is damaged by a failed write", &self.segments.last().unwrap().path));
+        }
+
+        let record = encode_record(term, json::encode(entry).as_bytes());
+        let record_size = record.len() as u64;
+
+        let current_size = self.segments.last().unwrap().size;
+        if current_size > 0 && current_size + record_size > self.options.segment_size {
+            try!(self.roll_segment());
+        }
+
+        let position = self.segments.last().unwrap().size;
+
+        // part of the record may have reached the file, it is cut off so
+        // that the next record starts where the log ends
+        let written = match self.writer.write(record.as_slice()) {
+            Ok(_) => self.sync_if_needed(),
+            Err(error) => Err(error),
+        };
+
+        match written {
+            Ok(_) => (),
+            Err(error) => {
+                if truncate_file(&self.segments.last().unwrap().path, position).is_err() {
+                    self.damaged = true;
+                }
+
+                return Err(error);
+            },
+        }
+
+        self.segments.last_mut().unwrap().size += record_size;
+        self.locations.push(RecordLocation {
+            segment: self.segments.len() - 1,
+            position: position,
+            term: term,
+        });
+
+        Ok(self.first_offset + self.locations.len() - 1)
+    }
+
+    fn roll_segment(&mut self) -> IoResult < () > {
+        try!(self.writer.fsync());
+
+        let segment = try!(create_segment(&self.directory, self.first_offset + self.locations.len()));
+        self.writer = try!(File::open_mode(&segment.path, Append, Write));
+        self.segments.push(segment);
+        self.unsynced = 0;
+
+        Ok(())
+    }
+
+    fn sync_if_needed(&mut self) -> IoResult < () > {
+        match self.options.sync {
+            SyncAlways => self.writer.datasync(),
+
+            SyncEvery(entries) => {
+                self.unsynced += 1;
+
+                if self.unsynced >= entries {
+                    self.unsynced = 0;
+                    self.writer.datasync()
+                } else {
+                    Ok(())
+                }
+            },
+
+            SyncNever => Ok(()),
+        }
+    }
+
+    // Makes snapshot the new start of the log: it is stored first, then
+    // records before it are forgotten and fully covered segments removed
+    fn move_start_to(&mut self, snapshot: Snapshot) -> IoResult < () > {
+        try!(write_snapshot(&self.directory, &snapshot));
+
+        let new_first_offset = snapshot.last_included_index;
+
+        if new_first_offset > self.len() {
+            for segment in self.segments.iter() {
+                try!(fs::unlink(&segment.path));
+            }
+
+            self.segments = vec![try!(create_segment(&self.directory, new_first_offset))];
+            self.writer = try!(File::open_mode(&self.segments[0].path, Append, Write));
+            self.locations.clear();
+        } else {
+            let count = new_first_offset - self.first_offset;
+            drop_prefix(&mut self.locations, count);
+
+            let mut obsolete = 0u;
+            while obsolete + 1 < self.segments.len() && self.segments[obsolete + 1].first_offset <= new_first_offset {
+                try!(fs::unlink(&self.segments[obsolete].path));
+                obsolete += 1;
+            }
+
+            drop_prefix(&mut self.segments, obsolete);
+            for location in self.locations.iter_mut() {
+                location.segment -= obsolete;
+            }
+        }
+
+        self.first_offset = new_first_offset;
+        self.last_snapshot = Some(snapshot);
+
+        Ok(())
+    }
+}
+
+impl < 'a, T: Committable + Encodable < json::Encoder < 'a >, IoError > + Decodable < json::Decoder, json::DecoderError >, Q: Queriable, R: Receivable + Encodable < json::Encoder < 'a >, IoError > + Decodable < json::Decoder, json::DecoderError >, P: LogPersistence < T, R > + PersistenceQuery < Q, R > + SnapshotPersistence + Default > ReplicationLog < T, Q, R > for FileReplicationLog < T, Q, R, P > {
+    fn new() -> FileReplicationLog < T, Q, R, P > {
+        match FileReplicationLog::open(&Path::new(DEFAULT_DIRECTORY), Default::default(), Default::default()) {
+            Ok(log) => log,
+            Err(e) => panic!("Unable to open replication log: {}", e),
+        }
+    }
+
+    fn len(&self) -> uint {
+        self.first_offset + self.locations.len()
+    }
+
+    fn first_offset(&self) -> uint {
+        self.first_offset
+    }
+
+    fn last_term(&self) -> uint {
+        match self.locations.last() {
+            Some(location) => location.term,
+            None => match self.last_snapshot {
+                Some(ref snapshot) => snapshot.last_included_term,
+                None => 0,
+            },
+        }
+    }
+
+    fn term_at(&self, offset: uint) -> Option < uint > {
+        if offset >= self.first_offset && offset < self.len() {
+            return Some(self.locations[offset - self.first_offset].term);
+        }
+
+        match self.last_snapshot {
+            Some(ref snapshot) if offset + 1 == snapshot.last_included_index => Some(snapshot.last_included_term),
+            _ => None,
+        }
+    }
+
+    fn entry_at(&self, offset: uint) -> IoResult < Entry < T > > {
+        if offset < self.first_offset || offset >= self.len() {
+            return Err(io::standard_error(io::InvalidInput));
+        }
+
+        let location = &self.locations[offset - self.first_offset];
+        let segment = &self.segments[location.segment];
+        let record = try!(segment.read_record_at(location.position));
+
+        match read_record(record.as_slice()) {
+            Some((_, _, payload)) => decode_entry(payload, &segment.path),
+            None => Err(corrupted("record checksum mismatch", &segment.path)),
+        }
+    }
+
+    fn committed_offset(&self) -> uint {
+        self.offset
+    }
+
+    fn commit_upto(&mut self, new_committed_offset: uint) -> IoResult < () > {
+        while self.offset < new_committed_offset && self.offset < self.len() {
+            match try!(self.entry_at(self.offset)) {
+                CommandEntry(command) => {
+                    let response = try!(self.persistence.commit(command));
+                    self.responses.push((self.offset, response));
+                },
+                RegisterSessionEntry(timestamp) => self.sessions.register(self.offset, timestamp),
+                SessionCommandEntry(request) => match self.sessions.check(request.client_id, request.sequence, request.timestamp) {
+                    FreshRequest => {
+                        let term = self.locations[self.offset - self.first_offset].term;
+                        let response = try!(self.persistence.commit(request.command));
+                        self.sessions.applied(request.client_id, request.sequence, self.offset, term, encode_response(&response));
+                        self.responses.push((self.offset, response));
+                    },
+                    DuplicateRequest => {
+                        let cached = self.sessions.response(request.client_id, request.sequence)
+                            .and_then(|cached| decode_response(cached.response.as_slice()));
+
+                        match cached {
+                            Some(response) => self.responses.push((self.offset, response)),
+                            None => (),
+                        }
+                    },
+                    UnknownSession => (),
+                },
+                _ => (),
+            }
+            self.offset += 1;
+        }
+
+        Ok(())
+    }
+
+    fn discard_downto(&mut self, new_len: uint) -> IoResult < () > {
+        let new_len = cmp::max(new_len, self.offset);
+
+        if new_len >= self.len() {
+            return Ok(());
+        }
+
+        let (segment_index, position) = {
+            let location = &self.locations[new_len - self.first_offset];
+            (location.segment, location.position)
+        };
+
+        if self.segments.len() > segment_index + 1 {
+            while self.segments.len() > segment_index + 1 {
+                let segment = self.segments.pop().unwrap();
+                try!(fs::unlink(&segment.path));
+            }
+
+            try!(sync_directory(&self.directory));
+        }
+
+        {
+            let segment = self.segments.last_mut().unwrap();
+            try!(truncate_file(&segment.path, position));
+            segment.size = position;
+        }
+
+        // whatever a failed write left behind is gone with the tail
+        self.damaged = false;
+
+        self.writer = try!(File::open_mode(&self.segments.last().unwrap().path, Append, Write));
+        self.locations.truncate(new_len - self.first_offset);
+
+        Ok(())
+    }
+
+    fn enqueue(&mut self, entry: Entry < T >, term: uint) -> IoResult < uint > {
+        self.append(&entry, term)
+    }
+
+    fn query_persistance(&mut self, query: Q, respond_to: Sender < R >) {
+        self.persistence.query(query, respond_to);
+    }
+
+    fn take_responses(&mut self) -> Vec < (uint, R) > {
+        mem::replace(&mut self.responses, vec![])
+    }
+
+    fn sessions(&self) -> &Sessions {
+        &self.sessions
+    }
+
+    fn snapshot(&self) -> Option < Snapshot > {
+        self.last_snapshot.clone()
+    }
+
+    fn compact(&mut self) -> IoResult < () > {
+        if self.offset <= self.first_offset {
+            return Ok(());
+        }
+
+        // configuration in force at the snapshot point is kept with it
+        let mut configuration = self.last_snapshot.as_ref().and_then(|snapshot| snapshot.configuration.clone());
+        for offset in range(self.first_offset, self.offset) {
+            match try!(self.entry_at(offset)) {
+                ConfigurationEntry(x) => configuration = Some(x),
+                _ => (),
+            }
+        }
+
+        let snapshot = Snapshot {
+            last_included_index: self.offset,
+            last_included_term: self.term_at(self.offset - 1).unwrap(),
+            configuration: configuration,
+            sessions: self.sessions.clone(),
+            data: try!(self.persistence.snapshot()),
+        };
+
+        self.move_start_to(snapshot)
+    }
+
+    fn install_snapshot(&mut self, snapshot: Snapshot) -> IoResult < () > {
+        let index = snapshot.last_included_index;
+
+        if index <= self.offset {
+            return Ok(());
+        }
+
+        try!(self.persistence.restore(snapshot.data.as_slice()));
+
+        // conflicting log is dropped altogether, agreeing one keeps the tail
+        if self.term_at(index - 1) != Some(snapshot.last_included_term) {
+            self.locations.clear();
+        }
+
+        self.offset = index;
+        self.sessions = snapshot.sessions.clone();
+        self.move_start_to(snapshot)
+    }
+}
+
+fn find_segments(directory: &Path) -> IoResult < Vec < Segment > > {
+    let mut segments = vec![];
+
+    for path in try!(fs::readdir(directory)).into_iter() {
+        if path.extension_str() != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+
+        let first_offset = match path.filestem_str().and_then(|stem| from_str::< uint >(stem)) {
+            Some(first_offset) => first_offset,
+            None => continue,
+        };
+
+        let size = try!(path.stat()).size;
+
+        segments.push(Segment {
+            first_offset: first_offset,
+            path: path,
+            size: size,
+            reader: RefCell::new(None),
+        });
+    }
+
+    segments.sort_by(|a, b| a.first_offset.cmp(&b.first_offset));
+
+    Ok(segments)
+}
+
+// segment is there after a crash once the directory is synced
+fn create_segment(directory: &Path, first_offset: uint) -> IoResult < Segment > {
+    let path = directory.join(format!("{:020}.{}", first_offset, SEGMENT_EXTENSION));
+    try!(File::create(&path));
+    try!(sync_directory(directory));
+
+    Ok(Segment {
+        first_offset: first_offset,
+        path: path,
+        size: 0,
+        reader: RefCell::new(None),
+    })
+}
+
+// Walks records of the segment, remembering where each one starts, and
+// returns the size of the valid prefix. Damaged record is a torn write only
+// when it is the last one: it runs past the end of the segment, or no valid
+// record follows it.
+fn recover_segment(index: uint, segment: &Segment, locations: &mut Vec < RecordLocation >) -> IoResult < u64 > {
+    let bytes = try!(try!(File::open(&segment.path)).read_to_end());
+    let mut position = 0u;
+
+    while position < bytes.len() {
+        match read_record(bytes.slice_from(position)) {
+            Some((record_size, term, _)) => {
+                locations.push(RecordLocation {
+                    segment: index,
+                    position: position as u64,
+                    term: term,
+                });
+                position += record_size;
+            },
+            None => match declared_record_size(bytes.slice_from(position)) {
+                Some(record_size) if position + record_size < bytes.len() &&
+                    read_record(bytes.slice_from(position + record_size)).is_some() => {
+                    return Err(corrupted("corrupted record in the middle of the log", &segment.path));
+                },
+                _ => break,
+            },
+        }
+    }
+
+    Ok(position as u64)
+}
+
+fn read_snapshot(directory: &Path) -> IoResult < Option < Snapshot > > {
+    let path = directory.join(SNAPSHOT_FILENAME);
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let bytes = try!(try!(File::open(&path)).read_to_end());
+
+    match read_record(bytes.as_slice()) {
+        Some((_, _, payload)) => Ok(Some(try!(decode_entry(payload, &path)))),
+        None => Err(corrupted("snapshot checksum mismatch", &path)),
+    }
+}
+
+// written next to the log and renamed in place, so a crash never leaves a
+// half-written snapshot behind. Segments it covers are removed only after
+// the directory is synced, otherwise a crash could lose both.
+fn write_snapshot(directory: &Path, snapshot: &Snapshot) -> IoResult < () > {
+    let path = directory.join(SNAPSHOT_FILENAME);
+    let tmp_path = path.with_extension("tmp");
+
+    {
+        let mut file = try!(File::create(&tmp_path));
+        try!(file.write(encode_record(snapshot.last_included_term, json::encode(snapshot).as_bytes()).as_slice()));
+        try!(file.fsync());
+    }
+
+    try!(fs::rename(&tmp_path, &path));
+    sync_directory(directory)
+}
+
+fn truncate_file(path: &Path, size: u64) -> IoResult < () > {
+    let mut file = try!(File::open_mode(path, Open, Write));
+    try!(file.truncate(size as i64));
+    file.fsync()
+}
+
+fn drop_prefix < X >(items: &mut Vec < X >, count: uint) {
+    let rest = mem::replace(items, vec![]);
+    items.extend(rest.into_iter().skip(count));
+}
+
+fn encode_record(term: uint, payload: &[u8]) -> Vec < u8 > {
+    let mut body = MemWriter::new();
+    body.write_be_u64(term as u64).unwrap();
+    body.write(payload).unwrap();
+    let body = body.unwrap();
+
+    let mut record = MemWriter::with_capacity(HEADER_SIZE + body.len());
+    record.write_be_u32(body.len() as u32).unwrap();
+    record.write_be_u32(crc32(body.as_slice())).unwrap();
+    record.write(body.as_slice()).unwrap();
+    record.unwrap()
+}
+
+// size of the record according to its header, None when even the header is
+// incomplete
+fn declared_record_size(bytes: &[u8]) -> Option < uint > {
+    if bytes.len() < HEADER_SIZE {
+        return None;
+    }
+
+    let body_size = BufReader::new(bytes.slice_to(HEADER_SIZE)).read_be_u32().unwrap() as uint;
+    Some(HEADER_SIZE + body_size)
+}
+
+// Some((record_size, term, payload)) for a complete record with a matching
+// checksum, None for a torn or damaged one
+fn read_record < 'a >(bytes: &'a [u8]) -> Option < (uint, uint, &'a [u8]) > {
+    if bytes.len() < HEADER_SIZE {
+        return None;
+    }
+
+    let mut header = BufReader::new(bytes.slice_to(HEADER_SIZE));
+    let body_size = header.read_be_u32().unwrap() as uint;
+    let checksum = header.read_be_u32().unwrap();
+
+    if body_size < TERM_SIZE || bytes.len() < HEADER_SIZE + body_size {
+        return None;
+    }
+
+    let body = bytes.slice(HEADER_SIZE, HEADER_SIZE + body_size);
+
+    if crc32(body) != checksum {
+        return None;
+    }
+
+    let term = BufReader::new(body.slice_to(TERM_SIZE)).read_be_u64().unwrap() as uint;
+
+    Some((HEADER_SIZE + body_size, term, body.slice_from(TERM_SIZE)))
+}
+
+fn decode_entry < T: Decodable < json::Decoder, json::DecoderError > >(payload: &[u8], path: &Path) -> IoResult < T > {
+    let text = match str::from_utf8(payload) {
+        Some(text) => text,
+        None => return Err(corrupted("log entry is not valid utf-8", path)),
+    };
+
+    match json::decode(text) {
+        Ok(entry) => Ok(entry),
+        Err(_) => Err(corrupted("unable to decode log entry", path)),
+    }
+}
+
+fn corrupted(desc: &'static str, path: &Path) -> IoError {
+    IoError {
+        kind: io::OtherIoError,
+        desc: desc,
+        detail: Some(path.display().to_string()),
+    }
+}
+
+// CRC-32 (IEEE 802.3), bit by bit
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+
+    for &byte in bytes.iter() {
+        crc = crc ^ (byte as u32);
+
+        for _ in range(0u, 8) {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
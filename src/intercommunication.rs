@@ -1,22 +1,22 @@
 use std::collections::HashMap;
-use std::io::timer::sleep;
+use std::io::timer::Timer;
 use std::time::duration::Duration;
-use std::comm::Disconnected;
+use std::comm::{Empty, Disconnected};
 
 use std::fmt::Show;
 
-use std::sync::{Arc, Mutex};
-
 use std::task::TaskBuilder;
 
 use serialize::json;
 
-use super::replication::Committable;
+use super::replication::{Committable, Entry, Snapshot};
 
 pub trait Intercommunication < T: Committable + Send + Show > {
     fn new() -> Self;
     fn register(&mut self, host: String) -> Endpoint < T >;
     fn receive(&mut self) -> Option < Package < T > >;
+    // blocks until a package arrives or timeout passes
+    fn receive_timeout(&mut self, timeout: Duration) -> Option < Package < T > >;
     fn send(&mut self, recipient: String, package: Package < T >);
     fn is_debug(&self) -> bool;
 }
@@ -35,17 +35,20 @@ pub struct Endpoint < T: Committable + Send > {
     pub rx: Receiver < Package < T > >,
 }
 
-#[deriving(Encodable, Decodable, Show, Clone, Send)]
+#[deriving(Encodable, Decodable, Show, Clone, PartialEq, Send)]
 pub struct AppendLog < T: Committable > {
+    pub term: uint,
+    pub prev_log_index: uint,
+    pub prev_log_term: uint,
     pub committed_offset: uint,
-    pub node_list: Vec < String >,
-    pub enqueue: Option < AppendLogEntry < T > >,
+    pub entries: Vec < AppendLogEntry < T > >,
 }
 
-#[deriving(Encodable, Decodable, Show, Clone, Send)]
+#[deriving(Encodable, Decodable, Show, Clone, PartialEq, Send)]
 pub struct AppendLogEntry < T: Committable > {
     pub offset: uint,
-    pub entry: T,
+    pub term: uint,
+    pub entry: Entry < T >,
 }
 
 impl < T: Committable + Send + Show > Intercommunication < T > for DefaultIntercommunication < T > {
@@ -79,6 +82,10 @@ impl < T: Committable + Send + Show > Intercommunication < T > for DefaultInterc
         }
     }
 
+    fn receive_timeout(&mut self, timeout: Duration) -> Option < Package < T > > {
+        receive_timeout(&self.receiver, timeout)
+    }
+
     fn send(&mut self, recipient: String, package: Package < T >) {
         match self.senders.find(&recipient) {
             Some(tx) => {
@@ -103,16 +110,7 @@ impl < T: Committable + Send > Endpoint < T > {
     }
 
     pub fn listen_block_with_timeout(&self) -> Option < Package < T > > {
-        for _ in range(0, 10u) {
-            match self.listen() {
-                Some(x) => return Some(x),
-                _ => ()
-            }
-
-            sleep(Duration::milliseconds(2));
-        }
-
-        None
+        receive_timeout(&self.rx, Duration::milliseconds(20))
     }
 
     pub fn listen(&self) -> Option < Package < T > > {
@@ -123,7 +121,7 @@ impl < T: Committable + Send > Endpoint < T > {
     }
 }
 
-#[deriving(Encodable, Decodable, Show, Clone)]
+#[deriving(Encodable, Decodable, Show, Clone, PartialEq)]
 pub enum PackageDetails < T: Committable + Send > {
     Ack,
 
@@ -135,31 +133,98 @@ pub enum PackageDetails < T: Committable + Send > {
     // AppendQuery(log)
     AppendQuery(AppendLog < T >),
 
-    // Persisted(entry_offset)
-    Persisted(uint),
+    // Persisted(term, match_index)
+    Persisted(uint, uint),
+
+    // AppendRejected(term, prev_log_index, log_len)
+    AppendRejected(uint, uint, uint),
+
+    // InstallSnapshot(term, snapshot)
+    InstallSnapshot(uint, Snapshot),
 
-    // RequestVote(term
-    RequestVote(uint),
+    // RequestVote(term, last_log_index, last_log_term, leadership_transfer)
+    RequestVote(uint, uint, uint, bool),
 
     // Vote(term)
     Vote(uint),
+
+    // PreVote(term, last_log_index, last_log_term)
+    PreVote(uint, uint, uint),
+
+    // PreVoteResponse(term, granted)
+    PreVoteResponse(uint, bool),
+
+    // TimeoutNow(term)
+    TimeoutNow(uint),
+
+    // Heartbeat(term, round)
+    Heartbeat(uint, uint),
+
+    // HeartbeatResponse(term, round)
+    HeartbeatResponse(uint, uint),
+
+    // ReadIndexRequest(read_id)
+    ReadIndexRequest(uint),
+
+    // ReadIndexResponse(read_id, read_index)
+    ReadIndexResponse(uint, uint),
+
+    // Propose(proposal_id, entry)
+    Propose(uint, Entry < T >),
+
+    // ProposalAccepted(proposal_id, offset, term)
+    ProposalAccepted(uint, uint, uint),
+
+    // ProposalRejected(proposal_id, leader_hint), leader_hint naming the
+    // sender itself means the leader could not store the proposal
+    ProposalRejected(uint, Option < String >),
 }
 
-#[deriving(Encodable, Decodable, Show, Clone)]
+impl < T: Committable + Send > PackageDetails < T > {
+    // name of the variant, a label in metrics
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Ack => "ack",
+            LeaderQuery => "leader_query",
+            LeaderQueryResponse(..) => "leader_query_response",
+            AppendQuery(..) => "append_query",
+            Persisted(..) => "persisted",
+            AppendRejected(..) => "append_rejected",
+            InstallSnapshot(..) => "install_snapshot",
+            RequestVote(..) => "request_vote",
+            Vote(..) => "vote",
+            PreVote(..) => "pre_vote",
+            PreVoteResponse(..) => "pre_vote_response",
+            TimeoutNow(..) => "timeout_now",
+            Heartbeat(..) => "heartbeat",
+            HeartbeatResponse(..) => "heartbeat_response",
+            ReadIndexRequest(..) => "read_index_request",
+            ReadIndexResponse(..) => "read_index_response",
+            Propose(..) => "propose",
+            ProposalAccepted(..) => "proposal_accepted",
+            ProposalRejected(..) => "proposal_rejected",
+        }
+    }
+}
+
+#[deriving(Encodable, Decodable, Show, Clone, PartialEq)]
 pub enum Package < T: Committable + Send > {
     // Pack(from, to, package)
     Pack(String, String, PackageDetails < T >),
 }
 
+// longest time the router waits for a package before it checks whether it
+// was told to exit
+static EXIT_CHECK_INTERVAL_MS: i64 = 50;
+
 pub fn start < T: Committable + Send + Clone + Show, I: Intercommunication < T > + Send >(intercommunication: I) -> Sender < int > {
-    let mutex = Arc::new(Mutex::new(intercommunication));
     let (exit_tx, exit_rx) = channel();
 
     TaskBuilder::new().named("intercommunication").spawn(proc() {
-        loop {
-            let mut intercommunication = mutex.lock();
+        let mut intercommunication = intercommunication;
 
-            match intercommunication.receive() {
+        loop {
+            match intercommunication.receive_timeout(Duration::milliseconds(EXIT_CHECK_INTERVAL_MS)) {
                 Some(Pack(from, to, package)) => {
                     if intercommunication.is_debug() {
                         println!("sent package {} to host {} from {}", package, to, from);
@@ -174,12 +239,29 @@ pub fn start < T: Committable + Send + Clone + Show, I: Intercommunication < T >
                 Ok(_) => break,
                 _ => (),
             }
-
-            intercommunication.cond.signal();
-
-            sleep(Duration::milliseconds(2));
         }
     });
 
     exit_tx
 }
+
+// Blocks on the receiver until something arrives or timeout passes, with no
+// timer involved when something is there already
+pub fn receive_timeout < X: Send >(receiver: &Receiver < X >, timeout: Duration) -> Option < X > {
+    match receiver.try_recv() {
+        Ok(x) => return Some(x),
+        Err(Empty) => (),
+        Err(Disconnected) => return None,
+    }
+
+    let mut timer = match Timer::new() {
+        Ok(timer) => timer,
+        Err(e) => panic!("Unable to create timer: {}", e),
+    };
+    let timed_out = timer.oneshot(timeout);
+
+    select! {
+        x = receiver.recv_opt() => x.ok(),
+        () = timed_out.recv() => None
+    }
+}
@@ -5,5 +5,16 @@
 extern crate serialize;
 
 pub mod node;
+pub mod raw_node;
+pub mod clock;
+pub mod metrics;
+pub mod progress;
+pub mod membership;
+pub mod session;
 pub mod intercommunication;
+pub mod codec;
+pub mod tcp;
+pub mod simulation;
 pub mod replication;
+pub mod storage;
+pub mod file_log;
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+// Voters of the cluster. While a change is in progress the cluster runs in
+// joint consensus: both outgoing and new voters have to agree, each with
+// their own majority, so there is never a moment with two majorities.
+#[deriving(Encodable, Decodable, Show, Clone, PartialEq)]
+pub struct Configuration {
+    pub voters: Vec < String >,
+    pub outgoing_voters: Vec < String >,
+}
+
+#[deriving(Clone, Show, PartialEq)]
+pub enum MembershipChange {
+    AddVoter(String),
+    RemoveVoter(String),
+}
+
+impl Configuration {
+    pub fn new(voters: Vec < String >) -> Configuration {
+        Configuration {
+            voters: voters,
+            outgoing_voters: vec![],
+        }
+    }
+
+    pub fn is_joint(&self) -> bool {
+        !self.outgoing_voters.is_empty()
+    }
+
+    pub fn contains(&self, host: &String) -> bool {
+        self.voters.contains(host) || self.outgoing_voters.contains(host)
+    }
+
+    // every voter of both configurations, each one once
+    pub fn hosts(&self) -> Vec < String > {
+        let mut hosts = self.voters.clone();
+
+        for host in self.outgoing_voters.iter() {
+            if !hosts.contains(host) {
+                hosts.push(host.clone());
+            }
+        }
+
+        hosts
+    }
+
+    // joint configuration moving from current voters to the changed ones
+    pub fn apply(&self, changes: &[MembershipChange]) -> Configuration {
+        let mut voters = self.voters.clone();
+
+        for change in changes.iter() {
+            match *change {
+                AddVoter(ref host) => if !voters.contains(host) {
+                    voters.push(host.clone());
+                },
+                RemoveVoter(ref host) => voters.retain(|x| x != host),
+            }
+        }
+
+        Configuration {
+            voters: voters,
+            outgoing_voters: self.voters.clone(),
+        }
+    }
+
+    pub fn leave_joint(&self) -> Configuration {
+        Configuration::new(self.voters.clone())
+    }
+
+    pub fn has_quorum(&self, granted: &[String]) -> bool {
+        has_majority(&self.voters, granted) &&
+            (!self.is_joint() || has_majority(&self.outgoing_voters, granted))
+    }
+
+    // Highest log length stored on a majority of voters (of both
+    // configurations while in joint consensus)
+    pub fn quorum_match_index(&self, match_index: &HashMap < String, uint >) -> uint {
+        let index = majority_match_index(&self.voters, match_index);
+
+        if self.is_joint() {
+            let outgoing_index = majority_match_index(&self.outgoing_voters, match_index);
+            if outgoing_index < index {
+                return outgoing_index;
+            }
+        }
+
+        index
+    }
+}
+
+fn has_majority(voters: &Vec < String >, granted: &[String]) -> bool {
+    let count = voters.iter().filter(|&host| granted.contains(host)).count();
+    count > voters.len() / 2
+}
+
+fn majority_match_index(voters: &Vec < String >, match_index: &HashMap < String, uint >) -> uint {
+    if voters.is_empty() {
+        return 0;
+    }
+
+    let mut matched: Vec < uint > = voters.iter()
+        .map(|host| *match_index.find(host).unwrap_or(&0))
+        .collect();
+
+    matched.sort_by(|a, b| b.cmp(a));
+    matched[voters.len() / 2]
+}
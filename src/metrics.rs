@@ -0,0 +1,273 @@
+use std::collections::TreeMap;
+use std::io::IoResult;
+use std::sync::{Arc, Mutex};
+use std::time::duration::Duration;
+
+use super::node::{State, Follower, PreCandidate, Candidate, Leader};
+
+// What happens inside a node, reported by the raft core as it happens.
+// Every method does nothing unless implemented.
+pub trait Metrics {
+    fn term_changed(&mut self, _term: uint) {}
+    fn state_changed(&mut self, _from: State, _to: State) {}
+
+    fn election_started(&mut self) {}
+    fn election_won(&mut self) {}
+
+    fn entries_appended(&mut self, _count: uint) {}
+    // commit_index is the committed length of the log
+    fn entries_committed(&mut self, _count: uint, _commit_index: uint) {}
+
+    // entries of the leader's log the peer is not known to have
+    fn peer_lag(&mut self, _peer: &str, _lag: uint) {}
+
+    // kind is PackageDetails::kind
+    fn message_sent(&mut self, _kind: &str) {}
+    fn message_received(&mut self, _kind: &str) {}
+
+    // packages for the peer the transport gave up on, reported by
+    // TcpIntercommunication
+    fn packages_dropped(&mut self, _peer: &str, _count: uint) {}
+
+    // from the moment a proposal reached this node until it was committed
+    fn proposal_committed(&mut self, _latency: Duration) {}
+}
+
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+// upper bounds of the latency buckets in seconds
+static LATENCY_BUCKETS: [f64, ..13] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+struct Histogram {
+    // count of observations in each bucket alone, the last one is +Inf
+    buckets: Vec < uint >,
+    sum: f64,
+    count: uint,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            buckets: Vec::from_elem(LATENCY_BUCKETS.len() + 1, 0u),
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        let bucket = LATENCY_BUCKETS.iter().position(|bound| value <= *bound).unwrap_or(LATENCY_BUCKETS.len());
+
+        *self.buckets.get_mut(bucket) += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+struct Registry {
+    term: uint,
+    commit_index: uint,
+    // (from, to)
+    transitions: TreeMap < (&'static str, &'static str), uint >,
+    elections_started: uint,
+    elections_won: uint,
+    entries_appended: uint,
+    entries_committed: uint,
+    peer_lag: TreeMap < String, uint >,
+    messages_sent: TreeMap < String, uint >,
+    messages_received: TreeMap < String, uint >,
+    packages_dropped: TreeMap < String, uint >,
+    commit_latency: Histogram,
+}
+
+// Keeps everything reported and renders it in Prometheus text format. Clones
+// share the same values, so one is handed to the node and another one is
+// rendered on every scrape.
+#[deriving(Clone)]
+pub struct PrometheusMetrics {
+    registry: Arc < Mutex < Registry > >,
+}
+
+impl PrometheusMetrics {
+    pub fn new() -> PrometheusMetrics {
+        PrometheusMetrics {
+            registry: Arc::new(Mutex::new(Registry {
+                term: 0,
+                commit_index: 0,
+                transitions: TreeMap::new(),
+                elections_started: 0,
+                elections_won: 0,
+                entries_appended: 0,
+                entries_committed: 0,
+                peer_lag: TreeMap::new(),
+                messages_sent: TreeMap::new(),
+                messages_received: TreeMap::new(),
+                packages_dropped: TreeMap::new(),
+                commit_latency: Histogram::new(),
+            })),
+        }
+    }
+
+    pub fn render < W: Writer >(&self, writer: &mut W) -> IoResult < () > {
+        let registry = self.registry.lock();
+
+        try!(header(writer, "raft_term", "Current term of the node.", "gauge"));
+        try!(writeln!(writer, "raft_term {}", registry.term));
+
+        try!(header(writer, "raft_state_transitions_total", "State changes of the node.", "counter"));
+        for (&(from, to), count) in registry.transitions.iter() {
+            try!(writeln!(writer, "raft_state_transitions_total{{from=\"{}\",to=\"{}\"}} {}", from, to, count));
+        }
+
+        try!(header(writer, "raft_elections_started_total", "Elections the node stood for.", "counter"));
+        try!(writeln!(writer, "raft_elections_started_total {}", registry.elections_started));
+
+        try!(header(writer, "raft_elections_won_total", "Elections the node won.", "counter"));
+        try!(writeln!(writer, "raft_elections_won_total {}", registry.elections_won));
+
+        try!(header(writer, "raft_entries_appended_total", "Entries appended to the log.", "counter"));
+        try!(writeln!(writer, "raft_entries_appended_total {}", registry.entries_appended));
+
+        try!(header(writer, "raft_entries_committed_total", "Entries committed and applied.", "counter"));
+        try!(writeln!(writer, "raft_entries_committed_total {}", registry.entries_committed));
+
+        try!(header(writer, "raft_commit_index", "Committed length of the log.", "gauge"));
+        try!(writeln!(writer, "raft_commit_index {}", registry.commit_index));
+
+        try!(header(writer, "raft_peer_lag_entries", "Entries of the leader's log a peer is not known to have.", "gauge"));
+        for (peer, lag) in registry.peer_lag.iter() {
+            try!(writeln!(writer, "raft_peer_lag_entries{{peer=\"{}\"}} {}", escape(peer.as_slice()), lag));
+        }
+
+        try!(header(writer, "raft_messages_sent_total", "Messages sent to other nodes.", "counter"));
+        for (kind, count) in registry.messages_sent.iter() {
+            try!(writeln!(writer, "raft_messages_sent_total{{kind=\"{}\"}} {}", escape(kind.as_slice()), count));
+        }
+
+        try!(header(writer, "raft_messages_received_total", "Messages received from other nodes.", "counter"));
+        for (kind, count) in registry.messages_received.iter() {
+            try!(writeln!(writer, "raft_messages_received_total{{kind=\"{}\"}} {}", escape(kind.as_slice()), count));
+        }
+
+        try!(header(writer, "raft_packages_dropped_total", "Packages for a peer the transport gave up on.", "counter"));
+        for (peer, count) in registry.packages_dropped.iter() {
+            try!(writeln!(writer, "raft_packages_dropped_total{{peer=\"{}\"}} {}", escape(peer.as_slice()), count));
+        }
+
+        let latency = &registry.commit_latency;
+        let mut cumulative = 0u;
+
+        try!(header(writer, "raft_proposal_commit_latency_seconds", "Time from proposal to commit.", "histogram"));
+        for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            cumulative += latency.buckets[i];
+            try!(writeln!(writer, "raft_proposal_commit_latency_seconds_bucket{{le=\"{}\"}} {}", bound, cumulative));
+        }
+        try!(writeln!(writer, "raft_proposal_commit_latency_seconds_bucket{{le=\"+Inf\"}} {}", latency.count));
+        try!(writeln!(writer, "raft_proposal_commit_latency_seconds_sum {}", latency.sum));
+        try!(writeln!(writer, "raft_proposal_commit_latency_seconds_count {}", latency.count));
+
+        Ok(())
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn term_changed(&mut self, term: uint) {
+        self.registry.lock().term = term;
+    }
+
+    fn state_changed(&mut self, from: State, to: State) {
+        increment(&mut self.registry.lock().transitions, (state_name(from), state_name(to)));
+    }
+
+    fn election_started(&mut self) {
+        self.registry.lock().elections_started += 1;
+    }
+
+    fn election_won(&mut self) {
+        self.registry.lock().elections_won += 1;
+    }
+
+    fn entries_appended(&mut self, count: uint) {
+        self.registry.lock().entries_appended += count;
+    }
+
+    fn entries_committed(&mut self, count: uint, commit_index: uint) {
+        let mut registry = self.registry.lock();
+        registry.entries_committed += count;
+        registry.commit_index = commit_index;
+    }
+
+    fn peer_lag(&mut self, peer: &str, lag: uint) {
+        self.registry.lock().peer_lag.insert(peer.to_string(), lag);
+    }
+
+    fn message_sent(&mut self, kind: &str) {
+        increment(&mut self.registry.lock().messages_sent, kind.to_string());
+    }
+
+    fn message_received(&mut self, kind: &str) {
+        increment(&mut self.registry.lock().messages_received, kind.to_string());
+    }
+
+    fn packages_dropped(&mut self, peer: &str, count: uint) {
+        let mut registry = self.registry.lock();
+
+        match registry.packages_dropped.find_mut(&peer.to_string()) {
+            Some(dropped) => {
+                *dropped += count;
+                return;
+            },
+            None => (),
+        }
+
+        registry.packages_dropped.insert(peer.to_string(), count);
+    }
+
+    fn proposal_committed(&mut self, latency: Duration) {
+        let seconds = latency.num_microseconds().unwrap_or(0) as f64 / 1000000.0;
+        self.registry.lock().commit_latency.observe(seconds);
+    }
+}
+
+fn increment < K: Ord >(counters: &mut TreeMap < K, uint >, key: K) {
+    match counters.find_mut(&key) {
+        Some(count) => {
+            *count += 1;
+            return;
+        },
+        None => (),
+    }
+
+    counters.insert(key, 1);
+}
+
+fn header < W: Writer >(writer: &mut W, name: &str, help: &str, kind: &str) -> IoResult < () > {
+    try!(writeln!(writer, "# HELP {} {}", name, help));
+    writeln!(writer, "# TYPE {} {}", name, kind)
+}
+
+// label values are quoted, so backslashes, quotes and newlines are escaped
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn state_name(state: State) -> &'static str {
+    match state {
+        Follower => "follower",
+        PreCandidate => "pre_candidate",
+        Candidate => "candidate",
+        Leader => "leader",
+    }
+}
@@ -1,26 +1,167 @@
-extern crate time;
-extern crate core;
-
-use std::io::timer::sleep;
+use std::io::timer::Timer;
 use std::time::duration::Duration;
-use std::comm::Disconnected;
+use std::comm::Select;
 
 use std::task::TaskBuilder;
 
-use std::{rand, num};
+use std::default::Default;
+
+use std::{cmp, io};
 
 use std::fmt::Show;
 
-use super::intercommunication::{Intercommunication, Ack, LeaderQuery, LeaderQueryResponse, Persisted, Pack, Endpoint, AppendQuery, AppendLog, AppendLogEntry, RequestVote, Vote};
-use super::replication::{ReplicationLog, Committable, Receivable, Queriable};
+use super::intercommunication::{Intercommunication, Endpoint, Package};
+use super::replication::{ReplicationLog, Committable, Receivable, Queriable, Entry, CommandEntry, RegisterSessionEntry, SessionCommandEntry};
+use super::session::SessionCommand;
+use super::storage::HardStateStorage;
+use super::membership::{MembershipChange, AddVoter, RemoveVoter};
+use super::clock::Clock;
+use super::metrics::Metrics;
+use super::raw_node::{RawNode, Ready};
 
 #[deriving(Clone,Show,PartialEq)]
 pub enum State {
     Follower,
+    PreCandidate,
     Candidate,
     Leader,
 }
 
+// Limits for a single AppendLog sent to a follower and for the number of
+// AppendLogs a follower may have unacknowledged at a time
+#[deriving(Clone,Show,PartialEq)]
+pub struct AppendLimits {
+    pub max_entries: uint,
+    pub max_bytes: uint,
+    pub max_inflight: uint,
+}
+
+impl Default for AppendLimits {
+    fn default() -> AppendLimits {
+        AppendLimits {
+            max_entries: 64,
+            max_bytes: 1024 * 1024,
+            max_inflight: 4,
+        }
+    }
+}
+
+// Timing, batching and optional features a node starts with
+#[deriving(Clone,Show,PartialEq)]
+pub struct NodeConfig {
+    // leader sends heartbeats this often
+    pub heartbeat_interval: Duration,
+
+    // Node that hears nothing from a leader stands for election after a
+    // random time from this range. The lower end is also how long leases,
+    // quorum checks, leadership transfers and forwarded proposals last.
+    pub min_election_timeout: Duration,
+    pub max_election_timeout: Duration,
+
+    pub append_limits: AppendLimits,
+
+    pub pre_vote: bool,
+    pub check_quorum: bool,
+    pub forward_proposals: bool,
+}
+
+impl Default for NodeConfig {
+    fn default() -> NodeConfig {
+        NodeConfig {
+            heartbeat_interval: Duration::milliseconds(70),
+            min_election_timeout: Duration::milliseconds(150),
+            max_election_timeout: Duration::milliseconds(300),
+            append_limits: Default::default(),
+            pre_vote: false,
+            check_quorum: false,
+            forward_proposals: false,
+        }
+    }
+}
+
+#[deriving(Clone,Show,PartialEq)]
+pub enum ConfigError {
+    // heartbeat interval has to be positive and at most half of the minimal
+    // election timeout, so that a single lost heartbeat does not start an
+    // election
+    InvalidHeartbeatInterval,
+    // election timeouts have to be positive with min not above max
+    InvalidElectionTimeout,
+    // every append limit has to be positive
+    InvalidAppendLimits,
+}
+
+impl NodeConfig {
+    pub fn validate(&self) -> Result < (), ConfigError > {
+        if self.min_election_timeout <= Duration::zero() ||
+            self.min_election_timeout > self.max_election_timeout {
+            return Err(InvalidElectionTimeout);
+        }
+
+        if self.heartbeat_interval <= Duration::zero() ||
+            self.heartbeat_interval * 2 > self.min_election_timeout {
+            return Err(InvalidHeartbeatInterval);
+        }
+
+        let limits = &self.append_limits;
+        if limits.max_entries == 0 || limits.max_bytes == 0 || limits.max_inflight == 0 {
+            return Err(InvalidAppendLimits);
+        }
+
+        Ok(())
+    }
+}
+
+// How Node::query is made linearizable
+#[deriving(Clone,Show,PartialEq)]
+pub enum ReadConsistency {
+    // every read is confirmed by a heartbeat round
+    ReadIndexRead,
+    // LeaseRead(drift_bound): leader that heard from a majority within
+    // election timeout minus drift bound answers right away, relying on
+    // clocks of the nodes not drifting further apart than that
+    LeaseRead(Duration),
+}
+
+#[deriving(Clone,Show,PartialEq)]
+pub enum ProposalError {
+    // NotLeader(leader_hint)
+    NotLeader(Option < NodeHost >),
+    // leader could not store the proposal or did not answer in time
+    ProposalDropped,
+    // new term began before the entry was committed, it may or may not be
+    // committed by the new leader
+    LeadershipLost,
+    // entry was replaced by an entry of another leader
+    EntryOverwritten,
+    // client session is not registered or has expired, command was not
+    // applied
+    SessionExpired,
+}
+
+// Entry committed at index in term, applied is what handing it to the state
+// machine of this node resulted in: its answer to a command, None for
+// entries that are not commands or when this node applied the command
+// before it learned about the proposal
+#[deriving(Clone,Show,PartialEq)]
+pub struct Committed < R > {
+    pub index: uint,
+    pub term: uint,
+    pub applied: io::IoResult < Option < R > >,
+}
+
+// What enqueued command ends up with
+pub type Proposal < R > = Receiver < Result < Committed < R >, ProposalError > >;
+
+// Outcome of a leadership transfer
+#[deriving(Clone,Show,PartialEq)]
+pub enum TransferResult {
+    Transferred,
+    TransferTimedOut,
+    // node is not a leader, or target is not a voter of the cluster
+    TransferRejected,
+}
+
 pub struct Node < T: Committable + Send, Q: Queriable + Send, R: Receivable + Send > {
     contact: Option < NodeContact < T, Q, R > >,
 }
@@ -30,27 +171,25 @@ pub struct NodeHost {
     pub host: String,
 }
 
-struct NodeService < T: Committable + Send, R: ReplicationLog < T, Q, Rcv > + Send, Q: Queriable + Send, Rcv: Receivable + Send > {
-    state: State,
-    my_host: NodeHost,
-    leader_host: Option < NodeHost >,
 
-    contact: NodeServiceContact < T, Q, Rcv >,
-    nodes: Vec < NodeHost >,
+// Drives a RawNode from its own task: commands and packages are fed to it,
+// and what it is ready with is stored and sent
+struct NodeService < T: Committable + Send, R: ReplicationLog < T, Q, Rcv > + Send, Q: Queriable + Send, Rcv: Receivable + Send, S: HardStateStorage + Send > {
+    raw: RawNode < T, R, Q, Rcv >,
 
+    contact: NodeServiceContact < T, Q, Rcv >,
     comm: Endpoint < T >,
+    storage: S,
 
-    last_append_log_seen_at: time::Timespec,
-    last_sent_heartbeat: time::Timespec,
-    term: uint,
-    votes: uint,
-    already_requested: bool,
-
-    log: R,
-
-    election_timeout: Duration,
+    timer: Timer,
+    // intercommunication went away, only commands and timers are left
+    network_down: bool,
 }
 
+// longest the service sleeps, clocks that jump (manual ones in tests) are
+// looked at again at least this often
+static MAX_WAIT_MS: i64 = 100;
+
 struct NodeContact < T: Committable + Send, Q: Queriable + Send, R: Receivable + Send > {
     tx: Sender < Command < T, Q, R > >,
     rx: Receiver < CommandResponse >,
@@ -73,12 +212,39 @@ enum Command < T: Committable + Send, Q: Queriable + Send, R: Receivable + Send
     AssignState(State),
     FetchState,
 
-    Enqueue(T),
+    AssignAppendLimits(AppendLimits),
+
+    AssignPreVote(bool),
+    AssignCheckQuorum(bool),
+
+    ObserveState(Sender < State >),
+
+    AssignForwardProposals(bool),
+
+    AssignReadConsistency(ReadConsistency),
+    AssignClock(Box < Clock + Send >),
+    AssignSeed(u32),
+    AssignMetrics(Box < Metrics + Send >),
+
+    ChangeMembership(MembershipChange, Sender < Result < Committed < R >, ProposalError > >),
+
+    TransferLeadership(String, Sender < TransferResult >),
+
+    Enqueue(Entry < T >, Sender < Result < Committed < R >, ProposalError > >),
     Query(Q, Sender < R >),
 
+    Compact,
+
     ExitCommand,
 }
 
+// What woke the service up
+enum Wakeup < T: Committable + Send, Q: Queriable + Send, R: Receivable + Send > {
+    CommandArrived(Result < Command < T, Q, R >, () >),
+    PackageArrived(Result < Package < T >, () >),
+    TimerFired,
+}
+
 enum CommandResponse {
     FetchedLeader(Option < NodeHost >),
 
@@ -139,32 +305,139 @@ impl < T: Committable + Send + Clone + Show, Q: Queriable + Send, R: Receivable
         }
     }
 
-    pub fn enqueue(&self, command: T) {
-        self.contact().tx.send(Enqueue(command));
+    // Membership changes are made on the leader and go through the log: the
+    // proposal resolves once the joint configuration with the change is
+    // committed, or fails with LeadershipLost when leadership goes away
+    // before that. Any other node answers with NotLeader(leader_hint).
+    pub fn add_voter(&self, host: &str) -> Proposal < R > {
+        self.change_membership(AddVoter(host.to_string()))
+    }
+
+    pub fn remove_node(&self, host: &str) -> Proposal < R > {
+        self.change_membership(RemoveVoter(host.to_string()))
+    }
+
+    // Blocks until target becomes leader or the transfer times out after an
+    // election timeout. Proposals are rejected while the transfer is going on.
+    pub fn transfer_leadership(&self, target: &str) -> TransferResult {
+        let (tx, rx) = channel();
+        self.contact().tx.send(TransferLeadership(target.to_string(), tx));
+        rx.recv()
+    }
+
+    // Resolves once the command is committed and applied on this node.
+    // Followers reject commands with NotLeader unless they forward them to
+    // the leader.
+    pub fn enqueue(&self, command: T) -> Proposal < R > {
+        self.propose(CommandEntry(command))
+    }
+
+    // Committed index of the registration is the client id
+    pub fn register_session(&self) -> Proposal < R > {
+        self.propose(RegisterSessionEntry(0))
+    }
+
+    // Command applied at most once for the sequence, a retry resolves to
+    // where the first one was applied and what the state machine answered
+    pub fn enqueue_in_session(&self, client_id: uint, sequence: uint, command: T) -> Proposal < R > {
+        self.propose(SessionCommandEntry(SessionCommand {
+            client_id: client_id,
+            sequence: sequence,
+            timestamp: 0,
+            command: command,
+        }))
+    }
+
+    // overrides NodeConfig::forward_proposals
+    pub fn set_forward_proposals(&self, enabled: bool) {
+        self.contact().tx.send(AssignForwardProposals(enabled));
     }
 
     pub fn query(&self, query: Q, respond_to: &Sender < R >) {
         self.contact().tx.send(Query(query, respond_to.clone()));
     }
 
+    // overrides NodeConfig::append_limits
+    pub fn set_append_limits(&self, limits: AppendLimits) {
+        self.contact().tx.send(AssignAppendLimits(limits));
+    }
+
+    // overrides NodeConfig::pre_vote
+    pub fn set_pre_vote(&self, enabled: bool) {
+        self.contact().tx.send(AssignPreVote(enabled));
+    }
+
+    // overrides NodeConfig::check_quorum
+    pub fn set_check_quorum(&self, enabled: bool) {
+        self.contact().tx.send(AssignCheckQuorum(enabled));
+    }
+
+    // ReadIndexRead by default
+    pub fn set_read_consistency(&self, consistency: ReadConsistency) {
+        self.contact().tx.send(AssignReadConsistency(consistency));
+    }
+
+    // clock for timeouts and read leases
+    pub fn set_clock < C: Clock + Send + 'static >(&self, clock: C) {
+        self.contact().tx.send(AssignClock(box clock as Box < Clock + Send >));
+    }
+
+    // Seed of the election timeouts, random by default. Together with a
+    // manual clock it makes elections repeat from run to run.
+    pub fn set_seed(&self, seed: u32) {
+        self.contact().tx.send(AssignSeed(seed));
+    }
+
+    // NoopMetrics by default
+    pub fn set_metrics < M: Metrics + Send + 'static >(&self, metrics: M) {
+        self.contact().tx.send(AssignMetrics(box metrics as Box < Metrics + Send >));
+    }
+
+    // every state change from now on is sent to respond_to
+    pub fn observe_state(&self, respond_to: &Sender < State >) {
+        self.contact().tx.send(ObserveState(respond_to.clone()));
+    }
+
+    pub fn compact(&self) {
+        self.contact().tx.send(Compact);
+    }
+
     pub fn stop(&self) {
         self.contact().tx.send(ExitCommand);
     }
 
-    pub fn start < I: Intercommunication < T >, Y: ReplicationLog < T, Q, R > + 'static + Send >(&mut self, host: &str, intercommunication: &mut I, log: Y, election_timeout: Duration) {
+    // Node is not started when the config does not validate
+    pub fn start < I: Intercommunication < T >, Y: ReplicationLog < T, Q, R > + 'static + Send, S: HardStateStorage + 'static + Send >(&mut self, host: &str, intercommunication: &mut I, log: Y, storage: S, config: NodeConfig) -> Result < (), ConfigError > {
+        try!(config.validate());
+
         match self.contact {
             Some(_) => {},
             None => self.contact = Some(NodeService::start_service(
                     host.to_string(),
                     intercommunication,
                     log,
-                    election_timeout,
+                    storage,
+                    config,
                     )),
         }
+
+        Ok(())
     }
 
     // private
 
+    fn change_membership(&self, change: MembershipChange) -> Proposal < R > {
+        let (tx, rx) = channel();
+        self.contact().tx.send(ChangeMembership(change, tx));
+        rx
+    }
+
+    fn propose(&self, entry: Entry < T >) -> Proposal < R > {
+        let (tx, rx) = channel();
+        self.contact().tx.send(Enqueue(entry, tx));
+        rx
+    }
+
     fn contact(&self) -> &NodeContact < T, Q, R > {
         match self.contact {
             Some(ref x) => x,
@@ -174,50 +447,61 @@ impl < T: Committable + Send + Clone + Show, Q: Queriable + Send, R: Receivable
 
 }
 
-impl < T: Committable + Send + Clone + Show, R: ReplicationLog < T, Q, Rcv > + 'static + Send, Q: Queriable + Send, Rcv: Receivable + Send > NodeService < T, R, Q, Rcv > {
-    fn new (host: String, service_contact: NodeServiceContact < T, Q, Rcv >, comm: Endpoint < T >, log: R, election_timeout: Duration) -> NodeService < T, R, Q, Rcv > {
-        NodeService {
-            state: Follower,
-            my_host: NodeHost { host: host.clone() },
-            leader_host: None,
+impl < T: Committable + Send + Clone + Show, R: ReplicationLog < T, Q, Rcv > + 'static + Send, Q: Queriable + Send, Rcv: Receivable + Send, S: HardStateStorage + 'static + Send > NodeService < T, R, Q, Rcv, S > {
+    fn new (host: String, service_contact: NodeServiceContact < T, Q, Rcv >, comm: Endpoint < T >, log: R, mut storage: S, config: NodeConfig) -> NodeService < T, R, Q, Rcv, S > {
+        let hard_state = match storage.load() {
+            Ok(hard_state) => hard_state,
+            Err(e) => panic!("Unable to load hard state: {}", e),
+        };
 
-            contact: service_contact,
-            nodes: vec![NodeHost { host: host.clone() }],
+        let timer = match Timer::new() {
+            Ok(timer) => timer,
+            Err(e) => panic!("Unable to create timer: {}", e),
+        };
 
+        NodeService {
+            raw: RawNode::new(host.as_slice(), log, hard_state, config),
+            contact: service_contact,
             comm: comm,
-
-            last_append_log_seen_at: time::now().to_timespec(),
-            last_sent_heartbeat: time::now().to_timespec(),
-            term: 0,
-            votes: 0,
-            already_requested: false,
-
-            log: log,
-
-            election_timeout: election_timeout,
+            storage: storage,
+            timer: timer,
+            network_down: false,
         }
     }
 
-    fn start_service < I: Intercommunication < T > >(host: String, intercommunication: &mut I, log: R, election_timeout: Duration) -> NodeContact < T, Q, Rcv > {
+    fn start_service < I: Intercommunication < T > >(host: String, intercommunication: &mut I, log: R, storage: S, config: NodeConfig) -> NodeContact < T, Q, Rcv > {
         let (contact, service_contact) = NodeService::channels();
 
         let comm = intercommunication.register(host.clone());
 
         TaskBuilder::new().named(format!("{}-service", host)).spawn(proc() {
-            let mut me = NodeService::new(host, service_contact, comm, log, election_timeout);
+            let mut me = NodeService::new(host, service_contact, comm, log, storage, config);
 
             let mut dead = false;
 
             while !dead {
-                dead = dead || me.react_to_commands();
-
-                me.react_to_intercommunication();
+                dead = match me.wait() {
+                    CommandArrived(Ok(command)) => me.react_to_command(command),
+                    // node was dropped
+                    CommandArrived(Err(_)) => true,
+
+                    PackageArrived(Ok(package)) => {
+                        me.raw.step(package);
+                        false
+                    },
+                    PackageArrived(Err(_)) => {
+                        me.network_down = true;
+                        false
+                    },
 
-                me.election_handler();
+                    TimerFired => false,
+                };
 
-                me.autocommit();
+                me.raw.tick();
 
-                sleep(Duration::milliseconds(2));
+                if !me.handle_ready() {
+                    dead = true;
+                }
             }
         });
 
@@ -241,210 +525,149 @@ impl < T: Committable + Send + Clone + Show, R: ReplicationLog < T, Q, Rcv > + '
         (contact, service_contact)
     }
 
-    fn react_to_commands(&mut self) -> bool {
-        let mut dead = false;
-
-        match self.contact.rx.try_recv() {
-            Ok(FetchState) => self.contact.tx.send(FetchedState(self.state)),
-            Ok(AssignState(state)) => {
-                self.state = state;
-                self.contact.tx.send(FetchedState(self.state));
-            },
+    // Sleeps until a command or a package arrives, or until the next timer
+    // of the raft node goes off
+    fn wait(&mut self) -> Wakeup < T, Q, Rcv > {
+        let timeout = cmp::max(
+            cmp::min(self.raw.time_to_next_tick(), Duration::milliseconds(MAX_WAIT_MS)),
+            Duration::milliseconds(1));
+        let timed_out = self.timer.oneshot(timeout);
+
+        let select = Select::new();
+        let mut commands = select.handle(&self.contact.rx);
+        let mut packages = select.handle(&self.comm.rx);
+        let mut timer = select.handle(&timed_out);
+
+        unsafe {
+            commands.add();
+            if !self.network_down {
+                packages.add();
+            }
+            timer.add();
+        }
 
-            Ok(FetchLeader) => self.contact.tx.send(FetchedLeader(self.fetch_leader_host().clone())),
-            Ok(AssignLeader(leader)) => {
-                self.leader_host = leader.clone();
-                match leader {
-                    Some(leader) => self.comm.send(leader.host, Ack),
-                    None => (),
-                }
-                self.contact.tx.send(FetchedLeader(self.fetch_leader_host().clone()));
-            },
+        let id = select.wait();
 
-            Ok(FetchNodes) => self.contact.tx.send(FetchedNodes(self.nodes.clone())),
+        if id == commands.id() {
+            CommandArrived(commands.recv_opt())
+        } else if id == packages.id() {
+            PackageArrived(packages.recv_opt())
+        } else {
+            TimerFired
+        }
+    }
 
-            Ok(ExitCommand) => dead = true,
+    fn react_to_command(&mut self, command: Command < T, Q, Rcv >) -> bool {
+        let mut dead = false;
 
-            Ok(Introduce(host)) => {
-                self.comm.send(host.clone(), Ack);
-                self.comm.send(host, LeaderQuery);
+        match command {
+            FetchState => self.contact.tx.send(FetchedState(self.raw.state())),
+            AssignState(state) => {
+                self.raw.force_state(state);
+                self.contact.tx.send(FetchedState(self.raw.state()));
             },
 
-            Ok(Enqueue(command)) => {
-                if self.state == Leader {
-                    match self.log.enqueue(command.clone()) {
-                        Ok(entry_offset) => {
-                            self.send_append_log(Some(AppendLogEntry {
-                                offset: entry_offset,
-                                entry: command.clone(),
-                            }));
-                            //self.log.commit_upto(entry_offset);
-
-                            let me = self.my_host.host.clone();
-                            self.log.persisted(entry_offset, me);
-                        },
-                        _ => ()
-                    }
-                }
-            },
+            AssignAppendLimits(limits) => self.raw.set_append_limits(limits),
 
-            Ok(Query(query, respond_to)) => {
-                self.log.query_persistance(query, respond_to);
-            }
+            AssignPreVote(enabled) => self.raw.set_pre_vote(enabled),
+            AssignCheckQuorum(enabled) => self.raw.set_check_quorum(enabled),
 
-            Err(Disconnected) => dead = true,
+            ObserveState(respond_to) => self.raw.observe_state(respond_to),
 
-            Err(_) => (),
-        }
+            AssignForwardProposals(enabled) => self.raw.set_forward_proposals(enabled),
 
-        dead
-    }
+            AssignReadConsistency(consistency) => self.raw.set_read_consistency(consistency),
+            AssignClock(clock) => self.raw.set_clock(clock),
+            AssignSeed(seed) => self.raw.set_seed(seed),
+            AssignMetrics(metrics) => self.raw.set_metrics(metrics),
 
-    fn react_to_intercommunication(&mut self) {
-        match self.comm.listen() {
-            Some(Pack(from, _, Ack)) => {
-                self.nodes.push(NodeHost { host: from });
+            FetchLeader => self.contact.tx.send(FetchedLeader(self.raw.leader())),
+            AssignLeader(leader) => {
+                self.raw.force_follow(leader);
+                self.contact.tx.send(FetchedLeader(self.raw.leader()));
             },
 
-            Some(Pack(from, _, LeaderQuery)) => {
-                let leader_host = match self.fetch_leader_host() {
-                    Some(NodeHost { ref host }) => Some(host.clone()),
-                    None => None,
-                };
+            FetchNodes => self.contact.tx.send(FetchedNodes(self.raw.nodes())),
 
-                self.comm.send(from, LeaderQueryResponse(leader_host));
-            },
+            ExitCommand => dead = true,
 
-            Some(Pack(_, _, LeaderQueryResponse(leader_host))) => {
-                match leader_host {
-                    Some(host) => self.comm.send(host, Ack),
-                    None => (),
-                }
-            },
+            Introduce(host) => self.raw.introduce(host),
 
-            Some(Pack(leader, _, AppendQuery(log))) => {
-                self.nodes = log.node_list.iter().map(|x| { NodeHost { host: x.clone() } }).collect();
-                self.last_append_log_seen_at = time::now().to_timespec();
-                self.leader_host = Some(NodeHost { host: leader.clone() });
-                self.log.commit_upto(log.committed_offset);
-
-                match log.enqueue {
-                    Some(log_entry) => {
-                        match self.log.enqueue(log_entry.entry.clone()) {
-                            Ok(my_offset) => self.comm.send(leader, Persisted(log_entry.offset)),
-                            _ => (),
-                        }
-                    },
-                    None => (),
-                }
-            },
+            Enqueue(entry, respond_to) => self.raw.enqueue(entry, respond_to),
 
-            Some(Pack(follower, _, Persisted(offset))) => {
-                self.log.persisted(offset, follower);
-            }
+            ChangeMembership(change, respond_to) => self.raw.change_membership(change, respond_to),
 
-            Some(Pack(candidate, _, RequestVote(term))) => {
-                if term > self.term {
-                    self.term = term;
-                    self.votes = 0;
-                    self.last_append_log_seen_at = time::now().to_timespec();
-                    self.comm.send(candidate, Vote(term));
-                }
-            },
+            TransferLeadership(target, respond_to) => self.raw.transfer_leadership(target, respond_to),
 
-            Some(Pack(_, _, Vote(term))) => {
-                if term == self.term && self.state == Candidate {
-                    self.votes += 1;
-                    if self.votes > self.nodes.len() / 2 {
-                        self.state = Leader;
-                        self.send_append_log(None);
-                    }
-                }
-            },
+            Query(query, respond_to) => self.raw.query(query, respond_to),
 
-            None => (),
+            Compact => {
+                self.raw.log_mut().compact().ok();
+            },
         }
-    }
 
-    fn send_append_log(&mut self, enqueue: Option < AppendLogEntry < T > >) {
-        let node_list: Vec < String > = self.nodes.clone().iter().map(|x| { x.host.clone() }).collect();
-
-        for node in self.nodes.iter() {
-            if node.host != self.my_host.host {
-                let committed_offset = self.log.committed_offset();
-                self.comm.send(node.host.clone(), AppendQuery(AppendLog {
-                    committed_offset: committed_offset,
-                    node_list: node_list.clone(),
-                    enqueue: enqueue.clone(),
-                }));
-            }
-        }
+        dead
     }
 
-    fn election_handler(&mut self) {
-        let passed = time::now().to_timespec() - self.last_append_log_seen_at;
-        let passed_since_heartbeat = time::now().to_timespec() - self.last_sent_heartbeat;
-        let duration = self.election_timeout;
-        let heartbeat_timeout = Duration::milliseconds(70);
-
-        match self.state {
-            Follower => {
-                if passed > duration {
-                    self.state = Candidate;
-                    self.votes = 0;
-                    self.already_requested = false;
-                    self.last_append_log_seen_at = time::now().to_timespec();
-                }
-            },
+    // Does what the raft node asks for, in the order Ready says. Nothing is
+    // sent before the hard state and the entries are stored; when storing
+    // fails the Ready is kept and handed out again next time. False once the
+    // state machine gave up on an entry, the node stops then.
+    fn handle_ready(&mut self) -> bool {
+        while self.raw.has_ready() {
+            let ready: Ready < T > = self.raw.ready();
+
+            match ready.hard_state {
+                Some(ref hard_state) => match self.storage.save(hard_state) {
+                    Ok(_) => (),
+                    Err(_) => return true,
+                },
+                None => (),
+            }
 
-            Candidate => {
-                if passed > duration {
-                    self.state = Follower;
-                    self.votes = 0;
-                    self.last_append_log_seen_at = time::now().to_timespec();
-                }
+            match self.store(&ready) {
+                Ok(_) => (),
+                Err(_) => return true,
+            }
 
-                if !self.already_requested && self.state == Candidate {
-                    self.already_requested = true;
-                    self.term += 1;
+            for &(ref recipient, ref package) in ready.messages.iter() {
+                self.comm.send(recipient.clone(), package.clone());
+            }
 
-                    self.comm.send(self.my_host.host.clone(), Vote(self.term));
+            let applied = match ready.committed_entries.last() {
+                Some(last) => self.raw.log_mut().commit_upto(last.offset + 1),
+                None => Ok(()),
+            };
 
-                    for node in self.nodes.iter() {
-                        self.comm.send(node.host.clone(), RequestVote(self.term));
-                    }
-                }
-            },
+            if self.raw.needs_compaction() {
+                self.raw.log_mut().compact().ok();
+            }
 
-            Leader => {
-                self.leader_host = None;
+            self.raw.advance(&ready);
 
-                if passed_since_heartbeat > heartbeat_timeout {
-                    self.send_append_log(None);
-                    self.last_sent_heartbeat = time::now().to_timespec();
-                }
-            },
+            match applied {
+                Ok(_) => (),
+                Err(error) => return self.raw.apply_failed(error).is_ok(),
+            }
         }
-    }
 
-    fn fetch_leader_host(&self) -> Option < NodeHost > {
-        match self.state {
-            Leader => None,
-            _ => self.leader_host.clone(),
-        }
+        true
     }
 
-    fn autocommit(&mut self) {
-        let majority_size = (self.nodes.len() + 1) / 2;
-        let committed_offset_was = self.log.committed_offset();
+    fn store(&mut self, ready: &Ready < T >) -> io::IoResult < () > {
+        match ready.snapshot {
+            Some(ref snapshot) => try!(self.raw.log_mut().install_snapshot(snapshot.clone())),
+            None => (),
+        }
 
-        match self.state {
-            Leader => self.log.autocommit_if_safe(majority_size),
-            _ => (),
+        if !ready.entries.is_empty() {
+            try!(self.raw.log_mut().discard_downto(ready.entries[0].offset));
         }
 
-        if committed_offset_was < self.log.committed_offset() {
-            self.send_append_log(None);
+        for entry in ready.entries.iter() {
+            try!(self.raw.log_mut().enqueue(entry.entry.clone(), entry.term));
         }
+
+        Ok(())
     }
 }
@@ -0,0 +1,119 @@
+use std::cmp;
+use std::collections::RingBuf;
+
+#[deriving(Clone,Show,PartialEq)]
+pub enum ProgressState {
+    // leader is looking for the point where follower's log matches its own,
+    // one AppendLog at a time
+    Probe,
+
+    // logs match, entries are streamed without waiting for acknowledgements
+    Replicate,
+}
+
+// Leader's knowledge about a single follower. Offsets are log lengths:
+// follower's log matches leader's one for the first match_index entries and
+// the next AppendLog will start at next_index.
+#[deriving(Clone,Show)]
+pub struct Progress {
+    pub state: ProgressState,
+    pub next_index: uint,
+    pub match_index: uint,
+    // follower answered since the last quorum check, new follower is given
+    // the benefit of the doubt
+    pub recent_active: bool,
+
+    // log lengths at the end of each unacknowledged AppendLog
+    inflight: RingBuf < uint >,
+    probe_sent: bool,
+}
+
+impl Progress {
+    pub fn new(next_index: uint) -> Progress {
+        Progress {
+            state: Probe,
+            next_index: next_index,
+            match_index: 0,
+            recent_active: true,
+
+            inflight: RingBuf::new(),
+            probe_sent: false,
+        }
+    }
+
+    pub fn inflight(&self) -> uint {
+        self.inflight.len()
+    }
+
+    pub fn is_paused(&self, max_inflight: uint) -> bool {
+        match self.state {
+            Probe => self.probe_sent,
+            Replicate => self.inflight.len() >= max_inflight,
+        }
+    }
+
+    // AppendLog with entries up to sent_upto went out
+    pub fn sent(&mut self, sent_upto: uint) {
+        match self.state {
+            Probe => self.probe_sent = true,
+            Replicate => {
+                self.next_index = sent_upto;
+                self.inflight.push_back(sent_upto);
+            },
+        }
+    }
+
+    pub fn snapshot_sent(&mut self) {
+        self.state = Probe;
+        self.probe_sent = true;
+        self.inflight.clear();
+    }
+
+    // follower's log is known to match ours for the first match_index entries
+    pub fn acknowledged(&mut self, match_index: uint) -> bool {
+        let updated = match_index > self.match_index;
+
+        if updated {
+            self.match_index = match_index;
+        }
+
+        if self.next_index < match_index {
+            self.next_index = match_index;
+        }
+
+        while self.inflight.front().map_or(false, |&sent_upto| sent_upto <= match_index) {
+            self.inflight.pop_front();
+        }
+
+        if self.state == Probe {
+            self.state = Replicate;
+            self.next_index = self.match_index;
+            self.probe_sent = false;
+            self.inflight.clear();
+        }
+
+        updated
+    }
+
+    // follower refused AppendLog following prev_log_index, its log ending
+    // at log_len (or conflicting from there). Returns false for rejections
+    // of messages that were already superseded: an AppendLog or heartbeat
+    // before match_index was sent before the follower acknowledged more,
+    // whatever state we are in now.
+    pub fn rejected(&mut self, prev_log_index: uint, log_len: uint) -> bool {
+        if prev_log_index < self.match_index {
+            return false;
+        }
+
+        if self.state == Probe && prev_log_index != self.next_index {
+            return false;
+        }
+
+        self.state = Probe;
+        self.next_index = cmp::max(self.match_index, cmp::min(prev_log_index, log_len));
+        self.probe_sent = false;
+        self.inflight.clear();
+
+        true
+    }
+}
@@ -0,0 +1,1879 @@
+extern crate time;
+
+use std::time::duration::Duration;
+
+use std::collections::HashMap;
+
+use std::{cmp, mem, io, rand};
+use std::rand::{Rng, SeedableRng, XorShiftRng};
+
+use std::fmt::Show;
+
+use super::intercommunication::{Package, PackageDetails, Ack, LeaderQuery, LeaderQueryResponse, Persisted, Pack, AppendQuery, AppendLog, AppendLogEntry, AppendRejected, InstallSnapshot, RequestVote, Vote, PreVote, PreVoteResponse, TimeoutNow, Heartbeat, HeartbeatResponse, ReadIndexRequest, ReadIndexResponse, Propose, ProposalAccepted, ProposalRejected};
+use super::replication::{ReplicationLog, Committable, Receivable, Queriable, Snapshot, Entry, ConfigurationEntry, NoopEntry, RegisterSessionEntry, SessionCommandEntry};
+use super::session::SessionCommand;
+use super::storage::HardState;
+use super::progress::Progress;
+use super::membership::{Configuration, MembershipChange};
+use super::clock::{Clock, SystemClock};
+use super::metrics::{Metrics, NoopMetrics};
+use super::node::{State, Follower, PreCandidate, Candidate, Leader, NodeHost, AppendLimits, NodeConfig};
+use super::node::{ReadConsistency, ReadIndexRead, LeaseRead, ProposalError, NotLeader, ProposalDropped, LeadershipLost, EntryOverwritten, SessionExpired};
+use super::node::{Committed, TransferResult, Transferred, TransferTimedOut, TransferRejected};
+
+// committed entries kept in the log before it is compacted into a snapshot
+static DEFAULT_SNAPSHOT_THRESHOLD: uint = 1024;
+
+// times the state machine may fail an entry before the node gives up on it
+pub static MAX_APPLY_ATTEMPTS: uint = 3;
+
+// What a RawNode wants the driver to do, in this order: store the hard
+// state, install the snapshot, append the entries, send the messages and
+// hand the committed entries to the state machine. A node must not answer
+// with a vote or an acknowledgement it could forget after a restart. Ready
+// is handed out again until advance says it is done. Entry the state machine
+// failed to apply is reported with apply_failed and handed out again, up to
+// MAX_APPLY_ATTEMPTS times.
+pub struct Ready < T: Committable + Send > {
+    pub hard_state: Option < HardState >,
+    // snapshot from the leader, replaces the log up to its index
+    pub snapshot: Option < Snapshot >,
+    // replace whatever the log holds from the offset of the first one on
+    pub entries: Vec < AppendLogEntry < T > >,
+    // (recipient, package)
+    pub messages: Vec < (String, PackageDetails < T >) >,
+    // committed, for ReplicationLog::commit_upto
+    pub committed_entries: Vec < AppendLogEntry < T > >,
+}
+
+impl < T: Committable + Send > Ready < T > {
+    pub fn is_empty(&self) -> bool {
+        self.hard_state.is_none() && self.snapshot.is_none() && self.messages.is_empty() &&
+            self.entries.is_empty() && self.committed_entries.is_empty()
+    }
+}
+
+// Raft protocol without threads, network or storage: it is driven by tick,
+// step and propose and says what to do through Ready. It only reads the log,
+// the driver writes to it. Time is read from its clock only.
+pub struct RawNode < T: Committable + Send, L: ReplicationLog < T, Q, Rcv >, Q: Queriable + Send, Rcv: Receivable + Send > {
+    state: State,
+    my_host: NodeHost,
+    leader_host: Option < NodeHost >,
+
+    // hosts met through discovery, they form the cluster until there is a
+    // configuration in the log
+    nodes: Vec < NodeHost >,
+    configuration: Option < Configuration >,
+    // log length up to and including the latest configuration entry
+    configuration_index: uint,
+    // changes waiting for the configuration in force to commit, with whoever
+    // asked for them
+    pending_changes: Vec < (MembershipChange, Sender < Result < Committed < Rcv >, ProposalError > >) >,
+
+    // packages to be sent with the next Ready
+    messages: Vec < (String, PackageDetails < T >) >,
+
+    last_append_log_seen_at: time::Timespec,
+    last_sent_heartbeat: time::Timespec,
+    term: uint,
+    voted_for: Option < String >,
+    votes: Vec < String >,
+    already_requested: bool,
+
+    // Log as the driver stored it up to unstable_offset, unstable entries
+    // after that. Snapshot from the leader stands in for the stored log
+    // until the driver installs it.
+    log: L,
+    unstable_offset: uint,
+    unstable: Vec < AppendLogEntry < T > >,
+    pending_snapshot: Option < Snapshot >,
+
+    // entries before commit are committed, the ones before applied_offset
+    // were handed out to be applied
+    commit: uint,
+    applied_offset: uint,
+
+    // hard state the driver reported stored
+    persisted_hard_state: HardState,
+
+    snapshot_threshold: uint,
+
+    append_limits: AppendLimits,
+    progress: HashMap < String, Progress >,
+
+    transfer: Option < LeadershipTransfer >,
+    // set by TimeoutNow, election that follows bypasses leader stickiness
+    transfer_election: bool,
+    // ask whether an election could be won before increasing the term
+    pre_vote: bool,
+    // leader steps down when it has not heard from a majority for an
+    // election timeout
+    check_quorum: bool,
+    last_quorum_check: time::Timespec,
+
+    observers: Vec < Sender < State > >,
+    reported_state: State,
+
+    metrics: Box < Metrics + Send >,
+    reported_term: uint,
+
+    // reads waiting for leadership to be confirmed by a heartbeat round
+    read_round: uint,
+    pending_reads: Vec < PendingRead < Q, Rcv > >,
+    // reads waiting for the state machine to catch up with their index
+    ready_reads: Vec < ReadyRead < Q, Rcv > >,
+    // reads sent to the leader for a read index
+    next_read_id: uint,
+    forwarded_reads: HashMap < uint, ForwardedQuery < Q, Rcv > >,
+
+    // followers relay proposals to the leader instead of rejecting them
+    forward_proposals: bool,
+    next_proposal_id: uint,
+    // proposal_id => (respond_to, session, sent_at)
+    forwarded_proposals: HashMap < uint, (Sender < Result < Committed < Rcv >, ProposalError > >, Option < (uint, uint) >, time::Timespec) >,
+    // proposals stored in the log, waiting for commitment
+    proposals: Vec < PendingProposal < Rcv > >,
+    // entries the state machine failed to apply, and what it answered to
+    // the ones it applied
+    apply_failures: HashMap < uint, io::IoError >,
+    responses: HashMap < uint, Rcv >,
+    // (index, failures) of the entry the state machine keeps failing
+    apply_attempts: (uint, uint),
+
+    read_consistency: ReadConsistency,
+    clock: Box < Clock + Send >,
+    // latest heartbeat round each follower answered, and when recent rounds
+    // were sent
+    acked_rounds: HashMap < String, uint >,
+    round_sent_at: HashMap < uint, time::Timespec >,
+
+    heartbeat_interval: Duration,
+    // min_election_timeout of the config
+    election_timeout: Duration,
+    max_election_timeout: Duration,
+    // Follower and candidate wait somewhere between min and max election
+    // timeout, drawn anew every time the timer fires, so that nodes rarely
+    // stand for election at once
+    randomized_election_timeout: Duration,
+    seed: u32,
+    rng: XorShiftRng,
+}
+
+enum Reader < Q: Queriable + Send, Rcv: Receivable + Send > {
+    LocalRead(Q, Sender < Rcv >),
+    // ForwardedRead(follower, read_id)
+    ForwardedRead(String, uint),
+}
+
+// read_index is unknown until leader commits an entry of its own term
+struct PendingRead < Q: Queriable + Send, Rcv: Receivable + Send > {
+    round: uint,
+    read_index: Option < uint >,
+    acks: Vec < String >,
+    reader: Reader < Q, Rcv >,
+}
+
+struct ReadyRead < Q: Queriable + Send, Rcv: Receivable + Send > {
+    read_index: uint,
+    query: Q,
+    respond_to: Sender < Rcv >,
+}
+
+// Read waiting for a read index from the leader it was sent to, in the term
+// it was sent
+struct ForwardedQuery < Q: Queriable + Send, Rcv: Receivable + Send > {
+    query: Q,
+    respond_to: Sender < Rcv >,
+    leader: String,
+    term: uint,
+    sent_at: time::Timespec,
+}
+
+struct PendingProposal < Rcv > {
+    index: uint,
+    term: uint,
+    // (client_id, sequence) of a session command
+    session: Option < (uint, uint) >,
+    respond_to: Sender < Result < Committed < Rcv >, ProposalError > >,
+    // when the proposal reached this node
+    proposed_at: time::Timespec,
+}
+
+// Leadership transfer in progress, no proposals are accepted meanwhile
+struct LeadershipTransfer {
+    target: String,
+    started_at: time::Timespec,
+    timeout_now_sent: bool,
+    respond_to: Sender < TransferResult >,
+}
+
+impl < T: Committable + Send + Clone + Show, L: ReplicationLog < T, Q, Rcv >, Q: Queriable + Send, Rcv: Receivable + Send > RawNode < T, L, Q, Rcv > {
+    // config is expected to pass NodeConfig::validate
+    pub fn new(host: &str, log: L, hard_state: HardState, config: NodeConfig) -> RawNode < T, L, Q, Rcv > {
+        let term = hard_state.term;
+
+        let clock = box SystemClock as Box < Clock + Send >;
+        let now = clock.now();
+        let unstable_offset = log.len();
+        let applied_offset = log.committed_offset();
+        // entries committed before a restart are applied again by the driver
+        let commit = cmp::min(cmp::max(hard_state.commit, applied_offset), unstable_offset);
+
+        let mut node = RawNode {
+            state: Follower,
+            my_host: NodeHost { host: host.to_string() },
+            leader_host: None,
+
+            nodes: vec![NodeHost { host: host.to_string() }],
+            configuration: None,
+            configuration_index: 0,
+            pending_changes: vec![],
+
+            messages: vec![],
+
+            last_append_log_seen_at: now,
+            last_sent_heartbeat: now,
+            term: term,
+            voted_for: hard_state.voted_for.clone(),
+            votes: vec![],
+            already_requested: false,
+
+            log: log,
+            unstable_offset: unstable_offset,
+            unstable: vec![],
+            pending_snapshot: None,
+
+            commit: commit,
+            applied_offset: applied_offset,
+
+            persisted_hard_state: hard_state,
+
+            snapshot_threshold: DEFAULT_SNAPSHOT_THRESHOLD,
+
+            append_limits: config.append_limits,
+            progress: HashMap::new(),
+
+            transfer: None,
+            transfer_election: false,
+            pre_vote: config.pre_vote,
+            check_quorum: config.check_quorum,
+            last_quorum_check: now,
+
+            observers: vec![],
+            reported_state: Follower,
+
+            metrics: box NoopMetrics as Box < Metrics + Send >,
+            reported_term: term,
+
+            read_round: 0,
+            pending_reads: vec![],
+            ready_reads: vec![],
+            next_read_id: 0,
+            forwarded_reads: HashMap::new(),
+
+            forward_proposals: config.forward_proposals,
+            next_proposal_id: 0,
+            forwarded_proposals: HashMap::new(),
+            proposals: vec![],
+            apply_failures: HashMap::new(),
+            responses: HashMap::new(),
+            apply_attempts: (0, 0),
+
+            read_consistency: ReadIndexRead,
+            clock: clock,
+            acked_rounds: HashMap::new(),
+            round_sent_at: HashMap::new(),
+
+            heartbeat_interval: config.heartbeat_interval,
+            election_timeout: config.min_election_timeout,
+            max_election_timeout: config.max_election_timeout,
+            randomized_election_timeout: config.min_election_timeout,
+            seed: 0,
+            rng: rand::weak_rng(),
+        };
+
+        node.reload_configuration();
+        node.set_seed(rand::random());
+        node
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    // None when we are the leader
+    pub fn leader(&self) -> Option < NodeHost > {
+        self.fetch_leader_host()
+    }
+
+    pub fn nodes(&self) -> Vec < NodeHost > {
+        self.cluster().hosts().into_iter().map(|host| { NodeHost { host: host } }).collect()
+    }
+
+    pub fn force_state(&mut self, state: State) {
+        self.state = state;
+        self.progress.clear();
+        self.acked_rounds.clear();
+        self.round_sent_at.clear();
+        self.last_quorum_check = self.clock.now();
+    }
+
+    pub fn force_follow(&mut self, leader: Option < NodeHost >) {
+        self.leader_host = leader.clone();
+
+        match leader {
+            Some(leader) => self.send(leader.host, Ack),
+            None => (),
+        }
+    }
+
+    pub fn introduce(&mut self, host: String) {
+        self.send(host.clone(), Ack);
+        self.send(host, LeaderQuery);
+    }
+
+    pub fn set_append_limits(&mut self, limits: AppendLimits) {
+        self.append_limits = limits;
+    }
+
+    pub fn set_pre_vote(&mut self, enabled: bool) {
+        self.pre_vote = enabled;
+    }
+
+    pub fn set_check_quorum(&mut self, enabled: bool) {
+        self.check_quorum = enabled;
+    }
+
+    pub fn observe_state(&mut self, respond_to: Sender < State >) {
+        self.observers.push(respond_to);
+    }
+
+    pub fn set_forward_proposals(&mut self, enabled: bool) {
+        self.forward_proposals = enabled;
+    }
+
+    pub fn set_read_consistency(&mut self, consistency: ReadConsistency) {
+        self.read_consistency = consistency;
+    }
+
+    // Same seed on the same host gives the same election timeouts, so a run
+    // with a manual clock can be replayed. Hosts of a cluster sharing a seed
+    // still get different timeouts.
+    pub fn set_seed(&mut self, seed: u32) {
+        let host_hash = self.my_host.host.as_bytes().iter()
+            .fold(0x811c9dc5u32, |hash, &byte| (hash ^ byte as u32) * 0x01000193);
+
+        self.seed = seed;
+        self.rng = SeedableRng::from_seed([seed, host_hash, 0x2f6b1c3d, 0x5a0e9b47]);
+        self.randomize_election_timeout();
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    // term is reported right away, everything else as it happens
+    pub fn set_metrics(&mut self, metrics: Box < Metrics + Send >) {
+        self.metrics = metrics;
+        self.metrics.term_changed(self.term);
+        self.reported_term = self.term;
+    }
+
+    // timers start over on the new clock
+    pub fn set_clock(&mut self, clock: Box < Clock + Send >) {
+        self.clock = clock;
+
+        let now = self.clock.now();
+        self.last_append_log_seen_at = now;
+        self.last_sent_heartbeat = now;
+        self.last_quorum_check = now;
+        self.round_sent_at.clear();
+    }
+
+    // Only the leader changes the cluster, anybody else answers with where
+    // the leader is. respond_to is told once the joint configuration with
+    // the change is committed, from then on the change is carried through by
+    // whoever leads.
+    pub fn change_membership(&mut self, change: MembershipChange, respond_to: Sender < Result < Committed < Rcv >, ProposalError > >) {
+        if self.state != Leader {
+            respond_to.send_opt(Err(NotLeader(self.leader_host.clone()))).ok();
+            return;
+        }
+
+        match self.transfer {
+            Some(ref transfer) => {
+                respond_to.send_opt(Err(NotLeader(Some(NodeHost { host: transfer.target.clone() })))).ok();
+                return;
+            },
+            None => (),
+        }
+
+        self.pending_changes.push((change, respond_to));
+    }
+
+    pub fn transfer_leadership(&mut self, target: String, respond_to: Sender < TransferResult >) {
+        let eligible = self.state == Leader &&
+            self.transfer.is_none() &&
+            target != self.my_host.host &&
+            self.cluster().voters.contains(&target);
+
+        if eligible {
+            self.transfer = Some(LeadershipTransfer {
+                target: target,
+                started_at: self.clock.now(),
+                timeout_now_sent: false,
+                respond_to: respond_to,
+            });
+        } else {
+            respond_to.send(TransferRejected);
+        }
+    }
+
+    // Proposes the entry, or relays it to the leader, and tells respond_to
+    // what became of it
+    pub fn enqueue(&mut self, entry: Entry < T >, respond_to: Sender < Result < Committed < Rcv >, ProposalError > >) {
+        let session = session_of(&entry);
+        let leader = match self.leader_host {
+            Some(ref leader) if self.forward_proposals && self.state != Leader => Some(leader.host.clone()),
+            _ => None,
+        };
+
+        match leader {
+            Some(leader) => {
+                let proposal_id = self.next_proposal_id;
+                self.next_proposal_id += 1;
+
+                let now = self.clock.now();
+                self.forwarded_proposals.insert(proposal_id, (respond_to, session, now));
+                self.send(leader, Propose(proposal_id, entry));
+            },
+            None => match self.propose(entry) {
+                Ok(index) => self.proposals.push(PendingProposal {
+                    index: index,
+                    term: self.term,
+                    session: session,
+                    respond_to: respond_to,
+                    proposed_at: self.clock.now(),
+                }),
+                Err(error) => {
+                    respond_to.send_opt(Err(error)).ok();
+                },
+            },
+        }
+    }
+
+    pub fn query(&mut self, query: Q, respond_to: Sender < Rcv >) {
+        if self.has_read_lease() {
+            self.ready_reads.push(ReadyRead {
+                read_index: self.commit,
+                query: query,
+                respond_to: respond_to,
+            });
+        } else if self.state == Leader {
+            self.request_read_index(LocalRead(query, respond_to));
+        } else {
+            // without a known leader nobody can confirm the read and it is
+            // dropped
+            match self.leader_host.clone() {
+                Some(leader) => {
+                    let read_id = self.next_read_id;
+                    self.next_read_id += 1;
+
+                    self.forwarded_reads.insert(read_id, ForwardedQuery {
+                        query: query,
+                        respond_to: respond_to,
+                        leader: leader.host.clone(),
+                        term: self.term,
+                        sent_at: self.clock.now(),
+                    });
+                    self.send(leader.host, ReadIndexRequest(read_id));
+                },
+                None => (),
+            }
+        }
+    }
+
+    pub fn log(&self) -> &L {
+        &self.log
+    }
+
+    // For the driver to do what a Ready says, nothing else is to be written
+    pub fn log_mut(&mut self) -> &mut L {
+        &mut self.log
+    }
+
+    // committed entries kept in the log passed the threshold, the driver
+    // compacts the log after applying them
+    pub fn needs_compaction(&self) -> bool {
+        self.log.committed_offset() - self.log.first_offset() >= self.snapshot_threshold
+    }
+
+    // Timers and everything that waits for them: elections, heartbeats,
+    // replication, commitment, reads
+    pub fn tick(&mut self) {
+        self.election_handler();
+        self.check_quorum();
+        self.replicate();
+        self.autocommit();
+        self.transfer_leadership_step();
+        self.expire_forwarded_proposals();
+        self.resend_forwarded_reads();
+        self.resolve_proposals();
+        self.advance_membership();
+        self.serve_reads();
+        self.report_state();
+        self.report_metrics();
+    }
+
+    // What is left to do since the last advance, nothing changes until then
+    pub fn ready(&self) -> Ready < T > {
+        let hard_state = self.hard_state();
+
+        Ready {
+            hard_state: if hard_state != self.persisted_hard_state { Some(hard_state) } else { None },
+            snapshot: self.pending_snapshot.clone(),
+            entries: self.unstable.clone(),
+            messages: self.messages.clone(),
+            committed_entries: self.entries_between(cmp::max(self.applied_offset, self.first_offset()), self.commit),
+        }
+    }
+
+    pub fn has_ready(&self) -> bool {
+        self.hard_state() != self.persisted_hard_state || self.pending_snapshot.is_some() ||
+            !self.unstable.is_empty() || !self.messages.is_empty() ||
+            self.commit > cmp::max(self.applied_offset, self.first_offset())
+    }
+
+    // Driver did everything the Ready says. Entries it stored count towards
+    // commitment on a leader, proposals and reads waiting for the entries it
+    // applied are answered.
+    pub fn advance(&mut self, ready: &Ready < T >) {
+        match ready.hard_state {
+            Some(ref hard_state) => self.persisted_hard_state = hard_state.clone(),
+            None => (),
+        }
+
+        match ready.snapshot {
+            Some(ref snapshot) if ready.snapshot == self.pending_snapshot => {
+                self.applied_offset = cmp::max(self.applied_offset, snapshot.last_included_index);
+                self.pending_snapshot = None;
+            },
+            _ => (),
+        }
+
+        match ready.entries.last() {
+            Some(last) if ready.entries[0].offset == self.unstable_offset && self.term_at(last.offset) == Some(last.term) => {
+                drop_prefix(&mut self.unstable, ready.entries.len());
+                self.unstable_offset = last.offset + 1;
+                self.metrics.entries_appended(ready.entries.len());
+            },
+            _ => (),
+        }
+
+        // packages queued after the Ready was taken stay
+        let sent = cmp::min(ready.messages.len(), self.messages.len());
+        drop_prefix(&mut self.messages, sent);
+
+        match ready.committed_entries.last() {
+            Some(last) => {
+                self.applied_offset = cmp::max(self.applied_offset, last.offset + 1);
+                self.metrics.entries_committed(ready.committed_entries.len(), self.commit);
+            },
+            None => (),
+        }
+
+        for (index, response) in self.log.take_responses().into_iter() {
+            self.responses.insert(index, response);
+        }
+
+        self.autocommit();
+        self.resolve_proposals();
+        self.serve_reads();
+    }
+
+    // State machine failed to apply the entry at the committed offset of the
+    // log. Whoever proposed it is told, and the entry is handed out again
+    // with the next Ready. Once it failed MAX_APPLY_ATTEMPTS times nothing
+    // after it can ever be applied: the error is returned and the driver has
+    // to stop the node.
+    pub fn apply_failed(&mut self, error: io::IoError) -> io::IoResult < () > {
+        let failed_index = self.log.committed_offset();
+        let attempts = match self.apply_attempts {
+            (index, attempts) if index == failed_index => attempts + 1,
+            _ => 1,
+        };
+
+        self.apply_attempts = (failed_index, attempts);
+        self.apply_failures.insert(failed_index, error.clone());
+        self.applied_offset = failed_index;
+
+        self.resolve_proposals();
+
+        if attempts >= MAX_APPLY_ATTEMPTS {
+            Err(error)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Time until the nearest timer goes off. Everything else a tick does
+    // follows from step and the commands, so a driver that ticks after each
+    // of those can sleep this long in between.
+    pub fn time_to_next_tick(&self) -> Duration {
+        let mut deadlines = vec![];
+
+        match self.state {
+            Leader => {
+                deadlines.push(self.last_sent_heartbeat + self.heartbeat_interval);
+
+                if self.check_quorum {
+                    deadlines.push(self.last_quorum_check + self.election_timeout);
+                }
+            },
+            _ => deadlines.push(self.last_append_log_seen_at + self.randomized_election_timeout),
+        }
+
+        match self.transfer {
+            Some(ref transfer) => deadlines.push(transfer.started_at + self.election_timeout),
+            None => (),
+        }
+
+        for &(_, _, sent_at) in self.forwarded_proposals.values() {
+            deadlines.push(sent_at + self.election_timeout);
+        }
+
+        for read in self.forwarded_reads.values() {
+            deadlines.push(read.sent_at + self.election_timeout);
+        }
+
+        let now = self.clock.now();
+
+        match deadlines.into_iter().min() {
+            Some(deadline) if deadline > now => deadline - now,
+            _ => Duration::zero(),
+        }
+    }
+
+    // Reacts to a package from another node
+    pub fn step(&mut self, package: Package < T >) {
+        match package {
+            Pack(_, _, ref details) => self.metrics.message_received(details.kind()),
+        }
+
+        match package {
+            // once there is a configuration in the log hosts join through
+            // change_membership only
+            Pack(from, _, Ack) => {
+                if self.configuration.is_none() && !self.nodes.iter().any(|node| node.host == from) {
+                    self.nodes.push(NodeHost { host: from });
+                }
+            },
+
+            Pack(from, _, LeaderQuery) => {
+                let leader_host = match self.fetch_leader_host() {
+                    Some(NodeHost { ref host }) => Some(host.clone()),
+                    None => None,
+                };
+
+                self.send(from, LeaderQueryResponse(leader_host));
+            },
+
+            Pack(_, _, LeaderQueryResponse(leader_host)) => {
+                match leader_host {
+                    Some(host) => self.send(host, Ack),
+                    None => (),
+                }
+            },
+
+            Pack(leader, _, AppendQuery(log)) => {
+                self.react_to_append_log(leader, log);
+            },
+
+            Pack(follower, _, Persisted(term, match_index)) => {
+                if term > self.term {
+                    self.step_down(term);
+                    self.last_append_log_seen_at = self.clock.now();
+                } else if term == self.term && self.state == Leader {
+                    let progress = self.progress(&follower);
+                    progress.recent_active = true;
+                    progress.acknowledged(match_index);
+                }
+            },
+
+            Pack(follower, _, AppendRejected(term, prev_log_index, log_len)) => {
+                if term > self.term {
+                    self.step_down(term);
+                    self.last_append_log_seen_at = self.clock.now();
+                } else if self.state == Leader {
+                    // back off towards the point where logs match, one
+                    // probe at a time
+                    let progress = self.progress(&follower);
+                    progress.recent_active = true;
+                    progress.rejected(prev_log_index, log_len);
+                }
+            },
+
+            Pack(leader, _, InstallSnapshot(term, snapshot)) => {
+                self.react_to_install_snapshot(leader, term, snapshot);
+            },
+
+            Pack(candidate, _, RequestVote(term, last_log_index, last_log_term, leadership_transfer)) => {
+                // node removed from the cluster stops hearing from leader and
+                // keeps asking for votes, it must not disturb a live leader,
+                // unless the leader itself handed leadership over
+                if !leadership_transfer && self.heard_from_leader_recently() {
+                    return;
+                }
+
+                if term > self.term {
+                    self.step_down(term);
+                }
+
+                let not_voted_yet = match self.voted_for {
+                    Some(ref host) => *host == candidate,
+                    None => true,
+                };
+
+                let grant = term == self.term &&
+                    not_voted_yet &&
+                    self.is_log_up_to_date(last_log_index, last_log_term);
+
+                if grant {
+                    self.voted_for = Some(candidate.clone());
+                    self.last_append_log_seen_at = self.clock.now();
+                }
+
+                // vote goes out in the same Ready as the hard state storing it
+                if grant {
+                    self.send(candidate, Vote(term));
+                }
+            },
+
+            Pack(voter, _, Vote(term)) => {
+                if term == self.term && self.state == Candidate {
+                    if !self.votes.contains(&voter) {
+                        self.votes.push(voter);
+                    }
+
+                    if self.cluster().has_quorum(self.votes.as_slice()) {
+                        self.become_leader();
+                    }
+                }
+            },
+
+            // Nothing changes on our side: a node that would vote for the
+            // candidate says so, term and vote stay as they are
+            Pack(candidate, _, PreVote(term, last_log_index, last_log_term)) => {
+                let grant = term > self.term &&
+                    !self.heard_from_leader_recently() &&
+                    self.is_log_up_to_date(last_log_index, last_log_term);
+
+                self.send(candidate, PreVoteResponse(term, grant));
+            },
+
+            Pack(voter, _, PreVoteResponse(term, granted)) => {
+                if granted && term == self.term + 1 && self.state == PreCandidate {
+                    if !self.votes.contains(&voter) {
+                        self.votes.push(voter);
+                    }
+
+                    if self.cluster().has_quorum(self.votes.as_slice()) {
+                        self.state = Candidate;
+                        self.votes = vec![];
+                        self.already_requested = false;
+                        self.last_append_log_seen_at = self.clock.now();
+                    }
+                }
+            },
+
+            Pack(leader, _, Heartbeat(term, round)) => {
+                if term >= self.term {
+                    if term > self.term {
+                        self.step_down(term);
+                    }
+
+                    self.state = Follower;
+                    self.last_append_log_seen_at = self.clock.now();
+                    self.leader_host = Some(NodeHost { host: leader.clone() });
+                }
+
+                self.send(leader, HeartbeatResponse(self.term, round));
+            },
+
+            Pack(follower, _, HeartbeatResponse(term, round)) => {
+                if term > self.term {
+                    self.step_down(term);
+                    self.last_append_log_seen_at = self.clock.now();
+                } else if term == self.term && self.state == Leader {
+                    self.progress(&follower).recent_active = true;
+
+                    let acked_round = match self.acked_rounds.find(&follower) {
+                        Some(&acked_round) => cmp::max(acked_round, round),
+                        None => round,
+                    };
+                    self.acked_rounds.insert(follower.clone(), acked_round);
+
+                    // answer to a later round confirms earlier reads as well
+                    for read in self.pending_reads.iter_mut() {
+                        if read.round <= round && !read.acks.contains(&follower) {
+                            read.acks.push(follower.clone());
+                        }
+                    }
+                }
+            },
+
+            Pack(follower, _, ReadIndexRequest(read_id)) => {
+                if self.has_read_lease() {
+                    let read_index = self.commit;
+                    self.send(follower, ReadIndexResponse(read_id, read_index));
+                } else if self.state == Leader {
+                    self.request_read_index(ForwardedRead(follower, read_id));
+                }
+            },
+
+            Pack(_, _, ReadIndexResponse(read_id, read_index)) => {
+                match self.forwarded_reads.pop(&read_id) {
+                    Some(read) => self.ready_reads.push(ReadyRead {
+                        read_index: read_index,
+                        query: read.query,
+                        respond_to: read.respond_to,
+                    }),
+                    None => (),
+                }
+            },
+
+            Pack(follower, _, Propose(proposal_id, entry)) => {
+                // proposals are forwarded once at most, never in a loop
+                match self.propose(entry) {
+                    Ok(offset) => self.send(follower, ProposalAccepted(proposal_id, offset, self.term)),
+                    Err(NotLeader(leader_hint)) => {
+                        let leader_hint = leader_hint.map(|leader| { leader.host });
+                        self.send(follower, ProposalRejected(proposal_id, leader_hint));
+                    },
+                    Err(ProposalDropped) | Err(LeadershipLost) | Err(EntryOverwritten) | Err(SessionExpired) => {
+                        let me = self.my_host.host.clone();
+                        self.send(follower, ProposalRejected(proposal_id, Some(me)));
+                    },
+                }
+            },
+
+            // follower watches its own log for the entry to get committed
+            Pack(_, _, ProposalAccepted(proposal_id, offset, term)) => {
+                match self.forwarded_proposals.pop(&proposal_id) {
+                    Some((respond_to, session, sent_at)) => self.proposals.push(PendingProposal {
+                        index: offset,
+                        term: term,
+                        session: session,
+                        respond_to: respond_to,
+                        proposed_at: sent_at,
+                    }),
+                    None => (),
+                }
+            },
+
+            Pack(leader, _, ProposalRejected(proposal_id, leader_hint)) => {
+                match self.forwarded_proposals.pop(&proposal_id) {
+                    Some((respond_to, _, _)) => {
+                        let error = if leader_hint.as_ref() == Some(&leader) {
+                            ProposalDropped
+                        } else {
+                            NotLeader(leader_hint.map(|host| { NodeHost { host: host } }))
+                        };
+
+                        respond_to.send_opt(Err(error)).ok();
+                    },
+                    None => (),
+                }
+            },
+
+            Pack(_, _, TimeoutNow(term)) => {
+                if term == self.term && self.state == Follower &&
+                    self.cluster().voters.contains(&self.my_host.host) {
+                    self.state = Candidate;
+                    self.votes = vec![];
+                    self.already_requested = false;
+                    self.transfer_election = true;
+                    self.last_append_log_seen_at = self.clock.now();
+                }
+            },
+        }
+    }
+
+    // private
+
+    fn send(&mut self, recipient: String, package: PackageDetails < T >) {
+        self.metrics.message_sent(package.kind());
+        self.messages.push((recipient, package));
+    }
+
+    fn entries_between(&self, from: uint, to: uint) -> Vec < AppendLogEntry < T > > {
+        range(from, to).filter_map(|offset| {
+            match (self.entry_at(offset), self.term_at(offset)) {
+                (Ok(entry), Some(term)) => Some(AppendLogEntry { offset: offset, term: term, entry: entry }),
+                _ => None,
+            }
+        }).collect()
+    }
+
+    // The log as it will be once the driver is done with the Ready
+
+    fn log_len(&self) -> uint {
+        self.unstable_offset + self.unstable.len()
+    }
+
+    fn first_offset(&self) -> uint {
+        match self.pending_snapshot {
+            Some(ref snapshot) => snapshot.last_included_index,
+            None => self.log.first_offset(),
+        }
+    }
+
+    fn term_at(&self, offset: uint) -> Option < uint > {
+        if offset >= self.unstable_offset {
+            return if offset < self.log_len() {
+                Some(self.unstable[offset - self.unstable_offset].term)
+            } else {
+                None
+            };
+        }
+
+        match self.pending_snapshot {
+            Some(ref snapshot) if offset + 1 == snapshot.last_included_index => Some(snapshot.last_included_term),
+            Some(_) => None,
+            None => self.log.term_at(offset),
+        }
+    }
+
+    fn last_term(&self) -> uint {
+        match self.log_len() {
+            0 => 0,
+            len => self.term_at(len - 1).unwrap_or(0),
+        }
+    }
+
+    fn entry_at(&self, offset: uint) -> io::IoResult < Entry < T > > {
+        if offset >= self.unstable_offset {
+            return if offset < self.log_len() {
+                Ok(self.unstable[offset - self.unstable_offset].entry.clone())
+            } else {
+                Err(io::standard_error(io::InvalidInput))
+            };
+        }
+
+        match self.pending_snapshot {
+            Some(_) => Err(io::standard_error(io::InvalidInput)),
+            None => self.log.entry_at(offset),
+        }
+    }
+
+    fn last_snapshot(&self) -> Option < Snapshot > {
+        match self.pending_snapshot {
+            Some(ref snapshot) => Some(snapshot.clone()),
+            None => self.log.snapshot(),
+        }
+    }
+
+    fn append_entry(&mut self, entry: Entry < T >, term: uint) -> uint {
+        let offset = self.log_len();
+        self.unstable.push(AppendLogEntry { offset: offset, term: term, entry: entry });
+        offset
+    }
+
+    // stored entries from offset on are replaced by the next ones appended
+    fn truncate(&mut self, offset: uint) {
+        if offset >= self.unstable_offset {
+            self.unstable.truncate(offset - self.unstable_offset);
+        } else {
+            self.unstable.clear();
+            self.unstable_offset = offset;
+        }
+    }
+
+    fn react_to_append_log(&mut self, leader: String, log: AppendLog < T >) {
+        if log.term < self.term {
+            let log_len = self.log_len();
+            self.send(leader, AppendRejected(self.term, log.prev_log_index, log_len));
+            return;
+        }
+
+        if log.term > self.term {
+            self.step_down(log.term);
+        }
+
+        self.state = Follower;
+        self.last_append_log_seen_at = self.clock.now();
+        self.leader_host = Some(NodeHost { host: leader.clone() });
+
+        // log matching: our log should contain entry at prev_log_index with
+        // the same term, otherwise leader has to go further back. Entries
+        // compacted into snapshot are committed and therefore match.
+        if log.prev_log_index > self.log_len() {
+            let log_len = self.log_len();
+            self.send(leader, AppendRejected(self.term, log.prev_log_index, log_len));
+            return;
+        }
+
+        if log.prev_log_index >= self.first_offset() && log.prev_log_index > 0 &&
+            self.term_at(log.prev_log_index - 1) != Some(log.prev_log_term) {
+            self.send(leader, AppendRejected(self.term, log.prev_log_index, log.prev_log_index - 1));
+            return;
+        }
+
+        let mut last_new_index = log.prev_log_index;
+        let mut configuration_changed = false;
+
+        for log_entry in log.entries.into_iter() {
+            if log_entry.offset < self.first_offset() {
+                last_new_index = log_entry.offset + 1;
+                continue;
+            }
+
+            // conflicting suffix (same offset, different term) is discarded
+            match self.term_at(log_entry.offset) {
+                Some(term) if term != log_entry.term => {
+                    self.truncate(log_entry.offset);
+                    configuration_changed = true;
+                },
+                _ => (),
+            }
+
+            if self.log_len() == log_entry.offset {
+                match log_entry.entry {
+                    ConfigurationEntry(_) => configuration_changed = true,
+                    _ => (),
+                }
+
+                self.append_entry(log_entry.entry, log_entry.term);
+            }
+
+            if self.term_at(log_entry.offset) != Some(log_entry.term) {
+                break;
+            }
+
+            last_new_index = log_entry.offset + 1;
+        }
+
+        if configuration_changed {
+            self.reload_configuration();
+        }
+
+        self.commit_to(cmp::min(log.committed_offset, last_new_index));
+
+        // heartbeats are acknowledged too, that is how leader learns that
+        // its probe found the matching point. It goes out in the Ready that
+        // has the entries stored first.
+        self.send(leader, Persisted(self.term, last_new_index));
+    }
+
+    fn react_to_install_snapshot(&mut self, leader: String, term: uint, snapshot: Snapshot) {
+        if term < self.term {
+            let log_len = self.log_len();
+            self.send(leader, AppendRejected(self.term, snapshot.last_included_index, log_len));
+            return;
+        }
+
+        if term > self.term {
+            self.step_down(term);
+        }
+
+        self.state = Follower;
+        self.last_append_log_seen_at = self.clock.now();
+        self.leader_host = Some(NodeHost { host: leader.clone() });
+
+        let last_included_index = snapshot.last_included_index;
+
+        // entries after the snapshot come again from the leader
+        if last_included_index > self.commit {
+            self.pending_snapshot = Some(snapshot);
+            self.unstable = vec![];
+            self.unstable_offset = last_included_index;
+            self.commit = last_included_index;
+
+            self.reload_configuration();
+        }
+
+        self.send(leader, Persisted(self.term, last_included_index));
+    }
+
+    fn send_snapshot(&mut self, follower: String) {
+        match self.last_snapshot() {
+            Some(snapshot) => self.send(follower, InstallSnapshot(self.term, snapshot)),
+            None => (),
+        }
+    }
+
+    fn cluster(&self) -> Configuration {
+        match self.configuration {
+            Some(ref configuration) => configuration.clone(),
+            None => Configuration::new(self.nodes.iter().map(|node| { node.host.clone() }).collect()),
+        }
+    }
+
+    fn followers(&self) -> Vec < String > {
+        self.cluster().hosts().into_iter()
+            .filter(|host| *host != self.my_host.host)
+            .collect()
+    }
+
+    // Latest configuration in the log is in force as soon as it is
+    // appended, committed or not
+    fn reload_configuration(&mut self) {
+        let mut offset = self.log_len();
+
+        while offset > self.first_offset() {
+            offset -= 1;
+
+            match self.entry_at(offset) {
+                Ok(ConfigurationEntry(configuration)) => {
+                    self.configuration = Some(configuration);
+                    self.configuration_index = offset + 1;
+                    return;
+                },
+                _ => (),
+            }
+        }
+
+        self.configuration = self.last_snapshot().and_then(|snapshot| snapshot.configuration);
+        self.configuration_index = self.first_offset();
+    }
+
+    // changes that did not reach the log are lost with leadership
+    fn drop_pending_changes(&mut self) {
+        for (_, respond_to) in mem::replace(&mut self.pending_changes, vec![]).into_iter() {
+            respond_to.send_opt(Err(LeadershipLost)).ok();
+        }
+    }
+
+    fn append_configuration(&mut self, configuration: Configuration) {
+        let term = self.term;
+        let offset = self.append_entry(ConfigurationEntry(configuration.clone()), term);
+
+        self.configuration = Some(configuration);
+        self.configuration_index = offset + 1;
+    }
+
+    // Leader walks the cluster through one change at a time: the joint
+    // configuration first, the new one once the joint one is committed
+    fn advance_membership(&mut self) {
+        if self.state != Leader {
+            self.drop_pending_changes();
+            return;
+        }
+
+        // discovered hosts become the initial configuration
+        if self.configuration.is_none() {
+            let initial = self.cluster();
+            self.append_configuration(initial);
+            return;
+        }
+
+        if self.configuration_index > self.commit {
+            return;
+        }
+
+        let configuration = self.configuration.clone().unwrap();
+
+        if configuration.is_joint() {
+            self.append_configuration(configuration.leave_joint());
+        } else if !configuration.contains(&self.my_host.host) {
+            // removed leader leaves once the cluster can go on without it
+            self.state = Follower;
+            self.progress.clear();
+        } else if !self.pending_changes.is_empty() {
+            let pending = mem::replace(&mut self.pending_changes, vec![]);
+            let changes: Vec < MembershipChange > = pending.iter().map(|&(ref change, _)| change.clone()).collect();
+            let changed = configuration.apply(changes.as_slice());
+
+            // change that is in force already resolves with the
+            // configuration entry that has it
+            let (index, term) = if changed.voters != configuration.voters {
+                self.append_configuration(changed);
+                (self.configuration_index - 1, self.term)
+            } else {
+                let index = self.configuration_index - 1;
+                (index, self.term_at(index).unwrap_or(self.term))
+            };
+
+            let now = self.clock.now();
+            for (_, respond_to) in pending.into_iter() {
+                self.proposals.push(PendingProposal {
+                    index: index,
+                    term: term,
+                    session: None,
+                    respond_to: respond_to,
+                    proposed_at: now,
+                });
+            }
+        }
+    }
+
+    fn become_leader(&mut self) {
+        self.metrics.election_won();
+
+        self.state = Leader;
+        self.progress.clear();
+        self.acked_rounds.clear();
+        self.round_sent_at.clear();
+        self.last_quorum_check = self.clock.now();
+        self.drop_pending_changes();
+
+        // followers are probed from the end of our log as it was before the
+        // noop, so that the first AppendLog carries the noop already
+        let next_index = self.log_len();
+        for follower in self.followers().into_iter() {
+            self.progress.insert(follower, Progress::new(next_index));
+        }
+
+        // committing an entry of its own term commits everything before it,
+        // configuration change left unfinished by the previous leader too
+        let term = self.term;
+        self.append_entry(NoopEntry, term);
+
+        self.replicate();
+    }
+
+    // new follower is assumed to be up to date and probed from the end of
+    // our log
+    fn progress(&mut self, follower: &String) -> &mut Progress {
+        let next_index = self.log_len();
+
+        if !self.progress.contains_key(follower) {
+            self.progress.insert(follower.clone(), Progress::new(next_index));
+        }
+
+        self.progress.find_mut(follower).unwrap()
+    }
+
+    // Sends each follower whatever it is missing: a single probe while
+    // looking for the matching point, batch after batch once it is found
+    fn replicate(&mut self) {
+        if self.state != Leader {
+            return;
+        }
+
+        let max_inflight = self.append_limits.max_inflight;
+
+        for follower in self.followers().into_iter() {
+            loop {
+                let (paused, next_index) = {
+                    let progress = self.progress(&follower);
+                    (progress.is_paused(max_inflight), progress.next_index)
+                };
+
+                if paused || next_index >= self.log_len() {
+                    break;
+                }
+
+                if next_index < self.first_offset() {
+                    self.send_snapshot(follower.clone());
+                    self.progress(&follower).snapshot_sent();
+                    break;
+                }
+
+                let entries = self.batch_from(next_index);
+                if entries.is_empty() {
+                    break;
+                }
+
+                let sent_upto = next_index + entries.len();
+                self.send_append_log(follower.clone(), next_index, entries);
+                self.progress(&follower).sent(sent_upto);
+            }
+        }
+    }
+
+    // followers in probe state answer heartbeats too, so a lost probe is
+    // retried here
+    fn send_heartbeats(&mut self) {
+        for follower in self.followers().into_iter() {
+            let next_index = self.progress(&follower).next_index;
+            self.send_append_log(follower, next_index, vec![]);
+        }
+    }
+
+    fn batch_from(&self, offset: uint) -> Vec < AppendLogEntry < T > > {
+        let mut entries = vec![];
+        let mut bytes = 0u;
+        let mut offset = offset;
+
+        while offset < self.log_len() && entries.len() < self.append_limits.max_entries {
+            let entry = match self.entry_at(offset) {
+                Ok(entry) => entry,
+                Err(_) => break,
+            };
+
+            let size = entry.byte_size();
+            if !entries.is_empty() && bytes + size > self.append_limits.max_bytes {
+                break;
+            }
+
+            bytes += size;
+            entries.push(AppendLogEntry {
+                offset: offset,
+                term: self.term_at(offset).unwrap(),
+                entry: entry,
+            });
+            offset += 1;
+        }
+
+        entries
+    }
+
+    fn send_append_log(&mut self, follower: String, prev_log_index: uint, entries: Vec < AppendLogEntry < T > >) {
+        self.send(follower, AppendQuery(AppendLog {
+            term: self.term,
+            prev_log_index: prev_log_index,
+            prev_log_term: self.prev_log_term(prev_log_index),
+            committed_offset: self.commit,
+            entries: entries,
+        }));
+    }
+
+    fn election_handler(&mut self) {
+        let passed = self.clock.now() - self.last_append_log_seen_at;
+        let passed_since_heartbeat = self.clock.now() - self.last_sent_heartbeat;
+        let duration = self.randomized_election_timeout;
+        let heartbeat_timeout = self.heartbeat_interval;
+
+        match self.state {
+            Follower => {
+                // node removed from the cluster never stands for election
+                if passed > duration && self.cluster().contains(&self.my_host.host) {
+                    self.state = if self.pre_vote { PreCandidate } else { Candidate };
+                    self.votes = vec![];
+                    self.already_requested = false;
+                    self.last_append_log_seen_at = self.clock.now();
+                    self.randomize_election_timeout();
+                }
+            },
+
+            // node cut off from the cluster stays here and keeps its term, so
+            // it does not disrupt the leader once connectivity is back
+            PreCandidate => {
+                if passed > duration {
+                    self.state = Follower;
+                    self.votes = vec![];
+                    self.last_append_log_seen_at = self.clock.now();
+                    self.randomize_election_timeout();
+                }
+
+                if !self.already_requested && self.state == PreCandidate {
+                    self.already_requested = true;
+
+                    self.send(self.my_host.host.clone(), PreVoteResponse(self.term + 1, true));
+
+                    let last_log_index = self.log_len();
+                    let last_log_term = self.last_term();
+
+                    for host in self.followers().into_iter() {
+                        self.send(host, PreVote(self.term + 1, last_log_index, last_log_term));
+                    }
+                }
+            },
+
+            Candidate => {
+                if passed > duration {
+                    self.state = Follower;
+                    self.votes = vec![];
+                    self.last_append_log_seen_at = self.clock.now();
+                    self.randomize_election_timeout();
+                }
+
+                if !self.already_requested && self.state == Candidate {
+                    self.already_requested = true;
+                    self.term += 1;
+                    self.voted_for = Some(self.my_host.host.clone());
+                    self.metrics.election_started();
+
+                    self.send(self.my_host.host.clone(), Vote(self.term));
+
+                    let last_log_index = self.log_len();
+                    let last_log_term = self.last_term();
+
+                    let leadership_transfer = self.transfer_election;
+                    self.transfer_election = false;
+
+                    for host in self.followers().into_iter() {
+                        self.send(host, RequestVote(self.term, last_log_index, last_log_term, leadership_transfer));
+                    }
+                }
+            },
+
+            Leader => {
+                self.leader_host = None;
+
+                if passed_since_heartbeat > heartbeat_timeout {
+                    // lease is renewed with every heartbeat, and reads whose
+                    // round got lost are confirmed by the next one
+                    if self.read_consistency != ReadIndexRead || !self.pending_reads.is_empty() {
+                        self.read_round += 1;
+                        self.send_heartbeat_round();
+                    }
+
+                    self.send_heartbeats();
+                    self.last_sent_heartbeat = self.clock.now();
+                }
+            },
+        }
+    }
+
+    // Target is sent TimeoutNow once it has every entry of our log. Old
+    // leader learns about the new one from its higher term and steps down.
+    fn transfer_leadership_step(&mut self) {
+        if self.transfer.is_none() {
+            return;
+        }
+
+        let (target, started_at, timeout_now_sent) = {
+            let transfer = self.transfer.as_ref().unwrap();
+            (transfer.target.clone(), transfer.started_at, transfer.timeout_now_sent)
+        };
+
+        let leader_is_target = match self.leader_host {
+            Some(ref leader) => leader.host == target,
+            None => false,
+        };
+
+        let result = if self.state == Follower && leader_is_target {
+            Some(Transferred)
+        } else if self.clock.now() - started_at > self.election_timeout {
+            Some(TransferTimedOut)
+        } else {
+            None
+        };
+
+        match result {
+            Some(result) => {
+                self.transfer.take().unwrap().respond_to.send(result);
+                return;
+            },
+            None => (),
+        }
+
+        let log_len = self.log_len();
+        if self.state == Leader && !timeout_now_sent && self.progress(&target).match_index == log_len {
+            self.send(target, TimeoutNow(self.term));
+            self.transfer.as_mut().unwrap().timeout_now_sent = true;
+        }
+    }
+
+    // Appends the entry to the log of a leader, offset of the entry is known
+    // right away but it may still be lost until it is committed
+    pub fn propose(&mut self, entry: Entry < T >) -> Result < uint, ProposalError > {
+        if self.state != Leader {
+            return Err(NotLeader(self.leader_host.clone()));
+        }
+
+        // leadership is about to move to the transfer target
+        match self.transfer {
+            Some(ref transfer) => return Err(NotLeader(Some(NodeHost { host: transfer.target.clone() }))),
+            None => (),
+        }
+
+        // sessions expire by leader's time written into the log, so every
+        // replica expires them at the same entry
+        let timestamp = {
+            let now = self.clock.now();
+            now.sec * 1000 + (now.nsec / 1000000) as i64
+        };
+        let entry = match entry {
+            RegisterSessionEntry(_) => RegisterSessionEntry(timestamp),
+            SessionCommandEntry(request) => SessionCommandEntry(SessionCommand { timestamp: timestamp, ..request }),
+            entry => entry,
+        };
+
+        let term = self.term;
+        Ok(self.append_entry(entry, term))
+    }
+
+    // commit index never goes back, nor past the entries we have
+    fn commit_to(&mut self, offset: uint) {
+        let offset = cmp::min(offset, self.log_len());
+
+        if offset > self.commit {
+            self.commit = offset;
+        }
+    }
+
+    // proposals resolve once the entry is applied
+    fn resolve_proposals(&mut self) {
+        let applied = self.log.committed_offset();
+        let proposals = mem::replace(&mut self.proposals, vec![]);
+
+        for proposal in proposals.into_iter() {
+            let term_at = self.term_at(proposal.index);
+
+            let result = if term_at.is_some() && term_at != Some(proposal.term) {
+                Some(Err(EntryOverwritten))
+            } else if proposal.index < applied {
+                let response = Ok(self.responses.pop(&proposal.index));
+
+                match proposal.session {
+                    // duplicate resolves to where the command was applied
+                    // first, with the answer cached back then
+                    Some((client_id, sequence)) => match self.log.sessions().response(client_id, sequence) {
+                        Some(cached) => Some(Ok(Committed { index: cached.index, term: cached.term, applied: response })),
+                        None => Some(Err(SessionExpired)),
+                    },
+                    None => Some(Ok(Committed { index: proposal.index, term: proposal.term, applied: response })),
+                }
+            } else if self.apply_failures.contains_key(&proposal.index) {
+                let error = self.apply_failures.pop(&proposal.index).unwrap();
+                Some(Ok(Committed { index: proposal.index, term: proposal.term, applied: Err(error) }))
+            } else if self.term > proposal.term {
+                Some(Err(LeadershipLost))
+            } else {
+                None
+            };
+
+            match result {
+                Some(result) => {
+                    if result.is_ok() {
+                        self.metrics.proposal_committed(self.clock.now() - proposal.proposed_at);
+                    }
+
+                    proposal.respond_to.send_opt(result).ok();
+                },
+                None => self.proposals.push(proposal),
+            }
+        }
+
+        self.apply_failures.clear();
+        self.responses.clear();
+    }
+
+    // leader that went away or lost the proposal never answers
+    fn expire_forwarded_proposals(&mut self) {
+        let now = self.clock.now();
+        let expired: Vec < uint > = self.forwarded_proposals.iter()
+            .filter(|&(_, &(_, _, sent_at))| now - sent_at > self.election_timeout)
+            .map(|(&proposal_id, _)| proposal_id)
+            .collect();
+
+        for proposal_id in expired.iter() {
+            match self.forwarded_proposals.pop(proposal_id) {
+                Some((respond_to, _, _)) => {
+                    respond_to.send_opt(Err(ProposalDropped)).ok();
+                },
+                None => (),
+            }
+        }
+    }
+
+    // Read sent to a leader that did not answer within an election timeout
+    // is asked again. On another term or leader it is dropped, same as a read
+    // without a known leader, the read index may come from a stale leader.
+    fn resend_forwarded_reads(&mut self) {
+        let now = self.clock.now();
+        let term = self.term;
+        let leader = self.fetch_leader_host().map(|leader| leader.host);
+        let timeout = self.election_timeout;
+
+        let mut dropped = vec![];
+        let mut resent = vec![];
+
+        for (&read_id, read) in self.forwarded_reads.iter_mut() {
+            if read.term != term || leader.as_ref() != Some(&read.leader) {
+                dropped.push(read_id);
+            } else if now - read.sent_at > timeout {
+                read.sent_at = now;
+                resent.push((read.leader.clone(), read_id));
+            }
+        }
+
+        for read_id in dropped.iter() {
+            self.forwarded_reads.remove(read_id);
+        }
+
+        for (leader, read_id) in resent.into_iter() {
+            self.send(leader, ReadIndexRequest(read_id));
+        }
+    }
+
+    // ReadIndex: commit offset at the time of the read is safe to read at
+    // once a majority confirms we are still the leader
+    fn request_read_index(&mut self, reader: Reader < Q, Rcv >) {
+        self.read_round += 1;
+
+        let read_index = if self.committed_in_current_term() {
+            Some(self.commit)
+        } else {
+            None
+        };
+
+        self.pending_reads.push(PendingRead {
+            round: self.read_round,
+            read_index: read_index,
+            acks: vec![self.my_host.host.clone()],
+            reader: reader,
+        });
+
+        self.send_heartbeat_round();
+    }
+
+    fn send_heartbeat_round(&mut self) {
+        // rounds before the one a majority answered are not needed any more
+        let quorum_round = self.quorum_round();
+        let answered: Vec < uint > = self.round_sent_at.keys()
+            .filter(|&&round| round < quorum_round)
+            .map(|&round| round)
+            .collect();
+
+        for round in answered.iter() {
+            self.round_sent_at.remove(round);
+        }
+
+        self.round_sent_at.insert(self.read_round, self.clock.now());
+
+        for follower in self.followers().into_iter() {
+            self.send(follower, Heartbeat(self.term, self.read_round));
+        }
+    }
+
+    // Lease starts when the latest heartbeat round answered by a majority
+    // was sent: no other leader can be elected before followers' election
+    // timeout runs out after that. TimeoutNow of a leadership transfer lets
+    // the target win an election right away, so there is no lease meanwhile.
+    fn has_read_lease(&self) -> bool {
+        let drift_bound = match self.read_consistency {
+            LeaseRead(drift_bound) => drift_bound,
+            ReadIndexRead => return false,
+        };
+
+        if self.state != Leader || self.transfer.is_some() || !self.committed_in_current_term() {
+            return false;
+        }
+
+        match self.round_sent_at.find(&self.quorum_round()) {
+            Some(&sent_at) => self.clock.now() - sent_at < self.election_timeout - drift_bound,
+            None => false,
+        }
+    }
+
+    fn serve_reads(&mut self) {
+        if self.state != Leader {
+            self.pending_reads.clear();
+        } else if self.committed_in_current_term() {
+            let cluster = self.cluster();
+            let pending_reads = mem::replace(&mut self.pending_reads, vec![]);
+            let (confirmed, pending) = pending_reads.partition(|read| {
+                cluster.has_quorum(read.acks.as_slice())
+            });
+
+            self.pending_reads = pending;
+
+            for read in confirmed.into_iter() {
+                let read_index = read.read_index.unwrap_or(self.commit);
+
+                match read.reader {
+                    LocalRead(query, respond_to) => self.ready_reads.push(ReadyRead {
+                        read_index: read_index,
+                        query: query,
+                        respond_to: respond_to,
+                    }),
+                    ForwardedRead(follower, read_id) => {
+                        self.send(follower, ReadIndexResponse(read_id, read_index));
+                    },
+                }
+            }
+        }
+
+        // committed entries are handed to the state machine in order, so a
+        // query sent after they are applied sees their effects
+        let applied = self.log.committed_offset();
+        let ready_reads = mem::replace(&mut self.ready_reads, vec![]);
+        let (ready, waiting) = ready_reads.partition(|read| read.read_index <= applied);
+
+        self.ready_reads = waiting;
+
+        for read in ready.into_iter() {
+            self.log.query_persistance(read.query, read.respond_to);
+        }
+    }
+
+    // latest heartbeat round answered by a majority
+    fn quorum_round(&self) -> uint {
+        let mut acked_rounds = self.acked_rounds.clone();
+        acked_rounds.insert(self.my_host.host.clone(), self.read_round);
+
+        self.cluster().quorum_match_index(&acked_rounds)
+    }
+
+    fn committed_in_current_term(&self) -> bool {
+        self.commit > 0 && self.term_at(self.commit - 1) == Some(self.term)
+    }
+
+    // Leader cut off from the majority steps down, otherwise it would go on
+    // accepting proposals that can never be committed
+    fn check_quorum(&mut self) {
+        if !self.check_quorum || self.state != Leader {
+            return;
+        }
+
+        if self.clock.now() - self.last_quorum_check < self.election_timeout {
+            return;
+        }
+
+        self.last_quorum_check = self.clock.now();
+
+        let mut active = vec![self.my_host.host.clone()];
+
+        for follower in self.followers().into_iter() {
+            let progress = self.progress(&follower);
+
+            if progress.recent_active {
+                active.push(follower.clone());
+            }
+
+            progress.recent_active = false;
+        }
+
+        if !self.cluster().has_quorum(active.as_slice()) {
+            self.state = Follower;
+            self.progress.clear();
+            self.last_append_log_seen_at = self.clock.now();
+        }
+    }
+
+    fn report_state(&mut self) {
+        if self.state == self.reported_state {
+            return;
+        }
+
+        self.metrics.state_changed(self.reported_state, self.state);
+        self.reported_state = self.state;
+
+        let state = self.state;
+        self.observers.retain(|observer| observer.send_opt(state).is_ok());
+    }
+
+    // term and, on a leader, how far behind every follower is
+    fn report_metrics(&mut self) {
+        if self.term != self.reported_term {
+            self.reported_term = self.term;
+            self.metrics.term_changed(self.term);
+        }
+
+        if self.state != Leader {
+            return;
+        }
+
+        let len = self.log_len();
+
+        for follower in self.followers().into_iter() {
+            let match_index = self.progress(&follower).match_index;
+            self.metrics.peer_lag(follower.as_slice(), len - cmp::min(match_index, len));
+        }
+    }
+
+    // leader of the new term is not known until it shows up
+    fn step_down(&mut self, term: uint) {
+        self.term = term;
+        self.voted_for = None;
+        self.votes = vec![];
+        self.state = Follower;
+        self.leader_host = None;
+    }
+
+    fn heard_from_leader_recently(&self) -> bool {
+        match self.state {
+            Leader => true,
+            Follower => self.leader_host.is_some() &&
+                self.clock.now() - self.last_append_log_seen_at < self.election_timeout,
+            PreCandidate | Candidate => false,
+        }
+    }
+
+    fn randomize_election_timeout(&mut self) {
+        let min = self.election_timeout.num_microseconds().unwrap_or(0);
+        let max = self.max_election_timeout.num_microseconds().unwrap_or(0);
+        let timeout = if max > min { self.rng.gen_range(min, max) } else { min };
+
+        self.randomized_election_timeout = Duration::microseconds(timeout);
+    }
+
+    // term, vote and commit offset have to reach the storage before node
+    // answers anybody, otherwise a restarted node may vote twice in a term
+    fn hard_state(&self) -> HardState {
+        HardState {
+            term: self.term,
+            voted_for: self.voted_for.clone(),
+            commit: self.commit,
+        }
+    }
+
+    fn prev_log_term(&self, prev_log_index: uint) -> uint {
+        if prev_log_index == 0 {
+            return 0;
+        }
+
+        match self.term_at(prev_log_index - 1) {
+            Some(term) => term,
+            None => 0,
+        }
+    }
+
+    // Candidate's log is at least as up-to-date as ours when its last entry
+    // has a later term, or the same term and the log is not shorter
+    fn is_log_up_to_date(&self, last_log_index: uint, last_log_term: uint) -> bool {
+        let my_last_log_term = self.last_term();
+
+        last_log_term > my_last_log_term ||
+            (last_log_term == my_last_log_term && last_log_index >= self.log_len())
+    }
+
+    fn fetch_leader_host(&self) -> Option < NodeHost > {
+        match self.state {
+            Leader => None,
+            _ => self.leader_host.clone(),
+        }
+    }
+
+    // Log length stored on a majority of the cluster, leader included. The
+    // leader counts the entries the driver stored only.
+    fn quorum_match_index(&mut self) -> uint {
+        let mut match_index = HashMap::new();
+        match_index.insert(self.my_host.host.clone(), self.unstable_offset);
+
+        for follower in self.followers().into_iter() {
+            let matched = self.progress(&follower).match_index;
+            match_index.insert(follower, matched);
+        }
+
+        self.cluster().quorum_match_index(&match_index)
+    }
+
+    // Only entries of the current term are committed by counting replicas,
+    // earlier ones get committed along with them. Otherwise an entry of an
+    // old term stored on a majority could still be overwritten by a newer
+    // leader (Figure 8 in the Raft paper).
+    fn autocommit(&mut self) {
+        if self.state != Leader {
+            return;
+        }
+
+        let quorum_match_index = self.quorum_match_index();
+
+        if quorum_match_index <= self.commit ||
+            self.term_at(quorum_match_index - 1) != Some(self.term) {
+            return;
+        }
+
+        self.commit_to(quorum_match_index);
+        self.send_heartbeats();
+    }
+}
+
+// (client_id, sequence) of a session command
+fn session_of < T > (entry: &Entry < T >) -> Option < (uint, uint) > {
+    match *entry {
+        SessionCommandEntry(ref request) => Some((request.client_id, request.sequence)),
+        _ => None,
+    }
+}
+
+fn drop_prefix < X >(items: &mut Vec < X >, count: uint) {
+    let rest = mem::replace(items, vec![]);
+    items.extend(rest.into_iter().skip(count));
+}
@@ -1,10 +1,42 @@
 use std::io;
-use std::io::timer::sleep;
-use std::time::duration::Duration;
-use std::comm::Disconnected;
+use std::io::{MemWriter, BufReader};
+use std::mem;
+use std::default::Default;
+
+use super::membership::Configuration;
+use super::session::{Sessions, SessionCommand, FreshRequest, DuplicateRequest, UnknownSession, encode_response, decode_response};
 
 pub trait Committable {
+    // approximate size of the entry on the wire, used to limit AppendLog
+    // batches; types owning heap data should report it here
+    fn byte_size(&self) -> uint {
+        mem::size_of_val(self)
+    }
+}
 
+// Entry of the replicated log: a command for the state machine or a change
+// of the cluster configuration
+#[deriving(Encodable, Decodable, Show, Clone, PartialEq)]
+pub enum Entry < T > {
+    CommandEntry(T),
+    ConfigurationEntry(Configuration),
+
+    // appended by a new leader, so entries of previous terms get committed
+    NoopEntry,
+
+    // RegisterSessionEntry(timestamp): offset of the entry is the client id
+    RegisterSessionEntry(i64),
+    SessionCommandEntry(SessionCommand < T >),
+}
+
+impl < T: Committable > Entry < T > {
+    pub fn byte_size(&self) -> uint {
+        match *self {
+            CommandEntry(ref command) => command.byte_size(),
+            SessionCommandEntry(ref request) => request.command.byte_size() + mem::size_of_val(request),
+            _ => mem::size_of_val(self),
+        }
+    }
 }
 
 pub trait Receivable {
@@ -15,11 +47,33 @@ pub trait Queriable {
 
 }
 
-pub trait LogPersistence < T: Committable > {
-    fn commit(&self, entry: T) -> io::IoResult < () >;
+// commit answers what the state machine returns for the command, it is
+// handed to the proposer and cached for retries of session commands
+pub trait LogPersistence < T: Committable, R: Receivable > {
+    fn commit(&self, entry: T) -> io::IoResult < R >;
+}
+
+pub trait PersistenceQuery < Q: Queriable, R: Receivable > {
+    fn query(&self, query: Q, respond_to: Sender < R >);
 }
 
-#[deriving(Show, PartialEq)]
+pub trait SnapshotPersistence {
+    fn snapshot(&self) -> io::IoResult < Vec < u8 > >;
+    fn restore(&self, data: &[u8]) -> io::IoResult < () >;
+}
+
+// Snapshot of the state machine with all entries before
+// last_included_index applied
+#[deriving(Encodable, Decodable, Show, Clone, PartialEq)]
+pub struct Snapshot {
+    pub last_included_index: uint,
+    pub last_included_term: uint,
+    pub configuration: Option < Configuration >,
+    pub sessions: Sessions,
+    pub data: Vec < u8 >,
+}
+
+#[deriving(Encodable, Decodable, Clone, Show, PartialEq)]
 pub enum DefaultReceivable {
     ReceivableInt(int),
 }
@@ -38,30 +92,50 @@ pub trait ReplicationLog < T: Committable, Q: Queriable, R: Receivable > {
     fn new() -> Self;
 
     fn len(&self) -> uint;
+    fn first_offset(&self) -> uint;
+    fn last_term(&self) -> uint;
+    fn term_at(&self, offset: uint) -> Option < uint >;
+    fn entry_at(&self, offset: uint) -> io::IoResult < Entry < T > >;
     fn committed_offset(&self) -> uint;
     fn commit_upto(&mut self, new_committed_offset: uint) -> io::IoResult < () >;
     fn discard_downto(&mut self, new_len: uint) -> io::IoResult < () >;
 
-    fn autocommit_if_safe(&mut self, majority_size: uint);
-
-    fn enqueue(&mut self, entry: T) -> io::IoResult < uint >;
-    fn persisted(&mut self, offset: uint, node: String) -> io::IoResult < uint >;
+    fn enqueue(&mut self, entry: Entry < T >, term: uint) -> io::IoResult < uint >;
     fn query_persistance(&mut self, query: Q, respond_to: Sender < R >);
+
+    // answers of the state machine to commands committed since the previous
+    // call, by offset; a retried session command gets the cached answer of
+    // its first application
+    fn take_responses(&mut self) -> Vec < (uint, R) >;
+
+    // client sessions as of the committed offset
+    fn sessions(&self) -> &Sessions;
+
+    fn snapshot(&self) -> Option < Snapshot >;
+    fn compact(&mut self) -> io::IoResult < () >;
+    fn install_snapshot(&mut self, snapshot: Snapshot) -> io::IoResult < () >;
 }
 
-#[deriving(Clone, Show, PartialEq)]
+#[deriving(Encodable, Decodable, Clone, Show, PartialEq)]
 pub enum DefaultCommand {
     TestSet(int),
     TestAdd(int),
 }
 
-#[deriving(Clone, Show, PartialEq)]
+#[deriving(Encodable, Decodable, Clone, Show, PartialEq)]
 pub struct DefaultCommandContainer {
     pub command: DefaultCommand,
 }
 
+enum PersistenceMessage {
+    Apply(DefaultCommandContainer, Sender < DefaultReceivable >),
+    TakeSnapshot(Sender < int >),
+    RestoreSnapshot(int),
+    ReadValue(Sender < DefaultReceivable >),
+}
+
 pub struct DefaultPersistence {
-    tx: Sender < DefaultCommandContainer >,
+    tx: Sender < PersistenceMessage >,
     pub rx: Receiver < int >,
 }
 
@@ -79,20 +153,30 @@ impl DefaultPersistence {
             let mut value = 0i;
 
             loop {
-                match rx.try_recv() {
-                    Ok(command) => {
+                match rx.recv_opt() {
+                    Ok(Apply(command, respond_to)) => {
                         match command {
                             DefaultCommandContainer{ command: TestSet(x) } => value = x,
                             DefaultCommandContainer{ command: TestAdd(dx) } => value += dx,
                         }
 
                         value_tx.send(value);
+                        respond_to.send_opt(ReceivableInt(value)).ok();
                     }
-                    Err(Disconnected) => break,
-                    _ => ()
+                    Ok(TakeSnapshot(respond_to)) => respond_to.send(value),
+                    // answered after every command applied before it
+                    Ok(ReadValue(respond_to)) => {
+                        match respond_to.send_opt(ReceivableInt(value)) {
+                            Err(_) => (),
+                            _ => (),
+                        }
+                    },
+                    Ok(RestoreSnapshot(snapshot_value)) => {
+                        value = snapshot_value;
+                        value_tx.send(value);
+                    }
+                    Err(_) => break,
                 }
-
-                sleep(Duration::milliseconds(2));
             }
         });
 
@@ -100,32 +184,55 @@ impl DefaultPersistence {
     }
 }
 
+impl Default for DefaultPersistence {
+    fn default() -> DefaultPersistence {
+        DefaultPersistence::start()
+    }
+}
+
 pub struct DefaultReplicationLog {
-    log: Vec < DefaultCommandContainer >,
-    persisted_by: Vec < DefaultPersistedBy >,
+    log: Vec < Entry < DefaultCommandContainer > >,
+    terms: Vec < uint >,
+    first_offset: uint,
+    last_snapshot: Option < Snapshot >,
     offset: uint,
+    responses: Vec < (uint, DefaultReceivable) >,
+    pub sessions: Sessions,
     pub persistence: DefaultPersistence,
 }
 
-struct DefaultPersistedBy {
-    node_list: Vec < String >,
-}
-
 impl Committable for DefaultCommandContainer {
 
 }
 
-impl LogPersistence < DefaultCommandContainer > for DefaultPersistence {
-    fn commit(&self, entry: DefaultCommandContainer) -> io::IoResult < () > {
-        self.tx.send(entry);
-        Ok(())
+impl LogPersistence < DefaultCommandContainer, DefaultReceivable > for DefaultPersistence {
+    fn commit(&self, entry: DefaultCommandContainer) -> io::IoResult < DefaultReceivable > {
+        let (tx, rx) = channel();
+        self.tx.send(Apply(entry, tx));
+        Ok(rx.recv())
     }
 }
 
-impl DefaultReplicationLog {
-    fn safe_to_commit(&mut self, offset: uint, majority_size: uint) -> bool {
-        self.persisted_by.len() > offset &&
-            self.persisted_by[offset].node_list.len() >= majority_size
+impl PersistenceQuery < DefaultQuery, DefaultReceivable > for DefaultPersistence {
+    fn query(&self, _: DefaultQuery, respond_to: Sender < DefaultReceivable >) {
+        self.tx.send(ReadValue(respond_to));
+    }
+}
+
+impl SnapshotPersistence for DefaultPersistence {
+    fn snapshot(&self) -> io::IoResult < Vec < u8 > > {
+        let (tx, rx) = channel();
+        self.tx.send(TakeSnapshot(tx));
+
+        let mut data = MemWriter::new();
+        try!(data.write_be_i64(rx.recv() as i64));
+        Ok(data.unwrap())
+    }
+
+    fn restore(&self, data: &[u8]) -> io::IoResult < () > {
+        let value = try!(BufReader::new(data).read_be_i64());
+        self.tx.send(RestoreSnapshot(value as int));
+        Ok(())
     }
 }
 
@@ -133,14 +240,51 @@ impl ReplicationLog < DefaultCommandContainer, DefaultQuery, DefaultReceivable >
     fn new() -> DefaultReplicationLog {
         DefaultReplicationLog {
             log: vec![],
-            persisted_by: vec![],
+            terms: vec![],
+            first_offset: 0,
+            last_snapshot: None,
             offset: 0,
+            responses: vec![],
+            sessions: Default::default(),
             persistence: DefaultPersistence::start(),
         }
     }
 
     fn len(&self) -> uint {
-        self.log.len()
+        self.first_offset + self.log.len()
+    }
+
+    fn first_offset(&self) -> uint {
+        self.first_offset
+    }
+
+    fn last_term(&self) -> uint {
+        match self.terms.last() {
+            Some(&term) => term,
+            None => match self.last_snapshot {
+                Some(ref snapshot) => snapshot.last_included_term,
+                None => 0,
+            },
+        }
+    }
+
+    fn term_at(&self, offset: uint) -> Option < uint > {
+        if offset >= self.first_offset && offset < self.len() {
+            return Some(self.terms[offset - self.first_offset]);
+        }
+
+        match self.last_snapshot {
+            Some(ref snapshot) if offset + 1 == snapshot.last_included_index => Some(snapshot.last_included_term),
+            _ => None,
+        }
+    }
+
+    fn entry_at(&self, offset: uint) -> io::IoResult < Entry < DefaultCommandContainer > > {
+        if offset >= self.first_offset && offset < self.len() {
+            Ok(self.log[offset - self.first_offset].clone())
+        } else {
+            Err(io::standard_error(io::InvalidInput))
+        }
     }
 
     fn committed_offset(&self) -> uint {
@@ -149,7 +293,33 @@ impl ReplicationLog < DefaultCommandContainer, DefaultQuery, DefaultReceivable >
 
     fn commit_upto(&mut self, new_committed_offset: uint) -> io::IoResult < () > {
         while self.offset < new_committed_offset && self.offset < self.len() {
-            self.persistence.commit(self.log[self.offset]);
+            let index = self.offset - self.first_offset;
+
+            match self.log[index] {
+                CommandEntry(ref command) => {
+                    let response = try!(self.persistence.commit(command.clone()));
+                    self.responses.push((self.offset, response));
+                },
+                RegisterSessionEntry(timestamp) => self.sessions.register(self.offset, timestamp),
+                SessionCommandEntry(ref request) => match self.sessions.check(request.client_id, request.sequence, request.timestamp) {
+                    FreshRequest => {
+                        let response = try!(self.persistence.commit(request.command.clone()));
+                        self.sessions.applied(request.client_id, request.sequence, self.offset, self.terms[index], encode_response(&response));
+                        self.responses.push((self.offset, response));
+                    },
+                    DuplicateRequest => {
+                        let cached = self.sessions.response(request.client_id, request.sequence)
+                            .and_then(|cached| decode_response(cached.response.as_slice()));
+
+                        match cached {
+                            Some(response) => self.responses.push((self.offset, response)),
+                            None => (),
+                        }
+                    },
+                    UnknownSession => (),
+                },
+                _ => (),
+            }
             self.offset += 1;
         }
 
@@ -159,36 +329,95 @@ impl ReplicationLog < DefaultCommandContainer, DefaultQuery, DefaultReceivable >
     fn discard_downto(&mut self, new_len: uint) -> io::IoResult < () > {
         while self.len() > new_len && self.len() > self.offset {
             self.log.pop();
+            self.terms.pop();
         }
         Ok(())
     }
 
-    fn autocommit_if_safe(&mut self, majority_size: uint) {
-        let target_offset = self.committed_offset();
-        let safe = self.safe_to_commit(target_offset, majority_size);
+    fn enqueue(&mut self, entry: Entry < DefaultCommandContainer >, term: uint) -> io::IoResult < uint > {
+        self.log.push(entry);
+        self.terms.push(term);
+        Ok(self.len() - 1)
+    }
 
-        if safe {
-            self.commit_upto(target_offset + 1);
-        }
+    fn query_persistance(&mut self, query: DefaultQuery, respond_to: Sender < DefaultReceivable >) {
+        self.persistence.query(query, respond_to);
     }
 
-    fn enqueue(&mut self, entry: DefaultCommandContainer) -> io::IoResult < uint > {
-        self.log.push(entry);
-        self.persisted_by.push(DefaultPersistedBy { node_list: vec![] });
-        Ok(self.log.len() - 1)
+    fn take_responses(&mut self) -> Vec < (uint, DefaultReceivable) > {
+        mem::replace(&mut self.responses, vec![])
+    }
+
+    fn sessions(&self) -> &Sessions {
+        &self.sessions
+    }
+
+    fn snapshot(&self) -> Option < Snapshot > {
+        self.last_snapshot.clone()
     }
 
-    fn persisted(&mut self, offset: uint, host: String) -> io::IoResult < uint > {
-        if !self.persisted_by[offset].node_list.contains(&host) {
-            self.persisted_by[offset].node_list.push(host);
+    fn compact(&mut self) -> io::IoResult < () > {
+        if self.offset <= self.first_offset {
+            return Ok(());
+        }
+
+        let count = self.offset - self.first_offset;
+
+        // configuration in force at the snapshot point is kept with it
+        let mut configuration = self.last_snapshot.as_ref().and_then(|snapshot| snapshot.configuration.clone());
+        for entry in self.log.iter().take(count) {
+            match *entry {
+                ConfigurationEntry(ref x) => configuration = Some(x.clone()),
+                _ => (),
+            }
         }
-        Ok(offset)
+
+        let snapshot = Snapshot {
+            last_included_index: self.offset,
+            last_included_term: self.term_at(self.offset - 1).unwrap(),
+            configuration: configuration,
+            sessions: self.sessions.clone(),
+            data: try!(self.persistence.snapshot()),
+        };
+
+        drop_prefix(&mut self.log, count);
+        drop_prefix(&mut self.terms, count);
+
+        self.first_offset = self.offset;
+        self.last_snapshot = Some(snapshot);
+
+        Ok(())
     }
 
-    fn query_persistance(&mut self, query: DefaultQuery, respond_to: Sender < DefaultReceivable >) {
-        match self.persistence.rx.try_recv() {
-            Ok(value) => respond_to.send(ReceivableInt(value)),
-            _ => (),
+    fn install_snapshot(&mut self, snapshot: Snapshot) -> io::IoResult < () > {
+        let index = snapshot.last_included_index;
+
+        if index <= self.offset {
+            return Ok(());
+        }
+
+        try!(self.persistence.restore(snapshot.data.as_slice()));
+
+        // entries following the snapshot are kept when the log agrees with it
+        if self.term_at(index - 1) == Some(snapshot.last_included_term) {
+            let count = index - self.first_offset;
+            drop_prefix(&mut self.log, count);
+            drop_prefix(&mut self.terms, count);
+        } else {
+            self.log.clear();
+            self.terms.clear();
         }
+
+        self.first_offset = index;
+        self.offset = index;
+        self.sessions = snapshot.sessions.clone();
+        self.last_snapshot = Some(snapshot);
+
+        Ok(())
     }
 }
+
+fn drop_prefix < X >(items: &mut Vec < X >, count: uint) {
+    let rest = mem::replace(items, vec![]);
+    items.extend(rest.into_iter().skip(count));
+}
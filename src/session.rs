@@ -0,0 +1,161 @@
+use std::cmp;
+use std::str;
+use std::default::Default;
+use std::io::IoError;
+
+use serialize::{Encodable, Decodable, json};
+
+// sessions idle for an hour of leader's time are forgotten
+static DEFAULT_SESSION_TIMEOUT: i64 = 60 * 60 * 1000;
+
+// responses kept for each session, so a client may have that many commands
+// in flight; past it the smallest sequence is forgotten and every sequence
+// up to it counts as applied
+static MAX_CACHED_RESPONSES: uint = 16;
+
+// Command of a registered client. Client numbers its commands, a command
+// with a sequence that was applied already is not applied again. Sequences
+// may be applied in any order as long as the client keeps no more than
+// MAX_CACHED_RESPONSES commands in flight.
+#[deriving(Encodable, Decodable, Show, Clone, PartialEq)]
+pub struct SessionCommand < T > {
+    pub client_id: uint,
+    pub sequence: uint,
+    // leader's clock in milliseconds when the entry was appended
+    pub timestamp: i64,
+    pub command: T,
+}
+
+#[deriving(Clone, Show, PartialEq)]
+pub enum SessionStatus {
+    FreshRequest,
+    DuplicateRequest,
+    // never registered or expired
+    UnknownSession,
+}
+
+// Offset and term the command was applied at and what the state machine
+// answered, encoded with encode_response
+#[deriving(Encodable, Decodable, Show, Clone, PartialEq)]
+pub struct CachedResponse {
+    pub sequence: uint,
+    pub index: uint,
+    pub term: uint,
+    pub response: Vec < u8 >,
+}
+
+#[deriving(Encodable, Decodable, Show, Clone, PartialEq)]
+pub struct Session {
+    pub client_id: uint,
+    // sequences up to this one are applied, their responses are forgotten
+    pub forgotten_sequence: uint,
+    pub last_active: i64,
+    // applied sequences above forgotten_sequence
+    pub responses: Vec < CachedResponse >,
+}
+
+// Client sessions as of some point in the log. Every replica sees the same
+// entries with the same timestamps, so every replica expires the same
+// sessions at the same point and nothing depends on local clocks.
+#[deriving(Encodable, Decodable, Show, Clone, PartialEq)]
+pub struct Sessions {
+    // milliseconds
+    pub timeout: i64,
+    sessions: Vec < Session >,
+}
+
+impl Sessions {
+    pub fn new(timeout: i64) -> Sessions {
+        Sessions {
+            timeout: timeout,
+            sessions: vec![],
+        }
+    }
+
+    pub fn contains(&self, client_id: uint) -> bool {
+        self.sessions.iter().any(|session| session.client_id == client_id)
+    }
+
+    // client id is the offset of the registration entry
+    pub fn register(&mut self, client_id: uint, timestamp: i64) {
+        self.expire(timestamp);
+
+        if !self.contains(client_id) {
+            self.sessions.push(Session {
+                client_id: client_id,
+                forgotten_sequence: 0,
+                last_active: timestamp,
+                responses: vec![],
+            });
+        }
+    }
+
+    // Whether the command should be applied. Nothing is recorded until
+    // applied is called, so a command that failed to apply is retried.
+    pub fn check(&mut self, client_id: uint, sequence: uint, timestamp: i64) -> SessionStatus {
+        self.expire(timestamp);
+
+        match self.sessions.iter_mut().find(|session| session.client_id == client_id) {
+            Some(session) => {
+                session.last_active = cmp::max(session.last_active, timestamp);
+
+                if sequence <= session.forgotten_sequence || session.responses.iter().any(|response| response.sequence == sequence) {
+                    DuplicateRequest
+                } else {
+                    FreshRequest
+                }
+            },
+            None => UnknownSession,
+        }
+    }
+
+    pub fn applied(&mut self, client_id: uint, sequence: uint, index: uint, term: uint, response: Vec < u8 >) {
+        match self.sessions.iter_mut().find(|session| session.client_id == client_id) {
+            Some(session) => {
+                session.responses.push(CachedResponse {
+                    sequence: sequence,
+                    index: index,
+                    term: term,
+                    response: response,
+                });
+
+                if session.responses.len() > MAX_CACHED_RESPONSES {
+                    let (oldest, _) = session.responses.iter().enumerate()
+                        .min_by(|&(_, response)| response.sequence)
+                        .unwrap();
+                    let forgotten = session.responses.remove(oldest).unwrap();
+                    session.forgotten_sequence = cmp::max(session.forgotten_sequence, forgotten.sequence);
+                }
+            },
+            None => (),
+        }
+    }
+
+    pub fn response(&self, client_id: uint, sequence: uint) -> Option < CachedResponse > {
+        self.sessions.iter()
+            .find(|session| session.client_id == client_id)
+            .and_then(|session| session.responses.iter().find(|response| response.sequence == sequence))
+            .map(|response| response.clone())
+    }
+
+    fn expire(&mut self, now: i64) {
+        let timeout = self.timeout;
+        self.sessions.retain(|session| session.last_active + timeout >= now);
+    }
+}
+
+// Responses are cached as json, so sessions do not depend on the state
+// machine and travel in snapshots as they are
+pub fn encode_response < 'a, R: Encodable < json::Encoder < 'a >, IoError > >(response: &R) -> Vec < u8 > {
+    json::encode(response).into_bytes()
+}
+
+pub fn decode_response < R: Decodable < json::Decoder, json::DecoderError > >(bytes: &[u8]) -> Option < R > {
+    str::from_utf8(bytes).and_then(|text| json::decode(text).ok())
+}
+
+impl Default for Sessions {
+    fn default() -> Sessions {
+        Sessions::new(DEFAULT_SESSION_TIMEOUT)
+    }
+}
@@ -0,0 +1,223 @@
+extern crate time;
+
+use std::fmt::Show;
+use std::{cmp, mem};
+use std::rand::{Rng, SeedableRng, XorShiftRng};
+use std::sync::{Arc, Mutex};
+use std::time::duration::Duration;
+
+use super::clock::{Clock, SystemClock};
+use super::intercommunication::{Intercommunication, Endpoint, Package, Pack};
+use super::replication::Committable;
+
+struct NetworkConditions {
+    drop_rate: f64,
+    duplicate_rate: f64,
+    min_latency: Duration,
+    max_latency: Duration,
+
+    // (from, to) pairs that can not reach each other
+    blocked: Vec < (String, String) >,
+}
+
+// Handle to the conditions of a simulated network, kept by tests after the
+// intercommunication itself was handed to the router
+#[deriving(Clone)]
+pub struct SimulatedNetwork {
+    conditions: Arc < Mutex < NetworkConditions > >,
+}
+
+impl SimulatedNetwork {
+    fn new() -> SimulatedNetwork {
+        SimulatedNetwork {
+            conditions: Arc::new(Mutex::new(NetworkConditions {
+                drop_rate: 0.0,
+                duplicate_rate: 0.0,
+                min_latency: Duration::zero(),
+                max_latency: Duration::zero(),
+                blocked: vec![],
+            })),
+        }
+    }
+
+    // share of packages lost, from 0.0 to 1.0
+    pub fn set_drop_rate(&self, rate: f64) {
+        self.conditions.lock().drop_rate = rate;
+    }
+
+    // share of packages delivered twice, from 0.0 to 1.0
+    pub fn set_duplicate_rate(&self, rate: f64) {
+        self.conditions.lock().duplicate_rate = rate;
+    }
+
+    // Every package gets its own latency from the range, so packages are
+    // reordered when the range is wide enough
+    pub fn set_latency(&self, min: Duration, max: Duration) {
+        let mut conditions = self.conditions.lock();
+        conditions.min_latency = min;
+        conditions.max_latency = max;
+    }
+
+    // packages from one host do not reach the other one, the other way
+    // around still works
+    pub fn block(&self, from: &str, to: &str) {
+        self.conditions.lock().blocked.push((from.to_string(), to.to_string()));
+    }
+
+    // hosts on one side do not reach hosts on the other side and back
+    pub fn partition(&self, side: &[&str], other_side: &[&str]) {
+        for from in side.iter() {
+            for to in other_side.iter() {
+                self.block(*from, *to);
+                self.block(*to, *from);
+            }
+        }
+    }
+
+    // removes every partition and block
+    pub fn heal(&self) {
+        self.conditions.lock().blocked.clear();
+    }
+
+    fn is_blocked(&self, from: &String, to: &String) -> bool {
+        self.conditions.lock().blocked.iter().any(|&(ref x, ref y)| x == from && y == to)
+    }
+}
+
+struct InFlight < T: Committable + Send > {
+    deliver_at: time::Timespec,
+    recipient: String,
+    package: Package < T >,
+}
+
+// Network with faults that wraps any intercommunication. Every decision is
+// taken by a random generator with a given seed, drawn for packages in the
+// order they are sent. A single sender on a manual clock gets the same
+// faults from the same seed; packages of concurrent node tasks arrive in a
+// different order every run, so for them a seed does not replay a run.
+pub struct SimulatedIntercommunication < T: Committable + Send, I > {
+    comm: I,
+    rng: XorShiftRng,
+    in_flight: Vec < InFlight < T > >,
+    clock: Box < Clock + Send >,
+
+    pub seed: u32,
+    pub network: SimulatedNetwork,
+}
+
+impl < T: Committable + Send + Show + Clone, I: Intercommunication < T > > SimulatedIntercommunication < T, I > {
+    pub fn with_seed(seed: u32) -> SimulatedIntercommunication < T, I > {
+        SimulatedIntercommunication {
+            comm: Intercommunication::new(),
+            rng: SeedableRng::from_seed([seed, 0x193a6754, 0xa8a7d469, 0x97830e05]),
+            in_flight: vec![],
+            clock: box SystemClock as Box < Clock + Send >,
+            seed: seed,
+            network: SimulatedNetwork::new(),
+        }
+    }
+
+    pub fn set_clock < C: Clock + Send + 'static >(&mut self, clock: C) {
+        self.clock = box clock as Box < Clock + Send >;
+    }
+
+    // hands packages that arrived to the wrapped intercommunication, those
+    // sent to a host partitioned away in the meantime are lost
+    fn deliver(&mut self) {
+        let now = self.clock.now();
+        let in_flight = mem::replace(&mut self.in_flight, vec![]);
+        let (arrived, in_flight) = in_flight.partition(|x| x.deliver_at <= now);
+
+        self.in_flight = in_flight;
+
+        for x in arrived.into_iter() {
+            let blocked = match x.package {
+                Pack(ref from, ref to, _) => self.network.is_blocked(from, to),
+            };
+
+            if !blocked {
+                self.comm.send(x.recipient, x.package);
+            }
+        }
+    }
+
+    fn latency(&mut self) -> Duration {
+        let conditions = self.network.conditions.lock();
+        let min = conditions.min_latency.num_microseconds().unwrap_or(0);
+        let max = conditions.max_latency.num_microseconds().unwrap_or(0);
+
+        if max <= min {
+            return conditions.min_latency;
+        }
+
+        Duration::microseconds(self.rng.gen_range(min, max + 1))
+    }
+}
+
+impl < T: Committable + Send + Show + Clone, I: Intercommunication < T > > Intercommunication < T > for SimulatedIntercommunication < T, I > {
+    fn new() -> SimulatedIntercommunication < T, I > {
+        SimulatedIntercommunication::with_seed(0)
+    }
+
+    fn register(&mut self, host: String) -> Endpoint < T > {
+        self.comm.register(host)
+    }
+
+    fn receive(&mut self) -> Option < Package < T > > {
+        self.deliver();
+        self.comm.receive()
+    }
+
+    // wakes up in time to deliver the next package in flight
+    fn receive_timeout(&mut self, timeout: Duration) -> Option < Package < T > > {
+        self.deliver();
+
+        let now = self.clock.now();
+        let timeout = match self.in_flight.iter().map(|x| x.deliver_at).min() {
+            Some(deliver_at) => cmp::max(cmp::min(deliver_at - now, timeout), Duration::zero()),
+            None => timeout,
+        };
+
+        match self.comm.receive_timeout(timeout) {
+            Some(package) => Some(package),
+            None => self.receive(),
+        }
+    }
+
+    fn send(&mut self, recipient: String, package: Package < T >) {
+        let blocked = match package {
+            Pack(ref from, ref to, _) => self.network.is_blocked(from, to),
+        };
+
+        // random numbers are drawn for every package, so the sequence of
+        // decisions depends on the seed and the order of packages only
+        let (drop_rate, duplicate_rate) = {
+            let conditions = self.network.conditions.lock();
+            (conditions.drop_rate, conditions.duplicate_rate)
+        };
+        let dropped = self.rng.gen::< f64 >() < drop_rate;
+        let duplicated = self.rng.gen::< f64 >() < duplicate_rate;
+
+        if blocked || dropped {
+            return;
+        }
+
+        let copies = if duplicated { 2u } else { 1u };
+
+        for _ in range(0, copies) {
+            let deliver_at = self.clock.now() + self.latency();
+
+            self.in_flight.push(InFlight {
+                deliver_at: deliver_at,
+                recipient: recipient.clone(),
+                package: package.clone(),
+            });
+        }
+
+        self.deliver();
+    }
+
+    fn is_debug(&self) -> bool {
+        self.comm.is_debug()
+    }
+}
@@ -0,0 +1,108 @@
+use std::io;
+use std::io::{File, Truncate, Write};
+use std::io::fs;
+use std::io::fs::PathExtensions;
+
+use std::sync::{Arc, Mutex};
+
+use serialize::json;
+
+#[deriving(Encodable, Decodable, Show, Clone, PartialEq)]
+pub struct HardState {
+    pub term: uint,
+    pub voted_for: Option < String >,
+    pub commit: uint,
+}
+
+impl HardState {
+    pub fn new() -> HardState {
+        HardState {
+            term: 0,
+            voted_for: None,
+            commit: 0,
+        }
+    }
+}
+
+pub trait HardStateStorage {
+    fn load(&mut self) -> io::IoResult < HardState >;
+    fn save(&mut self, state: &HardState) -> io::IoResult < () >;
+}
+
+pub struct FileHardStateStorage {
+    path: Path,
+}
+
+impl FileHardStateStorage {
+    pub fn new(path: &Path) -> FileHardStateStorage {
+        FileHardStateStorage { path: path.clone() }
+    }
+}
+
+impl HardStateStorage for FileHardStateStorage {
+    fn load(&mut self) -> io::IoResult < HardState > {
+        if !self.path.exists() {
+            return Ok(HardState::new());
+        }
+
+        let contents = try!(try!(File::open(&self.path)).read_to_string());
+
+        match json::decode(contents.as_slice()) {
+            Ok(state) => Ok(state),
+            Err(_) => Err(io::IoError {
+                kind: io::InvalidInput,
+                desc: "malformed hard state file",
+                detail: Some(self.path.display().to_string()),
+            }),
+        }
+    }
+
+    // written to a temporary file first and renamed over the old one, so a
+    // crash leaves either the previous or the new state, never a mix. The
+    // rename itself is durable only once the directory is synced, until then
+    // a crash may bring back the old term and vote.
+    fn save(&mut self, state: &HardState) -> io::IoResult < () > {
+        let tmp_path = self.path.with_extension("tmp");
+
+        {
+            let mut file = try!(File::open_mode(&tmp_path, Truncate, Write));
+            try!(file.write_str(json::encode(state).as_slice()));
+            try!(file.fsync());
+        }
+
+        try!(fs::rename(&tmp_path, &self.path));
+        sync_directory(&self.path.dir_path())
+    }
+}
+
+// Entries of a directory (created, renamed or removed files) reach the disk
+// with an fsync of the directory, fsync of a file covers its contents only
+pub fn sync_directory(directory: &Path) -> io::IoResult < () > {
+    try!(File::open(directory)).fsync()
+}
+
+#[deriving(Clone)]
+pub struct MemoryHardStateStorage {
+    state: Arc < Mutex < HardState > >,
+}
+
+impl MemoryHardStateStorage {
+    pub fn new() -> MemoryHardStateStorage {
+        MemoryHardStateStorage { state: Arc::new(Mutex::new(HardState::new())) }
+    }
+
+    pub fn state(&self) -> HardState {
+        self.state.lock().clone()
+    }
+}
+
+impl HardStateStorage for MemoryHardStateStorage {
+    fn load(&mut self) -> io::IoResult < HardState > {
+        Ok(self.state.lock().clone())
+    }
+
+    fn save(&mut self, state: &HardState) -> io::IoResult < () > {
+        *self.state.lock() = state.clone();
+        Ok(())
+    }
+}
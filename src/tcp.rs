@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::io::{IoResult, IoError, InvalidInput, MemWriter};
+use std::io::net::ip::SocketAddr;
+use std::io::net::tcp::{TcpListener, TcpStream, TcpAcceptor};
+use std::io::{Listener, Acceptor};
+use std::io::timer::sleep;
+use std::time::duration::Duration;
+use std::comm::Disconnected;
+use std::default::Default;
+use std::fmt::Show;
+use std::cmp;
+use std::sync::{Arc, Mutex};
+
+use std::task::TaskBuilder;
+
+use serialize::{Encodable, Decodable, json};
+
+use super::intercommunication::{Intercommunication, Endpoint, Package, receive_timeout};
+use super::replication::Committable;
+use super::metrics::{Metrics, NoopMetrics};
+use super::codec::{Codec, BinaryCodec, JsonCodec, BinaryEncoder, BinaryDecoder, WireFormat, BinaryFormat, JsonFormat};
+
+// frame layout: body_size (u32) | package encoded with the codec
+static MAX_FRAME_SIZE: uint = 64 * 1024 * 1024;
+
+#[deriving(Clone, Show)]
+pub struct TcpOptions {
+    // packages waiting for a peer, newer ones are dropped once it is full
+    pub queue_size: uint,
+
+    pub connect_timeout: Duration,
+
+    // delay before reconnecting doubles after every failed attempt. Once it
+    // reaches max_backoff the peer counts as down: the package in flight is
+    // still retried every max_backoff, and of the queued ones only the newest
+    // is kept. Every dropped package is reported to Metrics::packages_dropped.
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+
+    // both ends have to use the same one
+    pub format: WireFormat,
+}
+
+impl Default for TcpOptions {
+    fn default() -> TcpOptions {
+        TcpOptions {
+            queue_size: 1024,
+            connect_timeout: Duration::milliseconds(500),
+            min_backoff: Duration::milliseconds(10),
+            max_backoff: Duration::seconds(2),
+            format: BinaryFormat,
+        }
+    }
+}
+
+// Hosts are "ip:port" addresses. Registered hosts listen on their address,
+// packages for any other host go over a persistent connection to it.
+pub struct TcpIntercommunication < T: Committable + Send > {
+    receiver: Receiver < Package < T > >,
+    sender: Sender < Package < T > >,
+
+    // hosts registered in this process
+    local: HashMap < String, Sender < Package < T > > >,
+    acceptors: Vec < TcpAcceptor >,
+
+    // outgoing queue of every remote host
+    peers: HashMap < String, SyncSender < Package < T > > >,
+
+    // shared with the peer tasks
+    metrics: Arc < Mutex < Box < Metrics + Send > > >,
+
+    pub options: TcpOptions,
+    pub is_debug: bool,
+}
+
+impl < 'a, T: Committable + Send + Show + Encodable < json::Encoder < 'a >, IoError > + Decodable < json::Decoder, json::DecoderError > + Encodable < BinaryEncoder, IoError > + Decodable < BinaryDecoder, IoError > > TcpIntercommunication < T > {
+    pub fn with_options(options: TcpOptions) -> TcpIntercommunication < T > {
+        let mut me: TcpIntercommunication < T > = Intercommunication::new();
+        me.options = options;
+        me
+    }
+
+    // NoopMetrics by default
+    pub fn set_metrics < M: Metrics + Send + 'static >(&mut self, metrics: M) {
+        *self.metrics.lock() = box metrics as Box < Metrics + Send >;
+    }
+
+    fn peer(&mut self, host: &String) -> SyncSender < Package < T > > {
+        match self.peers.find(host) {
+            Some(queue) => return queue.clone(),
+            None => (),
+        }
+
+        let (tx, rx) = sync_channel(self.options.queue_size);
+        let options = self.options.clone();
+        let address = host.clone();
+        let metrics = self.metrics.clone();
+
+        TaskBuilder::new().named(format!("tcp-peer-{}", host)).spawn(proc() {
+            serve_peer(address, options, rx, metrics);
+        });
+
+        self.peers.insert(host.clone(), tx.clone());
+        tx
+    }
+}
+
+impl < 'a, T: Committable + Send + Show + Encodable < json::Encoder < 'a >, IoError > + Decodable < json::Decoder, json::DecoderError > + Encodable < BinaryEncoder, IoError > + Decodable < BinaryDecoder, IoError > > Intercommunication < T > for TcpIntercommunication < T > {
+    fn new() -> TcpIntercommunication < T > {
+        let (tx, rx) = channel();
+
+        TcpIntercommunication {
+            receiver: rx,
+            sender: tx,
+            local: HashMap::new(),
+            acceptors: vec![],
+            peers: HashMap::new(),
+            metrics: Arc::new(Mutex::new(box NoopMetrics as Box < Metrics + Send >)),
+            options: Default::default(),
+            is_debug: false,
+        }
+    }
+
+    fn register(&mut self, host: String) -> Endpoint < T > {
+        let acceptor = match listen(host.as_slice()) {
+            Ok(acceptor) => acceptor,
+            Err(error) => panic!("Unable to listen on {}: {}", host, error),
+        };
+
+        let (tx, rx) = channel();
+        let format = self.options.format.clone();
+
+        self.local.insert(host.clone(), tx.clone());
+        self.acceptors.push(acceptor.clone());
+
+        TaskBuilder::new().named(format!("tcp-listener-{}", host)).spawn(proc() {
+            accept_connections(acceptor, format, tx);
+        });
+
+        Endpoint {
+            host: host,
+            rx: rx,
+            tx: self.sender.clone(),
+        }
+    }
+
+    fn receive(&mut self) -> Option < Package < T > > {
+        match self.receiver.try_recv() {
+            Ok(package) => Some(package),
+            _ => None,
+        }
+    }
+
+    fn receive_timeout(&mut self, timeout: Duration) -> Option < Package < T > > {
+        receive_timeout(&self.receiver, timeout)
+    }
+
+    fn send(&mut self, recipient: String, package: Package < T >) {
+        match self.local.find(&recipient) {
+            Some(tx) => {
+                tx.send_opt(package).ok();
+                return;
+            },
+            None => (),
+        }
+
+        // peer that is down for long does not take all the memory
+        match self.peer(&recipient).try_send(package) {
+            Err(_) => {
+                self.metrics.lock().packages_dropped(recipient.as_slice(), 1);
+
+                if self.is_debug {
+                    println!("dropped package for host {}, queue is full", recipient);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn is_debug(&self) -> bool {
+        self.is_debug
+    }
+}
+
+#[unsafe_destructor]
+impl < T: Committable + Send > Drop for TcpIntercommunication < T > {
+    fn drop(&mut self) {
+        for acceptor in self.acceptors.iter_mut() {
+            acceptor.close_accept().ok();
+        }
+    }
+}
+
+fn address(host: &str) -> IoResult < SocketAddr > {
+    match from_str(host) {
+        Some(address) => Ok(address),
+        None => Err(IoError {
+            kind: InvalidInput,
+            desc: "host is not an ip:port address",
+            detail: Some(host.to_string()),
+        }),
+    }
+}
+
+fn listen(host: &str) -> IoResult < TcpAcceptor > {
+    let address = try!(address(host));
+    TcpListener::bind(address.ip.to_string().as_slice(), address.port).listen()
+}
+
+fn accept_connections < 'a, T: Committable + Send + Encodable < json::Encoder < 'a >, IoError > + Decodable < json::Decoder, json::DecoderError > + Encodable < BinaryEncoder, IoError > + Decodable < BinaryDecoder, IoError > >(mut acceptor: TcpAcceptor, format: WireFormat, deliver_to: Sender < Package < T > >) {
+    for stream in acceptor.incoming() {
+        match stream {
+            Ok(stream) => {
+                let deliver_to = deliver_to.clone();
+                let format = format.clone();
+
+                spawn(proc() {
+                    read_packages(stream, format, deliver_to);
+                });
+            },
+            // closed when intercommunication goes away
+            Err(_) => break,
+        }
+    }
+}
+
+fn read_packages < 'a, T: Committable + Send + Encodable < json::Encoder < 'a >, IoError > + Decodable < json::Decoder, json::DecoderError > + Encodable < BinaryEncoder, IoError > + Decodable < BinaryDecoder, IoError > >(mut stream: TcpStream, format: WireFormat, deliver_to: Sender < Package < T > >) {
+    loop {
+        let package = match read_frame(&mut stream, &format) {
+            Ok(package) => package,
+            Err(_) => break,
+        };
+
+        match deliver_to.send_opt(package) {
+            Err(_) => break,
+            _ => (),
+        }
+    }
+}
+
+// Packages are sent one by one over a single connection, so a peer sees them
+// in the order they were sent. Connection is reestablished with exponential
+// backoff and the package that failed is sent again. Once the backoff
+// reaches its maximum the peer is considered down and only the newest queued
+// package waits behind the one in flight.
+fn serve_peer < 'a, T: Committable + Send + Encodable < json::Encoder < 'a >, IoError > + Decodable < json::Decoder, json::DecoderError > + Encodable < BinaryEncoder, IoError > + Decodable < BinaryDecoder, IoError > >(host: String, options: TcpOptions, queue: Receiver < Package < T > >, metrics: Arc < Mutex < Box < Metrics + Send > > >) {
+    let mut connection: Option < TcpStream > = None;
+    let mut backoff = options.min_backoff;
+
+    // kept while the peer was down
+    let mut newest: Option < Package < T > > = None;
+
+    loop {
+        let package = match newest.take() {
+            Some(package) => package,
+            None => match queue.recv_opt() {
+                Ok(package) => package,
+                // intercommunication went away
+                Err(_) => break,
+            },
+        };
+
+        let frame = encode_frame(&package, &options.format);
+
+        loop {
+            if connection.is_none() {
+                connection = match connect(host.as_slice(), options.connect_timeout) {
+                    Ok(stream) => Some(stream),
+                    Err(_) => None,
+                };
+            }
+
+            let written = match connection {
+                Some(ref mut stream) => write_frame(stream, frame.as_slice()).is_ok(),
+                None => false,
+            };
+
+            if written {
+                backoff = options.min_backoff;
+                break;
+            }
+
+            connection = None;
+            sleep(backoff);
+
+            // peer is down, queued packages are stale by the time it is back
+            // and raft sends whatever it still needs again
+            if backoff == options.max_backoff {
+                let mut dropped = 0u;
+
+                loop {
+                    match queue.try_recv() {
+                        Ok(queued) => {
+                            if newest.is_some() {
+                                dropped += 1;
+                            }
+
+                            newest = Some(queued);
+                        },
+                        Err(Disconnected) => return,
+                        Err(_) => break,
+                    }
+                }
+
+                if dropped > 0 {
+                    metrics.lock().packages_dropped(host.as_slice(), dropped);
+                }
+            }
+
+            backoff = cmp::min(backoff * 2, options.max_backoff);
+        }
+    }
+}
+
+fn connect(host: &str, timeout: Duration) -> IoResult < TcpStream > {
+    let address = try!(address(host));
+    TcpStream::connect_timeout(address, timeout)
+}
+
+fn write_frame(stream: &mut TcpStream, frame: &[u8]) -> IoResult < () > {
+    try!(stream.write(frame));
+    stream.flush()
+}
+
+fn encode_frame < 'a, T: Committable + Send + Encodable < json::Encoder < 'a >, IoError > + Decodable < json::Decoder, json::DecoderError > + Encodable < BinaryEncoder, IoError > + Decodable < BinaryDecoder, IoError > >(package: &Package < T >, format: &WireFormat) -> Vec < u8 > {
+    let body = match *format {
+        BinaryFormat => BinaryCodec.encode(package),
+        JsonFormat => JsonCodec.encode(package),
+    };
+
+    let mut frame = MemWriter::with_capacity(4 + body.len());
+    frame.write_be_u32(body.len() as u32).unwrap();
+    frame.write(body.as_slice()).unwrap();
+    frame.unwrap()
+}
+
+fn read_frame < 'a, T: Committable + Send + Encodable < json::Encoder < 'a >, IoError > + Decodable < json::Decoder, json::DecoderError > + Encodable < BinaryEncoder, IoError > + Decodable < BinaryDecoder, IoError > >(stream: &mut TcpStream, format: &WireFormat) -> IoResult < Package < T > > {
+    let size = try!(stream.read_be_u32()) as uint;
+
+    if size > MAX_FRAME_SIZE {
+        return Err(IoError {
+            kind: InvalidInput,
+            desc: "frame is too large",
+            detail: Some(format!("{} bytes", size)),
+        });
+    }
+
+    let body = try!(stream.read_exact(size));
+
+    match *format {
+        BinaryFormat => BinaryCodec.decode(body.as_slice()),
+        JsonFormat => JsonCodec.decode(body.as_slice()),
+    }
+}
@@ -0,0 +1,88 @@
+extern crate raft_rs;
+extern crate test;
+
+use raft_rs::node::{Node, Leader, NodeConfig};
+use raft_rs::intercommunication::{DefaultIntercommunication, Intercommunication, start};
+use raft_rs::replication::{DefaultReplicationLog, ReplicationLog, DefaultCommandContainer, DefaultQuery, DefaultReceivable, TestAdd};
+use raft_rs::storage::MemoryHardStateStorage;
+
+use std::io::timer::sleep;
+use std::time::duration::Duration;
+use std::default::Default;
+
+use test::Bencher;
+
+type TestNode = Node < DefaultCommandContainer, DefaultQuery, DefaultReceivable >;
+
+// Nodes introduced to each other, returned once one of them is the leader
+fn start_cluster(hosts: &[&str]) -> (Vec < TestNode >, Sender < int >) {
+    let mut comm: DefaultIntercommunication < DefaultCommandContainer > = Intercommunication::new();
+    let mut nodes = vec![];
+
+    for host in hosts.iter() {
+        let log: DefaultReplicationLog = ReplicationLog::new();
+        let mut node = Node::new();
+        let config: NodeConfig = Default::default();
+        node.start(*host, &mut comm, log, MemoryHardStateStorage::new(), config).unwrap();
+        nodes.push(node);
+    }
+
+    let sig = start(comm);
+
+    for (i, node) in nodes.iter().enumerate() {
+        for (j, host) in hosts.iter().enumerate() {
+            if i != j {
+                node.introduce(*host);
+            }
+        }
+    }
+
+    while !nodes.iter().any(|node| node.state() == Leader) {
+        sleep(Duration::milliseconds(10));
+    }
+
+    (nodes, sig)
+}
+
+fn stop_cluster(nodes: Vec < TestNode >, sig: Sender < int >) {
+    for node in nodes.iter() {
+        node.stop();
+    }
+
+    sig.send(0);
+}
+
+fn commit_latency(b: &mut Bencher, hosts: &[&str]) {
+    let (nodes, sig) = start_cluster(hosts);
+
+    {
+        let leader = nodes.iter().find(|node| node.state() == Leader).unwrap();
+
+        b.iter(|| {
+            leader.enqueue(DefaultCommandContainer { command: TestAdd(1) }).recv()
+        });
+    }
+
+    stop_cluster(nodes, sig);
+}
+
+// Node wakes up as soon as a command arrives
+#[bench]
+fn command_round_trip(b: &mut Bencher) {
+    let (nodes, sig) = start_cluster(&["john"]);
+
+    b.iter(|| nodes[0].state());
+
+    stop_cluster(nodes, sig);
+}
+
+#[bench]
+fn single_node_commit_latency(b: &mut Bencher) {
+    commit_latency(b, &["john"]);
+}
+
+// every hop through the intercommunication router wakes the receiver at once
+#[bench]
+fn three_node_commit_latency(b: &mut Bencher) {
+    commit_latency(b, &["john", "sarah", "alice"]);
+}
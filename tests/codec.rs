@@ -0,0 +1,198 @@
+extern crate raft_rs;
+
+use raft_rs::codec::{Codec, BinaryCodec, JsonCodec, PROTOCOL_VERSION};
+use raft_rs::intercommunication::{Package, Pack, PackageDetails, AppendLog, AppendLogEntry};
+use raft_rs::intercommunication::{Ack, LeaderQuery, LeaderQueryResponse, AppendQuery, Persisted, AppendRejected, InstallSnapshot, RequestVote, Vote, PreVote, PreVoteResponse, TimeoutNow, Heartbeat, HeartbeatResponse, ReadIndexRequest, ReadIndexResponse, Propose, ProposalAccepted, ProposalRejected};
+use raft_rs::replication::{DefaultCommandContainer, TestSet, TestAdd, Snapshot, Entry, CommandEntry, ConfigurationEntry, NoopEntry, RegisterSessionEntry, SessionCommandEntry};
+use raft_rs::membership::Configuration;
+use raft_rs::session::{Sessions, SessionCommand};
+
+use std::rand::{Rng, SeedableRng, XorShiftRng};
+use std::{int, i64, uint};
+
+static VARIANTS: uint = 19;
+
+fn rng(seed: u32) -> XorShiftRng {
+    SeedableRng::from_seed([seed + 1, 7, 13, 42])
+}
+
+// json keeps numbers as f64, so random ones stay within its precision
+static LARGEST: i64 = 1 << 52;
+
+fn number < R: Rng >(rng: &mut R) -> uint {
+    // small and large numbers take different number of varint bytes
+    match rng.gen_range(0u, 3) {
+        0 => rng.gen_range(0u, 128),
+        1 => rng.gen_range(0u, 1 << 20),
+        _ => rng.gen_range(0, LARGEST as uint),
+    }
+}
+
+fn signed < R: Rng >(rng: &mut R) -> i64 {
+    rng.gen_range(-LARGEST, LARGEST)
+}
+
+fn text < R: Rng >(rng: &mut R) -> String {
+    let alphabet = ['a', 'z', 'Z', '0', '.', ':', '-', 'é', '漢', '\n'];
+    let len = rng.gen_range(0u, 20);
+
+    range(0, len).map(|_| alphabet[rng.gen_range(0, alphabet.len())]).collect()
+}
+
+fn maybe_text < R: Rng >(rng: &mut R) -> Option < String > {
+    if rng.gen() { Some(text(rng)) } else { None }
+}
+
+fn hosts < R: Rng >(rng: &mut R) -> Vec < String > {
+    let len = rng.gen_range(0u, 5);
+    range(0, len).map(|_| text(rng)).collect()
+}
+
+fn command < R: Rng >(rng: &mut R) -> DefaultCommandContainer {
+    let x = signed(rng) as int;
+    DefaultCommandContainer { command: if rng.gen() { TestSet(x) } else { TestAdd(x) } }
+}
+
+fn entry < R: Rng >(rng: &mut R) -> Entry < DefaultCommandContainer > {
+    match rng.gen_range(0u, 5) {
+        0 => CommandEntry(command(rng)),
+        1 => ConfigurationEntry(Configuration { voters: hosts(rng), outgoing_voters: hosts(rng) }),
+        2 => NoopEntry,
+        3 => RegisterSessionEntry(signed(rng)),
+        _ => SessionCommandEntry(SessionCommand {
+            client_id: number(rng),
+            sequence: number(rng),
+            timestamp: signed(rng),
+            command: command(rng),
+        }),
+    }
+}
+
+fn snapshot < R: Rng >(rng: &mut R) -> Snapshot {
+    let mut sessions = Sessions::new(signed(rng));
+    sessions.register(3, 0);
+    let response = range(0, rng.gen_range(0u, 20)).map(|_| rng.gen::< u8 >()).collect();
+    sessions.applied(3, number(rng), number(rng), number(rng), response);
+
+    Snapshot {
+        last_included_index: number(rng),
+        last_included_term: number(rng),
+        configuration: if rng.gen() { Some(Configuration::new(hosts(rng))) } else { None },
+        sessions: sessions,
+        data: range(0, rng.gen_range(0u, 100)).map(|_| rng.gen::< u8 >()).collect(),
+    }
+}
+
+fn details < R: Rng >(rng: &mut R, variant: uint) -> PackageDetails < DefaultCommandContainer > {
+    match variant {
+        0 => Ack,
+        1 => LeaderQuery,
+        2 => LeaderQueryResponse(maybe_text(rng)),
+        3 => AppendQuery(AppendLog {
+            term: number(rng),
+            prev_log_index: number(rng),
+            prev_log_term: number(rng),
+            committed_offset: number(rng),
+            entries: range(0, rng.gen_range(0u, 10)).map(|_| AppendLogEntry {
+                offset: number(rng),
+                term: number(rng),
+                entry: entry(rng),
+            }).collect(),
+        }),
+        4 => Persisted(number(rng), number(rng)),
+        5 => AppendRejected(number(rng), number(rng), number(rng)),
+        6 => InstallSnapshot(number(rng), snapshot(rng)),
+        7 => RequestVote(number(rng), number(rng), number(rng), rng.gen()),
+        8 => Vote(number(rng)),
+        9 => PreVote(number(rng), number(rng), number(rng)),
+        10 => PreVoteResponse(number(rng), rng.gen()),
+        11 => TimeoutNow(number(rng)),
+        12 => Heartbeat(number(rng), number(rng)),
+        13 => HeartbeatResponse(number(rng), number(rng)),
+        14 => ReadIndexRequest(number(rng)),
+        15 => ReadIndexResponse(number(rng), number(rng)),
+        16 => Propose(number(rng), entry(rng)),
+        17 => ProposalAccepted(number(rng), number(rng), number(rng)),
+        _ => ProposalRejected(number(rng), maybe_text(rng)),
+    }
+}
+
+fn package < R: Rng >(rng: &mut R, variant: uint) -> Package < DefaultCommandContainer > {
+    Pack(text(rng), text(rng), details(rng, variant))
+}
+
+fn assert_round_trips < C: Codec < DefaultCommandContainer > >(codec: C) {
+    for seed in range(0, 50u32) {
+        let mut rng = rng(seed);
+
+        for variant in range(0, VARIANTS) {
+            let package = package(&mut rng, variant);
+            let bytes = codec.encode(&package);
+
+            assert_eq!(Ok(package), codec.decode(bytes.as_slice()));
+        }
+    }
+}
+
+#[test]
+fn every_package_round_trips_through_binary_codec() {
+    assert_round_trips(BinaryCodec);
+}
+
+#[test]
+fn every_package_round_trips_through_json_codec() {
+    assert_round_trips(JsonCodec);
+}
+
+#[test]
+fn extreme_numbers_round_trip_through_binary_codec() {
+    let package = Pack("john".to_string(), "sarah".to_string(), Propose(uint::MAX, SessionCommandEntry(SessionCommand {
+        client_id: uint::MAX,
+        sequence: 0,
+        timestamp: i64::MIN,
+        command: DefaultCommandContainer { command: TestSet(int::MIN) },
+    })));
+
+    let bytes = BinaryCodec.encode(&package);
+    assert_eq!(Ok(package), BinaryCodec.decode(bytes.as_slice()));
+}
+
+#[test]
+fn binary_package_starts_with_protocol_version() {
+    let bytes = BinaryCodec.encode(&Pack("john".to_string(), "sarah".to_string(), Vote(3)));
+
+    assert_eq!(PROTOCOL_VERSION, bytes[0]);
+}
+
+#[test]
+fn package_of_other_protocol_version_is_rejected() {
+    let mut bytes = BinaryCodec.encode(&Pack("john".to_string(), "sarah".to_string(), Vote(3)));
+    *bytes.get_mut(0) = PROTOCOL_VERSION + 1;
+
+    let result: Result < Package < DefaultCommandContainer >, _ > = BinaryCodec.decode(bytes.as_slice());
+    assert!(result.is_err());
+}
+
+#[test]
+fn truncated_package_is_rejected() {
+    for seed in range(0, 10u32) {
+        let mut rng = rng(seed);
+
+        for variant in range(0, VARIANTS) {
+            let bytes = BinaryCodec.encode(&package(&mut rng, variant));
+
+            for len in range(0, bytes.len()) {
+                let result: Result < Package < DefaultCommandContainer >, _ > = BinaryCodec.decode(bytes.slice_to(len));
+                assert!(result.is_err());
+            }
+        }
+    }
+}
+
+#[test]
+fn binary_append_is_smaller_than_json() {
+    let mut rng = rng(0);
+    let append = package(&mut rng, 3);
+
+    assert!(BinaryCodec.encode(&append).len() < JsonCodec.encode(&append).len());
+}
@@ -0,0 +1,283 @@
+extern crate raft_rs;
+
+use std::io::{File, Append, Write, TempDir, IoResult};
+use std::io::fs;
+use std::default::Default;
+
+use raft_rs::file_log::{FileReplicationLog, FileLogOptions, SyncNever};
+use raft_rs::replication::{DefaultCommandContainer, DefaultPersistence, DefaultQuery, DefaultReceivable, ReplicationLog, TestAdd, TestSet, CommandEntry};
+
+type Log = FileReplicationLog < DefaultCommandContainer, DefaultQuery, DefaultReceivable, DefaultPersistence >;
+
+fn open(dir: &TempDir) -> Log {
+    FileReplicationLog::open(dir.path(), Default::default(), DefaultPersistence::start()).unwrap()
+}
+
+fn open_with_small_segments(dir: &TempDir) -> Log {
+    let options = FileLogOptions { segment_size: 64, sync: SyncNever };
+    FileReplicationLog::open(dir.path(), options, DefaultPersistence::start()).unwrap()
+}
+
+fn last_segment(dir: &TempDir) -> Path {
+    let mut paths = fs::readdir(dir.path()).unwrap();
+    paths.sort_by(|a, b| a.filename_str().cmp(&b.filename_str()));
+    paths.pop().unwrap()
+}
+
+#[test]
+fn enqueued_entries_survive_reopening() {
+    let dir = TempDir::new("raft-rs-file-log").unwrap();
+
+    {
+        let mut log = open(&dir);
+
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(3) }), 1).unwrap();
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), 1).unwrap();
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(21) }), 2).unwrap();
+    }
+
+    let log = open(&dir);
+
+    assert_eq!(3, log.len());
+    assert_eq!(Some(1), log.term_at(1));
+    assert_eq!(2, log.last_term());
+    assert_eq!(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), log.entry_at(1).unwrap());
+}
+
+#[test]
+fn committing_applies_entries_to_persistence() {
+    let dir = TempDir::new("raft-rs-file-log").unwrap();
+    let mut log = open(&dir);
+
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(3) }), 1).unwrap();
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), 1).unwrap();
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(21) }), 1).unwrap();
+
+    log.commit_upto(2).unwrap();
+
+    assert_eq!(2, log.committed_offset());
+    assert_eq!(3, log.persistence.rx.recv());
+    assert_eq!(8, log.persistence.rx.recv());
+}
+
+#[test]
+fn torn_tail_write_is_truncated_on_open() {
+    let dir = TempDir::new("raft-rs-file-log").unwrap();
+
+    {
+        let mut log = open(&dir);
+
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(3) }), 1).unwrap();
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), 1).unwrap();
+    }
+
+    {
+        let mut file = File::open_mode(&last_segment(&dir), Append, Write).unwrap();
+        file.write([0u8, 0, 0, 42, 1, 2, 3].as_slice()).unwrap();
+    }
+
+    let mut log = open(&dir);
+
+    assert_eq!(2, log.len());
+
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(9) }), 2).unwrap();
+
+    let log = open(&dir);
+
+    assert_eq!(3, log.len());
+    assert_eq!(CommandEntry(DefaultCommandContainer { command: TestSet(9) }), log.entry_at(2).unwrap());
+}
+
+#[test]
+fn record_with_bad_checksum_is_dropped_on_open() {
+    let dir = TempDir::new("raft-rs-file-log").unwrap();
+
+    {
+        let mut log = open(&dir);
+
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(3) }), 1).unwrap();
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), 1).unwrap();
+    }
+
+    let path = last_segment(&dir);
+    let mut bytes = File::open(&path).read_to_end().unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] = bytes[last] ^ 0xff;
+    File::create(&path).write(bytes.as_slice()).unwrap();
+
+    let log = open(&dir);
+
+    assert_eq!(1, log.len());
+}
+
+#[test]
+fn damaged_record_followed_by_valid_ones_fails_open() {
+    let dir = TempDir::new("raft-rs-file-log").unwrap();
+
+    {
+        let mut log = open(&dir);
+
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(3) }), 1).unwrap();
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), 1).unwrap();
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(9) }), 1).unwrap();
+    }
+
+    // term of the first record, right after its 8 byte header
+    let path = last_segment(&dir);
+    let mut bytes = File::open(&path).read_to_end().unwrap();
+    bytes[15] = bytes[15] ^ 0xff;
+    File::create(&path).write(bytes.as_slice()).unwrap();
+
+    let log: IoResult < Log > = FileReplicationLog::open(dir.path(), Default::default(), DefaultPersistence::start());
+
+    assert!(log.is_err());
+}
+
+#[test]
+fn log_is_split_into_segments() {
+    let dir = TempDir::new("raft-rs-file-log").unwrap();
+
+    {
+        let mut log = open_with_small_segments(&dir);
+
+        for i in range(0, 10i) {
+            log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(i) }), 1).unwrap();
+        }
+
+        assert!(log.segments_count() > 1);
+    }
+
+    let log = open_with_small_segments(&dir);
+
+    assert_eq!(10, log.len());
+    assert_eq!(CommandEntry(DefaultCommandContainer { command: TestSet(7) }), log.entry_at(7).unwrap());
+}
+
+#[test]
+fn discarding_entries_across_segments() {
+    let dir = TempDir::new("raft-rs-file-log").unwrap();
+
+    {
+        let mut log = open_with_small_segments(&dir);
+
+        for i in range(0, 10i) {
+            log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(i) }), 1).unwrap();
+        }
+
+        log.discard_downto(2).unwrap();
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(1) }), 2).unwrap();
+    }
+
+    let log = open_with_small_segments(&dir);
+
+    assert_eq!(3, log.len());
+    assert_eq!(Some(2), log.term_at(2));
+    assert_eq!(CommandEntry(DefaultCommandContainer { command: TestAdd(1) }), log.entry_at(2).unwrap());
+}
+
+#[test]
+fn entries_are_read_back_after_discarding_without_reopening() {
+    let dir = TempDir::new("raft-rs-file-log").unwrap();
+    let mut log = open_with_small_segments(&dir);
+
+    for i in range(0, 10i) {
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(i) }), 1).unwrap();
+    }
+
+    assert_eq!(CommandEntry(DefaultCommandContainer { command: TestSet(8) }), log.entry_at(8).unwrap());
+    assert_eq!(CommandEntry(DefaultCommandContainer { command: TestSet(2) }), log.entry_at(2).unwrap());
+
+    log.discard_downto(2).unwrap();
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(1) }), 2).unwrap();
+
+    assert_eq!(CommandEntry(DefaultCommandContainer { command: TestSet(1) }), log.entry_at(1).unwrap());
+    assert_eq!(CommandEntry(DefaultCommandContainer { command: TestAdd(1) }), log.entry_at(2).unwrap());
+}
+
+#[test]
+fn compaction_removes_covered_segments_and_survives_reopening() {
+    let dir = TempDir::new("raft-rs-file-log").unwrap();
+
+    {
+        let mut log = open_with_small_segments(&dir);
+
+        for i in range(0, 10i) {
+            log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(i) }), 1).unwrap();
+        }
+
+        let segments_before = log.segments_count();
+
+        log.commit_upto(6).unwrap();
+        log.compact().unwrap();
+
+        assert_eq!(6, log.first_offset());
+        assert!(log.segments_count() < segments_before);
+    }
+
+    let log = open_with_small_segments(&dir);
+
+    assert_eq!(6, log.first_offset());
+    assert_eq!(10, log.len());
+    assert_eq!(Some(1), log.term_at(5));
+    assert_eq!(None, log.term_at(4));
+    assert_eq!(CommandEntry(DefaultCommandContainer { command: TestSet(8) }), log.entry_at(8).unwrap());
+}
+
+#[test]
+fn compacted_log_restores_state_and_goes_on_committing_after_reopening() {
+    let dir = TempDir::new("raft-rs-file-log").unwrap();
+
+    {
+        let mut log = open(&dir);
+
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(3) }), 1).unwrap();
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), 1).unwrap();
+        log.commit_upto(2).unwrap();
+        log.compact().unwrap();
+
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(10) }), 1).unwrap();
+    }
+
+    let mut log = open(&dir);
+
+    assert_eq!(2, log.committed_offset());
+    // value of the snapshot
+    assert_eq!(8, log.persistence.rx.recv());
+
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(1) }), 2).unwrap();
+    log.commit_upto(4).unwrap();
+
+    assert_eq!(4, log.committed_offset());
+    assert_eq!(18, log.persistence.rx.recv());
+    assert_eq!(19, log.persistence.rx.recv());
+}
+
+#[test]
+fn installing_snapshot_into_lagging_log() {
+    let dir = TempDir::new("raft-rs-file-log").unwrap();
+    let other_dir = TempDir::new("raft-rs-file-log").unwrap();
+
+    let mut log = open(&dir);
+
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(3) }), 1).unwrap();
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), 1).unwrap();
+    log.commit_upto(2).unwrap();
+    log.compact().unwrap();
+
+    {
+        let mut other = open(&other_dir);
+
+        other.install_snapshot(log.snapshot().unwrap()).unwrap();
+
+        assert_eq!(8, other.persistence.rx.recv());
+
+        other.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(1) }), 2).unwrap();
+    }
+
+    let other = open(&other_dir);
+
+    assert_eq!(2, other.first_offset());
+    assert_eq!(3, other.len());
+    assert_eq!(Some(1), other.term_at(1));
+    assert_eq!(CommandEntry(DefaultCommandContainer { command: TestAdd(1) }), other.entry_at(2).unwrap());
+}
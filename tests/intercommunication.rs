@@ -4,7 +4,7 @@ extern crate raft_rs;
 mod using_dumb_network {
 
     use raft_rs::intercommunication::{Intercommunication, DefaultIntercommunication, Ack, Pack, start, AppendLog, AppendQuery, AppendLogEntry};
-    use raft_rs::replication::{DefaultCommandContainer, TestSet};
+    use raft_rs::replication::{DefaultCommandContainer, TestSet, TestAdd, CommandEntry};
 
     #[test]
     fn sending_simple_ack() {
@@ -38,27 +38,39 @@ mod using_dumb_network {
         let stop_comm = start(comm);
 
         comm_1.send("host_2".to_string(), AppendQuery(AppendLog {
+            term: 3,
+            prev_log_index: 1,
+            prev_log_term: 2,
             committed_offset: 0,
-            node_list: vec![],
-            enqueue: Some(AppendLogEntry {
-                offset: 1,
-                entry: DefaultCommandContainer { command: TestSet(2) },
-            }),
+            entries: vec![
+                AppendLogEntry {
+                    offset: 1,
+                    term: 0,
+                    entry: CommandEntry(DefaultCommandContainer { command: TestSet(2) }),
+                },
+                AppendLogEntry {
+                    offset: 2,
+                    term: 1,
+                    entry: CommandEntry(DefaultCommandContainer { command: TestAdd(3) }),
+                },
+            ],
         }));
 
         match comm_2.listen_block_with_timeout() {
-            Some(Pack(from, to, AppendQuery(AppendLog { committed_offset, node_list, enqueue }))) => {
+            Some(Pack(from, to, AppendQuery(AppendLog { term, prev_log_index, prev_log_term, committed_offset, entries }))) => {
                 assert_eq!(from, "host_1".to_string());
                 assert_eq!(to, "host_2".to_string());
-                assert_eq!(node_list, vec![]);
+                assert_eq!(term, 3);
+                assert_eq!(prev_log_index, 1);
+                assert_eq!(prev_log_term, 2);
                 assert_eq!(committed_offset, 0);
-                match enqueue {
-                    Some(AppendLogEntry { offset, entry }) => {
-                        assert_eq!(offset, 1);
-                        assert_eq!(entry, DefaultCommandContainer { command: TestSet(2) });
-                    },
-                    _ => panic!("No enqueue"),
-                }
+                assert_eq!(entries.len(), 2);
+                assert_eq!(entries[0].offset, 1);
+                assert_eq!(entries[0].term, 0);
+                assert_eq!(entries[0].entry, CommandEntry(DefaultCommandContainer { command: TestSet(2) }));
+                assert_eq!(entries[1].offset, 2);
+                assert_eq!(entries[1].term, 1);
+                assert_eq!(entries[1].entry, CommandEntry(DefaultCommandContainer { command: TestAdd(3) }));
             },
             _ => panic!("No append query"),
         }
@@ -66,3 +78,186 @@ mod using_dumb_network {
         stop_comm.send(0);
     }
 }
+
+mod using_tcp {
+
+    use raft_rs::intercommunication::{Intercommunication, Endpoint, Ack, Heartbeat, Pack, PackageDetails, start};
+    use raft_rs::tcp::{TcpIntercommunication, TcpOptions};
+    use raft_rs::metrics::Metrics;
+    use raft_rs::node::{Node, Leader, Committed, NodeConfig};
+    use raft_rs::replication::{DefaultReplicationLog, ReplicationLog, DefaultCommandContainer, DefaultQuery, DefaultReceivable, ReceivableInt, TestSet};
+    use raft_rs::storage::MemoryHardStateStorage;
+
+    use std::io::timer::sleep;
+    use std::time::duration::Duration;
+    use std::default::Default;
+    use std::sync::{Arc, Mutex};
+
+    // every comm stands for a separate process
+    fn comm() -> TcpIntercommunication < DefaultCommandContainer > {
+        Intercommunication::new()
+    }
+
+    fn listen(endpoint: &Endpoint < DefaultCommandContainer >) -> Option < String > {
+        for _ in range(0, 500u) {
+            match endpoint.listen() {
+                Some(Pack(from, _, Ack)) => return Some(from),
+                Some(_) => (),
+                None => sleep(Duration::milliseconds(2)),
+            }
+        }
+
+        None
+    }
+
+    #[test]
+    fn sending_ack_over_loopback() {
+        let mut comm_1 = comm();
+        let mut comm_2 = comm();
+
+        let host_1 = comm_1.register("127.0.0.1:17301".to_string());
+        let host_2 = comm_2.register("127.0.0.1:17302".to_string());
+
+        let stop_1 = start(comm_1);
+        let stop_2 = start(comm_2);
+
+        host_1.send("127.0.0.1:17302".to_string(), Ack);
+        assert_eq!(Some("127.0.0.1:17301".to_string()), listen(&host_2));
+
+        host_2.send("127.0.0.1:17301".to_string(), Ack);
+        assert_eq!(Some("127.0.0.1:17302".to_string()), listen(&host_1));
+
+        stop_1.send(0);
+        stop_2.send(0);
+    }
+
+    #[test]
+    fn package_is_delivered_once_peer_comes_up() {
+        let mut comm_1 = comm();
+        let host_1 = comm_1.register("127.0.0.1:17311".to_string());
+        let stop_1 = start(comm_1);
+
+        host_1.send("127.0.0.1:17312".to_string(), Ack);
+
+        sleep(Duration::milliseconds(50));
+
+        let mut comm_2 = comm();
+        let host_2 = comm_2.register("127.0.0.1:17312".to_string());
+        let stop_2 = start(comm_2);
+
+        assert_eq!(Some("127.0.0.1:17311".to_string()), listen(&host_2));
+
+        stop_1.send(0);
+        stop_2.send(0);
+    }
+
+    // counts what the transport gave up on
+    struct DropCounter {
+        dropped: Arc < Mutex < uint > >,
+    }
+
+    impl Metrics for DropCounter {
+        fn packages_dropped(&mut self, _peer: &str, count: uint) {
+            *self.dropped.lock() += count;
+        }
+    }
+
+    fn receive(endpoint: &Endpoint < DefaultCommandContainer >, count: uint) -> Vec < PackageDetails < DefaultCommandContainer > > {
+        let mut received = vec![];
+
+        for _ in range(0, 500u) {
+            match endpoint.listen() {
+                Some(Pack(_, _, details)) => {
+                    received.push(details);
+
+                    if received.len() == count {
+                        break;
+                    }
+                },
+                None => sleep(Duration::milliseconds(2)),
+            }
+        }
+
+        received
+    }
+
+    #[test]
+    fn peer_that_is_down_gets_package_in_flight_and_newest_one() {
+        let dropped = Arc::new(Mutex::new(0u));
+
+        let mut comm_1: TcpIntercommunication < DefaultCommandContainer > = TcpIntercommunication::with_options(TcpOptions {
+            queue_size: 4,
+            min_backoff: Duration::milliseconds(10),
+            max_backoff: Duration::milliseconds(20),
+            ..Default::default()
+        });
+        comm_1.set_metrics(DropCounter { dropped: dropped.clone() });
+
+        let host_1 = comm_1.register("127.0.0.1:17331".to_string());
+        let stop_1 = start(comm_1);
+
+        // Ack is in flight by the time the peer counts as down
+        host_1.send("127.0.0.1:17332".to_string(), Ack);
+        host_1.send("127.0.0.1:17332".to_string(), Heartbeat(1, 0));
+        host_1.send("127.0.0.1:17332".to_string(), Heartbeat(2, 0));
+        host_1.send("127.0.0.1:17332".to_string(), Heartbeat(3, 0));
+
+        sleep(Duration::milliseconds(100));
+
+        let mut comm_2 = comm();
+        let host_2 = comm_2.register("127.0.0.1:17332".to_string());
+        let stop_2 = start(comm_2);
+
+        assert_eq!(vec![Ack, Heartbeat(3, 0)], receive(&host_2, 3));
+        assert_eq!(2, *dropped.lock());
+
+        stop_1.send(0);
+        stop_2.send(0);
+    }
+
+    #[test]
+    fn cluster_commits_over_loopback() {
+        let hosts = ["127.0.0.1:17321", "127.0.0.1:17322", "127.0.0.1:17323"];
+
+        let mut nodes: Vec < Node < DefaultCommandContainer, DefaultQuery, DefaultReceivable > > = vec![];
+        let mut stops = vec![];
+
+        for host in hosts.iter() {
+            let mut node = Node::new();
+            let mut comm = comm();
+            let log: DefaultReplicationLog = ReplicationLog::new();
+
+            let config = NodeConfig {
+                min_election_timeout: Duration::milliseconds(300),
+                max_election_timeout: Duration::milliseconds(600),
+                ..Default::default()
+            };
+
+            node.start(*host, &mut comm, log, MemoryHardStateStorage::new(), config).unwrap();
+
+            nodes.push(node);
+            stops.push(start(comm));
+        }
+
+        nodes[0].forced_state(Leader);
+        nodes[1].introduce(hosts[0]);
+        nodes[2].introduce(hosts[0]);
+        nodes[0].add_voter(hosts[1]);
+        nodes[0].add_voter(hosts[2]);
+
+        sleep(Duration::milliseconds(200));
+
+        match nodes[0].enqueue(DefaultCommandContainer { command: TestSet(4) }).recv() {
+            Ok(Committed { applied, .. }) => assert_eq!(Ok(Some(ReceivableInt(4))), applied),
+            x => panic!("Should have been committed, got {}", x),
+        }
+
+        for node in nodes.iter_mut() {
+            node.stop();
+        }
+
+        for stop in stops.iter() {
+            stop.send(0);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+extern crate raft_rs;
+
+use std::collections::HashMap;
+
+use raft_rs::membership::{Configuration, AddVoter, RemoveVoter};
+
+fn hosts(hosts: Vec < &str >) -> Vec < String > {
+    hosts.iter().map(|x| { x.to_string() }).collect()
+}
+
+fn match_index(pairs: Vec < (&str, uint) >) -> HashMap < String, uint > {
+    let mut result = HashMap::new();
+
+    for &(host, index) in pairs.iter() {
+        result.insert(host.to_string(), index);
+    }
+
+    result
+}
+
+#[test]
+fn change_goes_through_joint_configuration() {
+    let configuration = Configuration::new(hosts(vec!["a", "b", "c"]));
+
+    let joint = configuration.apply(&[AddVoter("d".to_string()), RemoveVoter("a".to_string())]);
+
+    assert!(joint.is_joint());
+    assert_eq!(hosts(vec!["b", "c", "d"]), joint.voters);
+    assert_eq!(hosts(vec!["a", "b", "c"]), joint.outgoing_voters);
+    assert_eq!(hosts(vec!["b", "c", "d", "a"]), joint.hosts());
+    assert!(joint.contains(&"a".to_string()));
+
+    let new = joint.leave_joint();
+
+    assert!(!new.is_joint());
+    assert!(!new.contains(&"a".to_string()));
+}
+
+#[test]
+fn joint_quorum_needs_majority_of_both_configurations() {
+    let joint = Configuration::new(hosts(vec!["a", "b", "c"])).apply(&[
+        AddVoter("d".to_string()),
+        AddVoter("e".to_string()),
+    ]);
+
+    // majority of old voters only
+    assert!(!joint.has_quorum(hosts(vec!["a", "b"]).as_slice()));
+
+    // majority of new voters only
+    assert!(!joint.has_quorum(hosts(vec!["a", "d", "e"]).as_slice()));
+
+    assert!(joint.has_quorum(hosts(vec!["a", "b", "d"]).as_slice()));
+}
+
+#[test]
+fn quorum_match_index_is_stored_on_majority() {
+    let configuration = Configuration::new(hosts(vec!["a", "b", "c"]));
+
+    assert_eq!(5, configuration.quorum_match_index(&match_index(vec![("a", 7), ("b", 5), ("c", 1)])));
+    assert_eq!(0, configuration.quorum_match_index(&match_index(vec![("a", 7)])));
+}
+
+#[test]
+fn joint_quorum_match_index_is_the_lower_of_both() {
+    let joint = Configuration::new(hosts(vec!["a", "b", "c"])).apply(&[
+        RemoveVoter("b".to_string()),
+        RemoveVoter("c".to_string()),
+        AddVoter("d".to_string()),
+    ]);
+
+    let matched = match_index(vec![("a", 9), ("b", 2), ("c", 3), ("d", 8)]);
+
+    assert_eq!(3, joint.quorum_match_index(&matched));
+    assert_eq!(8, joint.leave_joint().quorum_match_index(&matched));
+}
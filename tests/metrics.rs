@@ -0,0 +1,142 @@
+extern crate raft_rs;
+
+use raft_rs::metrics::{Metrics, PrometheusMetrics};
+use raft_rs::raw_node::RawNode;
+use raft_rs::node::{Follower, Candidate, Leader, NodeConfig};
+use raft_rs::intercommunication::Pack;
+use raft_rs::replication::{DefaultReplicationLog, ReplicationLog, DefaultCommandContainer, DefaultQuery, DefaultReceivable, CommandEntry, TestSet};
+use raft_rs::storage::HardState;
+use raft_rs::clock::{Clock, ManualClock};
+
+use std::io::MemWriter;
+use std::time::duration::Duration;
+use std::default::Default;
+
+fn render(metrics: &PrometheusMetrics) -> String {
+    let mut writer = MemWriter::new();
+    metrics.render(&mut writer).unwrap();
+    String::from_utf8(writer.unwrap()).unwrap()
+}
+
+fn assert_has_line(text: &String, line: &str) {
+    assert!(text.as_slice().lines().any(|x| x == line), "no line {} in\n{}", line, text);
+}
+
+#[test]
+fn counters_and_gauges_are_rendered() {
+    let mut metrics = PrometheusMetrics::new();
+
+    metrics.term_changed(3);
+    metrics.state_changed(Follower, Candidate);
+    metrics.state_changed(Candidate, Leader);
+    metrics.election_started();
+    metrics.election_won();
+    metrics.entries_appended(5);
+    metrics.entries_committed(4, 7);
+    metrics.peer_lag("sarah", 2);
+    metrics.message_sent("vote");
+    metrics.message_sent("vote");
+    metrics.message_received("append_query");
+    metrics.packages_dropped("sarah", 3);
+    metrics.packages_dropped("sarah", 2);
+
+    let text = render(&metrics);
+
+    assert_has_line(&text, "# TYPE raft_term gauge");
+    assert_has_line(&text, "raft_term 3");
+    assert_has_line(&text, "raft_state_transitions_total{from=\"follower\",to=\"candidate\"} 1");
+    assert_has_line(&text, "raft_state_transitions_total{from=\"candidate\",to=\"leader\"} 1");
+    assert_has_line(&text, "raft_elections_started_total 1");
+    assert_has_line(&text, "raft_elections_won_total 1");
+    assert_has_line(&text, "raft_entries_appended_total 5");
+    assert_has_line(&text, "raft_entries_committed_total 4");
+    assert_has_line(&text, "raft_commit_index 7");
+    assert_has_line(&text, "raft_peer_lag_entries{peer=\"sarah\"} 2");
+    assert_has_line(&text, "raft_messages_sent_total{kind=\"vote\"} 2");
+    assert_has_line(&text, "raft_messages_received_total{kind=\"append_query\"} 1");
+    assert_has_line(&text, "raft_packages_dropped_total{peer=\"sarah\"} 5");
+}
+
+#[test]
+fn latency_buckets_are_cumulative() {
+    let mut metrics = PrometheusMetrics::new();
+
+    metrics.proposal_committed(Duration::microseconds(500));
+    metrics.proposal_committed(Duration::milliseconds(20));
+    metrics.proposal_committed(Duration::seconds(60));
+
+    let text = render(&metrics);
+
+    assert_has_line(&text, "# TYPE raft_proposal_commit_latency_seconds histogram");
+    assert_has_line(&text, "raft_proposal_commit_latency_seconds_bucket{le=\"0.001\"} 1");
+    assert_has_line(&text, "raft_proposal_commit_latency_seconds_bucket{le=\"0.01\"} 1");
+    assert_has_line(&text, "raft_proposal_commit_latency_seconds_bucket{le=\"0.025\"} 2");
+    assert_has_line(&text, "raft_proposal_commit_latency_seconds_bucket{le=\"10\"} 2");
+    assert_has_line(&text, "raft_proposal_commit_latency_seconds_bucket{le=\"+Inf\"} 3");
+    assert_has_line(&text, "raft_proposal_commit_latency_seconds_count 3");
+}
+
+#[test]
+fn label_values_are_escaped() {
+    let mut metrics = PrometheusMetrics::new();
+    metrics.peer_lag("a\"b\\c", 1);
+
+    assert_has_line(&render(&metrics), "raft_peer_lag_entries{peer=\"a\\\"b\\\\c\"} 1");
+}
+
+#[test]
+fn raw_node_reports_election_and_commit() {
+    let clock = ManualClock::new();
+    let metrics = PrometheusMetrics::new();
+
+    let log: DefaultReplicationLog = ReplicationLog::new();
+    let config: NodeConfig = Default::default();
+    let mut node: RawNode < DefaultCommandContainer, DefaultReplicationLog, DefaultQuery, DefaultReceivable > = RawNode::new("john", log, HardState::new(), config);
+    node.set_clock(box clock.clone() as Box < Clock + Send >);
+    node.set_metrics(box metrics.clone() as Box < Metrics + Send >);
+
+    clock.advance(Duration::milliseconds(300));
+
+    let mut proposed = false;
+
+    // single node only talks to itself
+    for _ in range(0, 10u) {
+        node.tick();
+
+        if node.state() == Leader && !proposed {
+            let (tx, _rx) = channel();
+            node.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(42) }), tx);
+            proposed = true;
+        }
+
+        // store and apply what the Ready says, like a driver would
+        let ready = node.ready();
+
+        for entry in ready.entries.iter() {
+            node.log_mut().enqueue(entry.entry.clone(), entry.term).unwrap();
+        }
+
+        match ready.committed_entries.last() {
+            Some(last) => node.log_mut().commit_upto(last.offset + 1).unwrap(),
+            None => (),
+        }
+
+        node.advance(&ready);
+
+        for (recipient, details) in ready.messages.into_iter() {
+            node.step(Pack("john".to_string(), recipient, details));
+        }
+    }
+
+    let text = render(&metrics);
+
+    assert_has_line(&text, "raft_term 1");
+    assert_has_line(&text, "raft_elections_started_total 1");
+    assert_has_line(&text, "raft_elections_won_total 1");
+    assert_has_line(&text, "raft_state_transitions_total{from=\"follower\",to=\"candidate\"} 1");
+    assert_has_line(&text, "raft_state_transitions_total{from=\"candidate\",to=\"leader\"} 1");
+    assert_has_line(&text, "raft_messages_sent_total{kind=\"vote\"} 1");
+    assert_has_line(&text, "raft_messages_received_total{kind=\"vote\"} 1");
+    assert_has_line(&text, "raft_entries_committed_total 3");
+    assert_has_line(&text, "raft_proposal_commit_latency_seconds_count 1");
+}
@@ -1,22 +1,62 @@
 extern crate raft_rs;
 
 mod helpers {
-    use raft_rs::node::{Node};
+    use raft_rs::node::{Node, NodeConfig};
     use raft_rs::intercommunication::{DefaultIntercommunication, Intercommunication, start};
     use raft_rs::replication::{DefaultReplicationLog, ReplicationLog, DefaultCommandContainer, DefaultReceivable, DefaultQuery};
+    use raft_rs::storage::MemoryHardStateStorage;
+    use raft_rs::simulation::SimulatedIntercommunication;
 
-    use std::{rand, num};
+    use std::{rand, os};
     use std::io::timer::sleep;
     use std::time::duration::Duration;
+    use std::default::Default;
 
     pub fn node() -> Node < DefaultCommandContainer, DefaultQuery, DefaultReceivable > {
         Node::new()
     }
 
+    pub type SimulatedComm = SimulatedIntercommunication < DefaultCommandContainer, DefaultIntercommunication < DefaultCommandContainer > >;
+
     pub fn comm() -> DefaultIntercommunication < DefaultCommandContainer > {
         Intercommunication::new()
     }
 
+    // every test runs in a task of its own
+    local_data_key!(test_seed: u32)
+
+    // Seed of the network and election timeouts of a test. RAFT_SEED reruns
+    // a failing test with the seed it printed; node tasks race, so that is
+    // no exact replay.
+    pub fn seed() -> u32 {
+        match test_seed.get() {
+            Some(seed) => return *seed,
+            None => (),
+        }
+
+        let seed = match os::getenv("RAFT_SEED").and_then(|seed| from_str(seed.as_slice())) {
+            Some(seed) => seed,
+            None => rand::random::< u32 >(),
+        };
+
+        println!("seed {}", seed);
+        test_seed.replace(Some(seed));
+        seed
+    }
+
+    pub fn simulated_comm() -> SimulatedComm {
+        SimulatedIntercommunication::with_seed(seed())
+    }
+
+    // node stands for election after one to two election timeouts
+    pub fn config(election_timeout: i64) -> NodeConfig {
+        NodeConfig {
+            min_election_timeout: Duration::milliseconds(election_timeout),
+            max_election_timeout: Duration::milliseconds(2 * election_timeout),
+            ..Default::default()
+        }
+    }
+
     pub fn start_comm < T: Intercommunication < DefaultCommandContainer > + Send >(comm: T) -> Sender < int > {
         start(comm)
     }
@@ -35,9 +75,23 @@ mod helpers {
     }
 
     pub fn node_start(node: &mut Node < DefaultCommandContainer, DefaultQuery, DefaultReceivable >, host: &str, comm: &mut DefaultIntercommunication < DefaultCommandContainer >) {
-        let mut log: DefaultReplicationLog = ReplicationLog::new();
-        let election_timeout = 150 + num::abs(rand::random::< i64 >() % 150);
-        node.start(host, comm, log, Duration::milliseconds(election_timeout));
+        let log: DefaultReplicationLog = ReplicationLog::new();
+        node_start_with_log(node, host, comm, log);
+    }
+
+    pub fn node_start_with_log(node: &mut Node < DefaultCommandContainer, DefaultQuery, DefaultReceivable >, host: &str, comm: &mut DefaultIntercommunication < DefaultCommandContainer >, log: DefaultReplicationLog) {
+        node_start_with_storage(node, host, comm, log, MemoryHardStateStorage::new());
+    }
+
+    pub fn node_start_simulated(node: &mut Node < DefaultCommandContainer, DefaultQuery, DefaultReceivable >, host: &str, comm: &mut SimulatedComm) {
+        let log: DefaultReplicationLog = ReplicationLog::new();
+        node.start(host, comm, log, MemoryHardStateStorage::new(), config(150)).unwrap();
+        node.set_seed(seed());
+    }
+
+    pub fn node_start_with_storage(node: &mut Node < DefaultCommandContainer, DefaultQuery, DefaultReceivable >, host: &str, comm: &mut DefaultIntercommunication < DefaultCommandContainer >, log: DefaultReplicationLog, storage: MemoryHardStateStorage) {
+        node.start(host, comm, log, storage, config(150)).unwrap();
+        node.set_seed(seed());
     }
 }
 
@@ -236,6 +290,8 @@ mod discovery {
             leader.forced_state(Leader);
             follower_1.force_follow("leader");
             node.introduce("john");
+            leader.add_voter("john");
+            leader.add_voter("sarah");
 
             helpers::sleep_ms(200);
 
@@ -293,6 +349,7 @@ mod election {
 
             leader.forced_state(Leader);
             node.introduce("leader");
+            leader.add_voter("john");
             node.force_follow("leader");
 
             helpers::sleep_ms(350);
@@ -464,6 +521,8 @@ mod election {
 
             node_2.introduce("john");
             node_3.introduce("john");
+            node_1.add_voter("sarah");
+            node_1.add_voter("james");
 
             helpers::sleep_ms(350);
 
@@ -502,6 +561,8 @@ mod election {
 
             node_2.introduce("john");
             node_3.introduce("john");
+            node_1.add_voter("sarah");
+            node_1.add_voter("james");
 
             helpers::sleep_ms(350);
 
@@ -528,6 +589,751 @@ mod election {
     }
 }
 
+mod pre_vote {
+
+    use helpers;
+    use raft_rs::node::{Node, Leader, Follower, NodeHost};
+    use raft_rs::intercommunication::{Intercommunication, Endpoint, Package, Pack, Ack, PreVote, PreVoteResponse, RequestVote};
+    use raft_rs::replication::{DefaultReplicationLog, ReplicationLog, DefaultCommandContainer, DefaultQuery, DefaultReceivable};
+    use raft_rs::storage::MemoryHardStateStorage;
+
+    fn start(node: &mut Node < DefaultCommandContainer, DefaultQuery, DefaultReceivable >, host: &str, comm: &mut helpers::SimulatedComm) {
+        let log: DefaultReplicationLog = ReplicationLog::new();
+        node.start(host, comm, log, MemoryHardStateStorage::new(), helpers::config(150)).unwrap();
+    }
+
+    fn wait_for_package(endpoint: &Endpoint < DefaultCommandContainer >) -> Option < Package < DefaultCommandContainer > > {
+        for _ in range(0, 50u) {
+            match endpoint.listen() {
+                Some(package) => return Some(package),
+                None => helpers::sleep_ms(10),
+            }
+        }
+
+        None
+    }
+
+    #[test]
+    fn term_is_not_increased_until_majority_agrees() {
+        let mut node = helpers::node();
+
+        helpers::with_proper_comm(|mut comm| {
+            helpers::node_start(&mut node, "john", &mut comm);
+            let other = comm.register("james".to_string());
+
+            let sig = helpers::start_comm(comm);
+
+            node.set_pre_vote(true);
+            other.send("john".to_string(), Ack);
+
+            match wait_for_package(&other) {
+                Some(Pack(_, _, PreVote(term, _, _))) => assert_eq!(1, term),
+                x => panic!("Should have asked for pre-vote first, got {}", x),
+            }
+
+            other.send("john".to_string(), PreVoteResponse(1, true));
+
+            match wait_for_package(&other) {
+                Some(Pack(_, _, RequestVote(term, _, _, _))) => assert_eq!(1, term),
+                x => panic!("Should have started election after pre-vote, got {}", x),
+            }
+
+            node.stop();
+
+            sig
+        })
+    }
+
+    #[test]
+    fn partitioned_node_does_not_disrupt_cluster_after_heal() {
+        let mut leader = helpers::node();
+        let mut follower = helpers::node();
+        let mut partitioned = helpers::node();
+
+        let mut comm = helpers::simulated_comm();
+        let network = comm.network.clone();
+
+        start(&mut leader, "leader", &mut comm);
+        start(&mut follower, "sarah", &mut comm);
+        start(&mut partitioned, "john", &mut comm);
+
+        let sig = helpers::start_comm(comm);
+
+        leader.set_pre_vote(true);
+        follower.set_pre_vote(true);
+        partitioned.set_pre_vote(true);
+
+        leader.forced_state(Leader);
+        follower.introduce("leader");
+        partitioned.introduce("leader");
+        leader.add_voter("sarah");
+        leader.add_voter("john");
+
+        helpers::sleep_ms(100);
+
+        network.partition(&["john"], &["leader", "sarah"]);
+
+        // several election timeouts pass on the other side of the partition
+        helpers::sleep_ms(1000);
+
+        assert_eq!(Leader, leader.state());
+
+        network.heal();
+
+        for _ in range(0, 10u) {
+            helpers::sleep_ms(50);
+            assert_eq!(Leader, leader.state());
+        }
+
+        assert_eq!(Follower, partitioned.state());
+        assert_eq!(Some(NodeHost { host: "leader".to_string() }), partitioned.fetch_leader());
+
+        leader.stop();
+        follower.stop();
+        partitioned.stop();
+
+        helpers::stop_comm(sig);
+    }
+}
+
+mod hard_state {
+
+    use helpers;
+    use raft_rs::intercommunication::{Intercommunication, Pack, RequestVote, Vote};
+    use raft_rs::replication::{DefaultReplicationLog, ReplicationLog};
+    use raft_rs::storage::MemoryHardStateStorage;
+
+    #[test]
+    fn vote_is_stored_before_it_is_sent() {
+        let mut node = helpers::node();
+        let storage = MemoryHardStateStorage::new();
+
+        helpers::with_proper_comm(|mut comm| {
+            let log: DefaultReplicationLog = ReplicationLog::new();
+            helpers::node_start_with_storage(&mut node, "john", &mut comm, log, storage.clone());
+            let candidate = comm.register("candidate".to_string());
+
+            let sig = helpers::start_comm(comm);
+
+            candidate.send("john".to_string(), RequestVote(5, 0, 0, false));
+
+            helpers::sleep_ms(30);
+
+            match candidate.listen() {
+                Some(Pack(_, _, Vote(5))) => (),
+                _ => panic!("Should have voted for candidate"),
+            }
+
+            assert_eq!(5, storage.state().term);
+            assert_eq!(Some("candidate".to_string()), storage.state().voted_for);
+
+            node.stop();
+
+            sig
+        })
+    }
+
+    #[test]
+    fn restarted_node_does_not_vote_twice_in_the_same_term() {
+        let mut node = helpers::node();
+        let mut restarted_node = helpers::node();
+        let storage = MemoryHardStateStorage::new();
+
+        helpers::with_proper_comm(|mut comm| {
+            let log: DefaultReplicationLog = ReplicationLog::new();
+            helpers::node_start_with_storage(&mut node, "john", &mut comm, log, storage.clone());
+            let candidate = comm.register("candidate".to_string());
+
+            let sig = helpers::start_comm(comm);
+
+            candidate.send("john".to_string(), RequestVote(5, 0, 0, false));
+
+            helpers::sleep_ms(30);
+
+            match candidate.listen() {
+                Some(Pack(_, _, Vote(5))) => (),
+                _ => panic!("Should have voted for candidate"),
+            }
+
+            node.stop();
+
+            sig
+        });
+
+        helpers::with_proper_comm(|mut comm| {
+            let log: DefaultReplicationLog = ReplicationLog::new();
+            helpers::node_start_with_storage(&mut restarted_node, "john", &mut comm, log, storage.clone());
+            let candidate = comm.register("candidate".to_string());
+            let other_candidate = comm.register("other-candidate".to_string());
+
+            let sig = helpers::start_comm(comm);
+
+            other_candidate.send("john".to_string(), RequestVote(5, 0, 0, false));
+
+            helpers::sleep_ms(30);
+
+            match other_candidate.listen() {
+                Some(Pack(_, _, Vote(_))) => panic!("Should have not voted twice in the same term"),
+                _ => (),
+            }
+
+            candidate.send("john".to_string(), RequestVote(5, 0, 0, false));
+
+            helpers::sleep_ms(30);
+
+            match candidate.listen() {
+                Some(Pack(_, _, Vote(5))) => (),
+                _ => panic!("Should have repeated its vote for the same candidate"),
+            }
+
+            restarted_node.stop();
+
+            sig
+        })
+    }
+}
+
+mod log_matching {
+
+    use helpers;
+    use raft_rs::intercommunication::{Intercommunication, Pack, AppendQuery, AppendLog, AppendLogEntry, AppendRejected, Persisted, RequestVote};
+    use raft_rs::replication::{DefaultReplicationLog, ReplicationLog, DefaultCommandContainer, TestSet, TestAdd, CommandEntry};
+
+    fn append_log(term: uint, prev_log_index: uint, prev_log_term: uint, entries: Vec < AppendLogEntry < DefaultCommandContainer > >) -> AppendLog < DefaultCommandContainer > {
+        AppendLog {
+            term: term,
+            prev_log_index: prev_log_index,
+            prev_log_term: prev_log_term,
+            committed_offset: 0,
+            entries: entries,
+        }
+    }
+
+    #[test]
+    fn follower_rejects_entries_past_the_end_of_its_log() {
+        let mut node = helpers::node();
+
+        helpers::with_proper_comm(|mut comm| {
+            helpers::node_start(&mut node, "john", &mut comm);
+            let leader = comm.register("leader".to_string());
+
+            let sig = helpers::start_comm(comm);
+
+            leader.send("john".to_string(), AppendQuery(append_log(0, 2, 0, vec![AppendLogEntry {
+                offset: 2,
+                term: 0,
+                entry: CommandEntry(DefaultCommandContainer { command: TestSet(2) }),
+            }])));
+
+            helpers::sleep_ms(30);
+
+            match leader.listen() {
+                Some(Pack(_, _, AppendRejected(_, _, log_len))) => assert_eq!(0, log_len),
+                _ => panic!("Should have rejected entry with missing predecessors"),
+            }
+
+            node.stop();
+
+            sig
+        })
+    }
+
+    #[test]
+    fn follower_rejects_append_log_from_stale_leader() {
+        let mut node = helpers::node();
+
+        helpers::with_proper_comm(|mut comm| {
+            helpers::node_start(&mut node, "john", &mut comm);
+            let leader = comm.register("leader".to_string());
+
+            let sig = helpers::start_comm(comm);
+
+            leader.send("john".to_string(), RequestVote(5, 0, 0, false));
+            helpers::sleep_ms(30);
+            leader.listen();
+
+            leader.send("john".to_string(), AppendQuery(append_log(3, 0, 0, vec![])));
+
+            helpers::sleep_ms(30);
+
+            match leader.listen() {
+                Some(Pack(_, _, AppendRejected(term, _, _))) => assert_eq!(5, term),
+                _ => panic!("Should have rejected stale leader"),
+            }
+
+            node.stop();
+
+            sig
+        })
+    }
+
+    #[test]
+    fn follower_truncates_conflicting_suffix() {
+        let mut node = helpers::node();
+        let mut log: DefaultReplicationLog = ReplicationLog::new();
+
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(2) }), 1);
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(3) }), 1);
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(9) }), 1);
+
+        helpers::with_proper_comm(|mut comm| {
+            helpers::node_start_with_log(&mut node, "john", &mut comm, log);
+            let leader = comm.register("leader".to_string());
+
+            let sig = helpers::start_comm(comm);
+
+            leader.send("john".to_string(), AppendQuery(append_log(2, 1, 1, vec![AppendLogEntry {
+                offset: 1,
+                term: 2,
+                entry: CommandEntry(DefaultCommandContainer { command: TestAdd(7) }),
+            }])));
+
+            helpers::sleep_ms(30);
+
+            match leader.listen() {
+                Some(Pack(_, _, Persisted(_, match_index))) => assert_eq!(2, match_index),
+                _ => panic!("Should have accepted entry from the leader"),
+            }
+
+            leader.send("john".to_string(), AppendQuery(append_log(2, 3, 1, vec![])));
+
+            helpers::sleep_ms(30);
+
+            match leader.listen() {
+                Some(Pack(_, _, AppendRejected(_, _, log_len))) => assert_eq!(2, log_len),
+                _ => panic!("Conflicting suffix should have been discarded"),
+            }
+
+            node.stop();
+
+            sig
+        })
+    }
+}
+
+mod snapshots {
+
+    use helpers;
+    use raft_rs::node::{Leader};
+    use raft_rs::replication::{DefaultCommandContainer, TestSet, TestAdd, DefaultReceivable, ReceivableInt, DefaultQuery};
+
+    #[test]
+    fn late_follower_catches_up_from_snapshot() {
+        let mut leader = helpers::node();
+        let mut follower = helpers::node();
+
+        helpers::with_proper_comm(|mut comm| {
+            helpers::node_start(&mut leader, "leader", &mut comm);
+            helpers::node_start(&mut follower, "john", &mut comm);
+
+            leader.forced_state(Leader);
+
+            let sig = helpers::start_comm(comm);
+
+            leader.enqueue(DefaultCommandContainer { command: TestSet(2) });
+            leader.enqueue(DefaultCommandContainer { command: TestAdd(3) });
+            leader.enqueue(DefaultCommandContainer { command: TestSet(9) });
+
+            helpers::sleep_ms(50);
+
+            leader.compact();
+
+            helpers::sleep_ms(20);
+
+            follower.introduce("leader");
+            leader.add_voter("john");
+
+            helpers::sleep_ms(500);
+
+            let (tx, rx): (Sender < DefaultReceivable >, Receiver < DefaultReceivable >) = channel();
+
+            follower.query(DefaultQuery, &tx);
+            helpers::sleep_ms(100);
+            assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
+
+            leader.stop();
+            follower.stop();
+
+            sig
+        })
+    }
+}
+
+mod batching {
+
+    use helpers;
+    use raft_rs::node::{Leader, AppendLimits};
+    use raft_rs::intercommunication::{Intercommunication, Endpoint, Pack, Ack, AppendQuery, AppendRejected, Persisted};
+    use raft_rs::replication::{DefaultCommandContainer, TestSet};
+
+    fn received_batches(follower: &Endpoint < DefaultCommandContainer >) -> Vec < Vec < uint > > {
+        let mut batches = vec![];
+
+        loop {
+            match follower.listen() {
+                Some(Pack(_, _, AppendQuery(log))) => {
+                    if !log.entries.is_empty() {
+                        batches.push(log.entries.iter().map(|x| { x.offset }).collect());
+                    }
+                },
+                Some(_) => (),
+                None => break,
+            }
+        }
+
+        batches
+    }
+
+    #[test]
+    fn leader_batches_entries_and_waits_for_acknowledgements() {
+        let mut leader = helpers::node();
+
+        helpers::with_proper_comm(|mut comm| {
+            helpers::node_start(&mut leader, "leader", &mut comm);
+            let follower = comm.register("john".to_string());
+
+            let sig = helpers::start_comm(comm);
+
+            // john is discovered before leader writes the initial
+            // configuration, that one entry takes offset 0
+            follower.send("leader".to_string(), Ack);
+            helpers::sleep_ms(20);
+
+            leader.forced_state(Leader);
+            leader.set_append_limits(AppendLimits { max_entries: 2, max_bytes: 1024 * 1024, max_inflight: 1 });
+
+            helpers::sleep_ms(20);
+
+            for i in range(0, 5i) {
+                leader.enqueue(DefaultCommandContainer { command: TestSet(i) });
+            }
+
+            helpers::sleep_ms(30);
+
+            assert_eq!(vec![vec![1u]], received_batches(&follower));
+
+            follower.send("leader".to_string(), Persisted(0, 2));
+
+            helpers::sleep_ms(30);
+
+            assert_eq!(vec![vec![2u, 3]], received_batches(&follower));
+
+            follower.send("leader".to_string(), Persisted(0, 4));
+
+            helpers::sleep_ms(30);
+
+            assert_eq!(vec![vec![4u, 5]], received_batches(&follower));
+
+            leader.stop();
+
+            sig
+        })
+    }
+
+    #[test]
+    fn leader_keeps_several_batches_in_flight() {
+        let mut leader = helpers::node();
+
+        helpers::with_proper_comm(|mut comm| {
+            helpers::node_start(&mut leader, "leader", &mut comm);
+            let follower = comm.register("john".to_string());
+
+            let sig = helpers::start_comm(comm);
+
+            // john is discovered before leader writes the initial
+            // configuration, that one entry takes offset 0
+            follower.send("leader".to_string(), Ack);
+            helpers::sleep_ms(20);
+
+            leader.forced_state(Leader);
+            leader.set_append_limits(AppendLimits { max_entries: 2, max_bytes: 1024 * 1024, max_inflight: 2 });
+
+            helpers::sleep_ms(20);
+
+            for i in range(0, 5i) {
+                leader.enqueue(DefaultCommandContainer { command: TestSet(i) });
+            }
+
+            helpers::sleep_ms(30);
+
+            // nothing else is sent until the probe is acknowledged
+            assert_eq!(vec![vec![1u]], received_batches(&follower));
+
+            follower.send("leader".to_string(), Persisted(0, 2));
+
+            helpers::sleep_ms(30);
+
+            assert_eq!(vec![vec![2u, 3], vec![4u, 5]], received_batches(&follower));
+
+            leader.stop();
+
+            sig
+        })
+    }
+
+    #[test]
+    fn rejected_follower_receives_missing_entries_again() {
+        let mut leader = helpers::node();
+
+        helpers::with_proper_comm(|mut comm| {
+            helpers::node_start(&mut leader, "leader", &mut comm);
+            let follower = comm.register("john".to_string());
+
+            let sig = helpers::start_comm(comm);
+
+            follower.send("leader".to_string(), Ack);
+            helpers::sleep_ms(20);
+
+            leader.forced_state(Leader);
+
+            helpers::sleep_ms(20);
+
+            for i in range(0, 3i) {
+                leader.enqueue(DefaultCommandContainer { command: TestSet(i) });
+            }
+
+            helpers::sleep_ms(30);
+
+            assert!(!received_batches(&follower).is_empty());
+
+            follower.send("leader".to_string(), AppendRejected(0, 1, 0));
+
+            helpers::sleep_ms(30);
+
+            assert_eq!(vec![vec![0u, 1, 2, 3]], received_batches(&follower));
+
+            leader.stop();
+
+            sig
+        })
+    }
+}
+
+mod commitment {
+
+    use helpers;
+    use raft_rs::node::{Leader};
+    use raft_rs::intercommunication::{Intercommunication, Persisted};
+    use raft_rs::replication::{DefaultReplicationLog, ReplicationLog, DefaultCommandContainer, TestSet, TestAdd, CommandEntry, ConfigurationEntry};
+    use raft_rs::membership::Configuration;
+    use raft_rs::storage::{HardState, HardStateStorage, MemoryHardStateStorage};
+
+    fn log_with_voters(voters: Vec < &str >, term: uint) -> DefaultReplicationLog {
+        let mut log: DefaultReplicationLog = ReplicationLog::new();
+        let configuration = Configuration::new(voters.iter().map(|x| { x.to_string() }).collect());
+
+        log.enqueue(ConfigurationEntry(configuration), term);
+
+        log
+    }
+
+    #[test]
+    fn entries_from_previous_terms_are_not_committed_by_counting_replicas() {
+        let mut leader = helpers::node();
+        let mut storage = MemoryHardStateStorage::new();
+        let mut log = log_with_voters(vec!["leader", "john"], 1);
+
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(2) }), 1);
+        log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(3) }), 1);
+        storage.save(&HardState { term: 2, voted_for: None, commit: 0 }).unwrap();
+
+        helpers::with_proper_comm(|mut comm| {
+            helpers::node_start_with_storage(&mut leader, "leader", &mut comm, log, storage.clone());
+            let follower = comm.register("john".to_string());
+
+            let sig = helpers::start_comm(comm);
+
+            leader.forced_state(Leader);
+
+            helpers::sleep_ms(20);
+
+            follower.send("leader".to_string(), Persisted(2, 3));
+
+            helpers::sleep_ms(30);
+
+            assert_eq!(0, storage.state().commit);
+
+            // entry of the current term commits everything before it at once
+            leader.enqueue(DefaultCommandContainer { command: TestAdd(5) });
+
+            helpers::sleep_ms(30);
+
+            follower.send("leader".to_string(), Persisted(2, 4));
+
+            helpers::sleep_ms(30);
+
+            assert_eq!(4, storage.state().commit);
+
+            leader.stop();
+
+            sig
+        })
+    }
+
+    #[test]
+    fn commit_needs_a_majority_of_the_cluster() {
+        let mut leader = helpers::node();
+        let storage = MemoryHardStateStorage::new();
+
+        helpers::with_proper_comm(|mut comm| {
+            let log = log_with_voters(vec!["leader", "sarah", "john", "bob"], 0);
+            helpers::node_start_with_storage(&mut leader, "leader", &mut comm, log, storage.clone());
+            let sarah = comm.register("sarah".to_string());
+            let john = comm.register("john".to_string());
+            comm.register("bob".to_string());
+
+            let sig = helpers::start_comm(comm);
+
+            leader.forced_state(Leader);
+
+            helpers::sleep_ms(20);
+
+            for i in range(0, 4i) {
+                leader.enqueue(DefaultCommandContainer { command: TestSet(i) });
+            }
+
+            helpers::sleep_ms(30);
+
+            sarah.send("leader".to_string(), Persisted(0, 5));
+
+            helpers::sleep_ms(30);
+
+            assert_eq!(0, storage.state().commit);
+
+            john.send("leader".to_string(), Persisted(0, 4));
+
+            helpers::sleep_ms(30);
+
+            assert_eq!(4, storage.state().commit);
+
+            leader.stop();
+
+            sig
+        })
+    }
+}
+
+mod simulated_network {
+
+    use helpers;
+    use raft_rs::node::{Node, Leader, Follower, Committed, NodeHost};
+    use raft_rs::replication::{DefaultCommandContainer, TestSet, DefaultQuery, DefaultReceivable, ReceivableInt};
+
+    use std::time::duration::Duration;
+
+    fn leader(nodes: &Vec < Node < DefaultCommandContainer, DefaultQuery, DefaultReceivable > >) -> Option < uint > {
+        range(0, nodes.len()).find(|&i| nodes[i].state() == Leader)
+    }
+
+    // proposal may fail while leadership moves, then it is proposed again
+    fn commit(nodes: &Vec < Node < DefaultCommandContainer, DefaultQuery, DefaultReceivable > >, value: int) {
+        for _ in range(0, 50u) {
+            match leader(nodes) {
+                Some(i) => match nodes[i].enqueue(DefaultCommandContainer { command: TestSet(value) }).recv() {
+                    Ok(Committed { .. }) => return,
+                    Err(_) => (),
+                },
+                None => (),
+            }
+
+            helpers::sleep_ms(50);
+        }
+
+        panic!("{} has not been committed", value)
+    }
+
+    fn start_cluster(hosts: &[&str], comm: &mut helpers::SimulatedComm) -> Vec < Node < DefaultCommandContainer, DefaultQuery, DefaultReceivable > > {
+        let mut nodes = vec![];
+
+        for host in hosts.iter() {
+            let mut node = helpers::node();
+            helpers::node_start_simulated(&mut node, *host, comm);
+            nodes.push(node);
+        }
+
+        nodes
+    }
+
+    #[test]
+    fn cluster_commits_over_lossy_network() {
+        let hosts = ["leader", "sarah", "john"];
+        let mut comm = helpers::simulated_comm();
+        let network = comm.network.clone();
+        let mut nodes = start_cluster(&hosts, &mut comm);
+
+        let sig = helpers::start_comm(comm);
+
+        nodes[0].forced_state(Leader);
+        nodes[1].introduce("leader");
+        nodes[2].introduce("leader");
+        nodes[0].add_voter(hosts[1]);
+        nodes[0].add_voter(hosts[2]);
+
+        helpers::sleep_ms(100);
+
+        network.set_drop_rate(0.05);
+        network.set_duplicate_rate(0.1);
+        network.set_latency(Duration::zero(), Duration::milliseconds(10));
+
+        for value in range(1, 6i) {
+            commit(&nodes, value);
+        }
+
+        let (tx, rx): (Sender < DefaultReceivable >, Receiver < DefaultReceivable >) = channel();
+
+        let i = leader(&nodes).unwrap();
+        nodes[i].query(DefaultQuery, &tx);
+        assert_eq!(ReceivableInt(5), rx.recv());
+
+        for node in nodes.iter_mut() {
+            node.stop();
+        }
+
+        helpers::stop_comm(sig);
+    }
+
+    #[test]
+    fn partitioned_leader_is_replaced_and_rejoins_as_follower() {
+        let hosts = ["leader", "sarah", "john"];
+        let mut comm = helpers::simulated_comm();
+        let network = comm.network.clone();
+        let mut nodes = start_cluster(&hosts, &mut comm);
+
+        let sig = helpers::start_comm(comm);
+
+        nodes[0].forced_state(Leader);
+        nodes[1].introduce("leader");
+        nodes[2].introduce("leader");
+        nodes[0].add_voter(hosts[1]);
+        nodes[0].add_voter(hosts[2]);
+
+        commit(&nodes, 1);
+
+        network.partition(&["leader"], &["sarah", "john"]);
+
+        // several election timeouts pass on the majority side
+        helpers::sleep_ms(1000);
+
+        let new_leader = match (nodes[1].state(), nodes[2].state()) {
+            (Leader, _) => "sarah",
+            (_, Leader) => "john",
+            x => panic!("Majority should have elected a leader, got {}", x),
+        };
+
+        network.heal();
+        helpers::sleep_ms(500);
+
+        assert_eq!(Follower, nodes[0].state());
+        assert_eq!(Some(NodeHost { host: new_leader.to_string() }), nodes[0].fetch_leader());
+
+        for node in nodes.iter_mut() {
+            node.stop();
+        }
+
+        helpers::stop_comm(sig);
+    }
+}
+
 mod replication {
 
     use helpers;
@@ -549,6 +1355,8 @@ mod replication {
 
             follower_1.introduce("leader");
             follower_2.introduce("leader");
+            node.add_voter("sarah");
+            node.add_voter("john");
 
             let sig = helpers::start_comm(comm);
 
@@ -567,38 +1375,38 @@ mod replication {
 
             node.query(DefaultQuery, &tx);
             helpers::sleep_ms(30);
-            assert_eq!(ReceivableInt(2), rx.try_recv().unwrap());
+            assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             node.query(DefaultQuery, &tx);
             helpers::sleep_ms(30);
-            assert_eq!(ReceivableInt(5), rx.try_recv().unwrap());
+            assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             node.query(DefaultQuery, &tx);
             helpers::sleep_ms(30);
             assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             follower_1.query(DefaultQuery, &tx);
-            helpers::sleep_ms(30);
-            assert_eq!(ReceivableInt(2), rx.try_recv().unwrap());
+            helpers::sleep_ms(100);
+            assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             follower_1.query(DefaultQuery, &tx);
-            helpers::sleep_ms(30);
-            assert_eq!(ReceivableInt(5), rx.try_recv().unwrap());
+            helpers::sleep_ms(100);
+            assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             follower_1.query(DefaultQuery, &tx);
-            helpers::sleep_ms(30);
+            helpers::sleep_ms(100);
             assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             follower_2.query(DefaultQuery, &tx);
-            helpers::sleep_ms(30);
-            assert_eq!(ReceivableInt(2), rx.try_recv().unwrap());
+            helpers::sleep_ms(100);
+            assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             follower_2.query(DefaultQuery, &tx);
-            helpers::sleep_ms(30);
-            assert_eq!(ReceivableInt(5), rx.try_recv().unwrap());
+            helpers::sleep_ms(100);
+            assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             follower_2.query(DefaultQuery, &tx);
-            helpers::sleep_ms(30);
+            helpers::sleep_ms(100);
             assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             node.stop();
@@ -624,6 +1432,8 @@ mod replication {
 
             follower_1.introduce("leader");
             follower_2.introduce("leader");
+            node.add_voter("sarah");
+            node.add_voter("john");
 
             let sig = helpers::start_comm(comm);
 
@@ -646,26 +1456,26 @@ mod replication {
 
             node.query(DefaultQuery, &tx);
             helpers::sleep_ms(30);
-            assert_eq!(ReceivableInt(2), rx.try_recv().unwrap());
+            assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             node.query(DefaultQuery, &tx);
             helpers::sleep_ms(30);
-            assert_eq!(ReceivableInt(5), rx.try_recv().unwrap());
+            assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             node.query(DefaultQuery, &tx);
             helpers::sleep_ms(30);
             assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             follower_2.query(DefaultQuery, &tx);
-            helpers::sleep_ms(30);
-            assert_eq!(ReceivableInt(2), rx.try_recv().unwrap());
+            helpers::sleep_ms(100);
+            assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             follower_2.query(DefaultQuery, &tx);
-            helpers::sleep_ms(30);
-            assert_eq!(ReceivableInt(5), rx.try_recv().unwrap());
+            helpers::sleep_ms(100);
+            assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             follower_2.query(DefaultQuery, &tx);
-            helpers::sleep_ms(30);
+            helpers::sleep_ms(100);
             assert_eq!(ReceivableInt(9), rx.try_recv().unwrap());
 
             node.stop();
@@ -690,6 +1500,8 @@ mod replication {
 
             follower_1.introduce("leader");
             follower_2.introduce("leader");
+            node.add_voter("sarah");
+            node.add_voter("john");
 
             let sig = helpers::start_comm(comm);
 
@@ -724,3 +1536,53 @@ mod replication {
         })
     }
 }
+
+mod config {
+
+    use helpers;
+    use raft_rs::node::{NodeConfig, InvalidHeartbeatInterval, InvalidElectionTimeout, InvalidAppendLimits};
+    use raft_rs::replication::{DefaultReplicationLog, ReplicationLog};
+    use raft_rs::storage::MemoryHardStateStorage;
+
+    use std::time::duration::Duration;
+    use std::default::Default;
+
+    #[test]
+    fn default_config_is_valid() {
+        let config: NodeConfig = Default::default();
+        assert_eq!(Ok(()), config.validate());
+    }
+
+    #[test]
+    fn heartbeat_has_to_be_well_below_election_timeout() {
+        let config = NodeConfig { heartbeat_interval: Duration::milliseconds(100), ..helpers::config(150) };
+        assert_eq!(Err(InvalidHeartbeatInterval), config.validate());
+
+        let config = NodeConfig { heartbeat_interval: Duration::zero(), ..helpers::config(150) };
+        assert_eq!(Err(InvalidHeartbeatInterval), config.validate());
+    }
+
+    #[test]
+    fn election_timeout_range_can_not_be_empty() {
+        let config = NodeConfig { max_election_timeout: Duration::milliseconds(100), ..helpers::config(150) };
+        assert_eq!(Err(InvalidElectionTimeout), config.validate());
+    }
+
+    #[test]
+    fn append_limits_have_to_be_positive() {
+        let mut config = helpers::config(150);
+        config.append_limits.max_inflight = 0;
+
+        assert_eq!(Err(InvalidAppendLimits), config.validate());
+    }
+
+    #[test]
+    fn node_does_not_start_with_invalid_config() {
+        let mut node = helpers::node();
+        let mut comm = helpers::comm();
+        let log: DefaultReplicationLog = ReplicationLog::new();
+        let config = NodeConfig { heartbeat_interval: Duration::milliseconds(100), ..helpers::config(150) };
+
+        assert_eq!(Err(InvalidHeartbeatInterval), node.start("john", &mut comm, log, MemoryHardStateStorage::new(), config));
+    }
+}
@@ -0,0 +1,112 @@
+extern crate raft_rs;
+
+use raft_rs::progress::{Progress, Probe, Replicate};
+
+#[test]
+fn new_follower_is_probed_one_append_at_a_time() {
+    let mut progress = Progress::new(5);
+
+    assert_eq!(Probe, progress.state);
+    assert!(!progress.is_paused(4));
+
+    progress.sent(7);
+
+    assert!(progress.is_paused(4));
+    assert_eq!(5, progress.next_index);
+}
+
+#[test]
+fn acknowledged_probe_switches_to_replication() {
+    let mut progress = Progress::new(5);
+    progress.sent(7);
+
+    assert!(progress.acknowledged(7));
+
+    assert_eq!(Replicate, progress.state);
+    assert_eq!(7, progress.match_index);
+    assert_eq!(7, progress.next_index);
+    assert!(!progress.is_paused(4));
+}
+
+#[test]
+fn replication_is_limited_by_inflight_appends() {
+    let mut progress = Progress::new(0);
+    progress.acknowledged(0);
+
+    progress.sent(2);
+    progress.sent(4);
+
+    assert_eq!(4, progress.next_index);
+    assert_eq!(2, progress.inflight());
+    assert!(progress.is_paused(2));
+
+    progress.acknowledged(2);
+
+    assert_eq!(1, progress.inflight());
+    assert!(!progress.is_paused(2));
+
+    // acknowledgement of the later append covers the earlier ones as well
+    progress.sent(6);
+    progress.acknowledged(6);
+
+    assert_eq!(0, progress.inflight());
+    assert_eq!(6, progress.match_index);
+}
+
+#[test]
+fn rejection_backs_off_to_follower_log_end() {
+    let mut progress = Progress::new(10);
+    progress.sent(10);
+
+    assert!(progress.rejected(10, 4));
+
+    assert_eq!(Probe, progress.state);
+    assert_eq!(4, progress.next_index);
+    assert!(!progress.is_paused(4));
+}
+
+#[test]
+fn rejection_never_backs_off_past_matched_entries() {
+    let mut progress = Progress::new(0);
+    progress.acknowledged(3);
+    progress.sent(6);
+
+    assert!(progress.rejected(6, 1));
+
+    assert_eq!(Probe, progress.state);
+    assert_eq!(3, progress.next_index);
+}
+
+#[test]
+fn stale_rejections_are_ignored() {
+    let mut progress = Progress::new(10);
+    progress.sent(10);
+    progress.rejected(10, 4);
+    progress.sent(6);
+
+    // rejection of the probe at 10 arriving once again
+    assert!(!progress.rejected(10, 4));
+    assert_eq!(4, progress.next_index);
+    assert!(progress.is_paused(4));
+
+    progress.acknowledged(6);
+    progress.sent(8);
+
+    assert!(!progress.rejected(2, 2));
+    assert_eq!(Replicate, progress.state);
+}
+
+#[test]
+fn stale_rejection_keeps_replication_going() {
+    let mut progress = Progress::new(0);
+    progress.acknowledged(5);
+    progress.sent(7);
+    progress.sent(9);
+
+    // heartbeat sent before entries up to 5 were acknowledged
+    assert!(!progress.rejected(3, 3));
+
+    assert_eq!(Replicate, progress.state);
+    assert_eq!(9, progress.next_index);
+    assert_eq!(2, progress.inflight());
+}
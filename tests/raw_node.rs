@@ -0,0 +1,1333 @@
+extern crate raft_rs;
+
+use raft_rs::raw_node::{RawNode, Ready, MAX_APPLY_ATTEMPTS};
+use raft_rs::node::{Follower, Candidate, Leader, NodeConfig, LeaseRead, NodeHost, Committed, ProposalError, NotLeader, ProposalDropped, LeadershipLost, EntryOverwritten, SessionExpired, Transferred, TransferTimedOut, TransferRejected};
+use raft_rs::intercommunication::{Pack, PackageDetails, AppendQuery, AppendLog, AppendLogEntry, AppendRejected, Ack, Persisted, RequestVote, Vote, Heartbeat, HeartbeatResponse, ReadIndexRequest, ReadIndexResponse, Propose, ProposalAccepted, ProposalRejected};
+use raft_rs::replication::{DefaultReplicationLog, ReplicationLog, DefaultCommandContainer, DefaultQuery, DefaultReceivable, ReceivableInt, CommandEntry, NoopEntry, RegisterSessionEntry, SessionCommandEntry, TestSet, TestAdd};
+use raft_rs::session::SessionCommand;
+use raft_rs::membership::{MembershipChange, AddVoter, RemoveVoter};
+use raft_rs::storage::HardState;
+use raft_rs::clock::{Clock, ManualClock};
+
+use std::io;
+use std::cmp;
+use std::time::duration::Duration;
+use std::default::Default;
+
+type TestNode = RawNode < DefaultCommandContainer, DefaultReplicationLog, DefaultQuery, DefaultReceivable >;
+
+fn raw_node(host: &str, election_timeout: i64, clock: &ManualClock) -> TestNode {
+    raw_node_with_log(host, election_timeout, ReplicationLog::new(), clock)
+}
+
+fn raw_node_with_log(host: &str, election_timeout: i64, log: DefaultReplicationLog, clock: &ManualClock) -> TestNode {
+    let config = NodeConfig {
+        min_election_timeout: Duration::milliseconds(election_timeout),
+        max_election_timeout: Duration::milliseconds(2 * election_timeout),
+        ..Default::default()
+    };
+
+    let mut node = RawNode::new(host, log, HardState::new(), config);
+    node.set_clock(box clock.clone() as Box < Clock + Send >);
+    node.set_seed(0);
+    node
+}
+
+// Does what the Ready says the way a driver would, hard state counts as
+// stored right away
+fn handle(node: &mut TestNode) -> Ready < DefaultCommandContainer > {
+    let ready = node.ready();
+
+    match ready.snapshot {
+        Some(ref snapshot) => node.log_mut().install_snapshot(snapshot.clone()).unwrap(),
+        None => (),
+    }
+
+    if !ready.entries.is_empty() {
+        node.log_mut().discard_downto(ready.entries[0].offset).unwrap();
+    }
+
+    for entry in ready.entries.iter() {
+        node.log_mut().enqueue(entry.entry.clone(), entry.term).unwrap();
+    }
+
+    let applied = match ready.committed_entries.last() {
+        Some(last) => node.log_mut().commit_upto(last.offset + 1),
+        None => Ok(()),
+    };
+
+    node.advance(&ready);
+
+    match applied {
+        Ok(_) => (),
+        Err(error) => node.apply_failed(error).unwrap(),
+    }
+
+    ready
+}
+
+// Handles Ready until there is nothing left, returns the messages and the
+// committed entries
+fn drain(node: &mut TestNode) -> (Vec < (String, PackageDetails < DefaultCommandContainer > ) >, Vec < AppendLogEntry < DefaultCommandContainer > >) {
+    let mut messages = vec![];
+    let mut committed = vec![];
+
+    for _ in range(0, 10u) {
+        if !node.has_ready() {
+            break;
+        }
+
+        let ready = handle(node);
+        messages.extend(ready.messages.into_iter());
+        committed.extend(ready.committed_entries.into_iter());
+    }
+
+    (messages, committed)
+}
+
+// Ticks the nodes and hands every message to its recipient until nobody has
+// anything to say. Returns what every node committed meanwhile.
+fn settle(nodes: &mut Vec < TestNode >, hosts: &[&str]) -> Vec < Vec < AppendLogEntry < DefaultCommandContainer > > > {
+    let mut committed: Vec < Vec < AppendLogEntry < DefaultCommandContainer > > > = hosts.iter().map(|_| vec![]).collect();
+
+    for _ in range(0, 100u) {
+        let mut packages = vec![];
+
+        for (i, node) in nodes.iter_mut().enumerate() {
+            node.tick();
+
+            let (messages, entries) = drain(node);
+            committed.get_mut(i).extend(entries.into_iter());
+
+            for (recipient, details) in messages.into_iter() {
+                packages.push(Pack(hosts[i].to_string(), recipient, details));
+            }
+        }
+
+        if packages.is_empty() {
+            break;
+        }
+
+        for package in packages.into_iter() {
+            let recipient = match package {
+                Pack(_, ref to, _) => hosts.iter().position(|host| *host == to.as_slice()),
+            };
+
+            match recipient {
+                Some(i) => nodes.get_mut(i).step(package),
+                None => (),
+            }
+        }
+    }
+
+    committed
+}
+
+// Ticks the node and returns what it sends
+fn tick(node: &mut TestNode) -> Vec < (String, PackageDetails < DefaultCommandContainer > ) > {
+    node.tick();
+
+    let (messages, _) = drain(node);
+    messages
+}
+
+fn latest_heartbeat_round(messages: Vec < (String, PackageDetails < DefaultCommandContainer > ) >) -> Option < uint > {
+    messages.into_iter().filter_map(|(_, details)| match details {
+        Heartbeat(_, round) => Some(round),
+        _ => None,
+    }).last()
+}
+
+fn read_index_requests(messages: Vec < (String, PackageDetails < DefaultCommandContainer > ) >) -> Vec < (String, uint) > {
+    messages.into_iter().filter_map(|(recipient, details)| match details {
+        ReadIndexRequest(read_id) => Some((recipient, read_id)),
+        _ => None,
+    }).collect()
+}
+
+// john leads sarah, who is played by the test, and has committed the
+// initial configuration
+fn leader_of_two(clock: &ManualClock) -> TestNode {
+    let mut node = raw_node("john", 150, clock);
+
+    node.step(Pack("sarah".to_string(), "john".to_string(), Ack));
+    node.force_state(Leader);
+    tick(&mut node);
+
+    node.step(Pack("sarah".to_string(), "john".to_string(), Persisted(0, 1)));
+    tick(&mut node);
+
+    node
+}
+
+// Leader of two that answered a heartbeat round at once, its read lease lasts
+// 100ms from now
+fn take_read_lease(node: &mut TestNode, clock: &ManualClock) {
+    node.set_read_consistency(LeaseRead(Duration::milliseconds(50)));
+
+    // heartbeat rounds go out with regular heartbeats
+    clock.advance(Duration::milliseconds(80));
+    let round = latest_heartbeat_round(tick(node)).expect("Leader should have sent a heartbeat round");
+
+    node.step(Pack("sarah".to_string(), "john".to_string(), HeartbeatResponse(0, round)));
+    tick(node);
+}
+
+// Nodes introduced to each other, the first one wins the first election in
+// term 1 and every node has the initial configuration committed
+fn elected_cluster(hosts: &[&str], clock: &ManualClock) -> Vec < TestNode > {
+    let mut nodes: Vec < TestNode > = hosts.iter().enumerate()
+        .map(|(i, host)| raw_node(*host, if i == 0 { 150 } else { 300 }, clock))
+        .collect();
+
+    for i in range(0, hosts.len()) {
+        for host in hosts.iter() {
+            if *host != hosts[i] {
+                nodes.get_mut(i).introduce(host.to_string());
+            }
+        }
+    }
+
+    settle(&mut nodes, hosts);
+    clock.advance(Duration::milliseconds(300));
+    settle(&mut nodes, hosts);
+
+    nodes
+}
+
+// follower of the leader played by the test, relaying proposals to it
+fn forwarding_follower(clock: &ManualClock) -> TestNode {
+    let mut node = raw_node("john", 150, clock);
+    node.set_forward_proposals(true);
+    node.force_follow(Some(NodeHost { host: "leader".to_string() }));
+    tick(&mut node);
+
+    node
+}
+
+fn session_command(client_id: uint, sequence: uint, command: DefaultCommandContainer) -> SessionCommand < DefaultCommandContainer > {
+    SessionCommand {
+        client_id: client_id,
+        sequence: sequence,
+        timestamp: 0,
+        command: command,
+    }
+}
+
+#[test]
+fn single_node_elects_itself_after_election_timeout() {
+    let clock = ManualClock::new();
+    let hosts = ["john"];
+    let mut nodes = vec![raw_node("john", 150, &clock)];
+
+    settle(&mut nodes, &hosts);
+    assert_eq!(Follower, nodes[0].state());
+
+    clock.advance(Duration::milliseconds(300));
+    settle(&mut nodes, &hosts);
+
+    assert_eq!(Leader, nodes[0].state());
+}
+
+#[test]
+fn cluster_commits_proposal() {
+    let clock = ManualClock::new();
+    let hosts = ["john", "sarah", "alice"];
+    let mut nodes = elected_cluster(&hosts, &clock);
+
+    assert_eq!(Leader, nodes[0].state());
+    assert_eq!(Follower, nodes[1].state());
+    assert_eq!(Follower, nodes[2].state());
+
+    let index = nodes.get_mut(0).propose(CommandEntry(DefaultCommandContainer { command: TestSet(42) })).unwrap();
+    let committed = settle(&mut nodes, &hosts);
+
+    for entries in committed.iter() {
+        let entry = entries.iter().find(|x| x.offset == index).unwrap();
+        assert_eq!(CommandEntry(DefaultCommandContainer { command: TestSet(42) }), entry.entry);
+    }
+}
+
+#[test]
+fn hard_state_is_ready_until_advanced() {
+    let clock = ManualClock::new();
+    let mut node = raw_node("john", 150, &clock);
+
+    clock.advance(Duration::milliseconds(300));
+
+    // follower becomes a candidate, then votes for itself
+    node.tick();
+    node.tick();
+
+    let expected = HardState { term: 1, voted_for: Some("john".to_string()), commit: 0 };
+
+    assert_eq!(Some(expected.clone()), node.ready().hard_state);
+
+    let ready = node.ready();
+    assert_eq!(Some(expected), ready.hard_state);
+
+    node.advance(&ready);
+    assert_eq!(None, node.ready().hard_state);
+}
+
+#[test]
+fn ready_is_handed_out_again_until_advanced() {
+    let clock = ManualClock::new();
+    let mut node = leader_of_two(&clock);
+
+    let (tx, _rx) = channel();
+    node.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(4) }), tx);
+    node.tick();
+
+    // driver failed to store the entry and did not advance
+    let failed = node.ready();
+    assert_eq!(1, failed.entries.len());
+
+    let ready = node.ready();
+    assert_eq!(failed.entries, ready.entries);
+    assert_eq!(failed.messages, ready.messages);
+    assert_eq!(ready.entries[0].offset, node.log().len());
+
+    handle(&mut node);
+    assert!(node.ready().entries.is_empty());
+    assert_eq!(ready.entries[0].offset + 1, node.log().len());
+}
+
+#[test]
+fn leader_commits_entries_it_stored_only() {
+    let clock = ManualClock::new();
+    let mut node = leader_of_two(&clock);
+    let committed = node.log().committed_offset();
+
+    let (tx, rx) = channel();
+    node.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(4) }), tx);
+    node.tick();
+    let index = node.ready().entries[0].offset;
+
+    // sarah has the entry, john has not stored it yet
+    node.step(Pack("sarah".to_string(), "john".to_string(), Persisted(0, index + 1)));
+    node.tick();
+
+    let ready = node.ready();
+    assert!(ready.committed_entries.is_empty());
+    assert_eq!(committed, node.log().committed_offset());
+
+    handle(&mut node);
+    let ready = handle(&mut node);
+    assert_eq!(vec![index], ready.committed_entries.iter().map(|entry| entry.offset).collect::< Vec < uint > >());
+    assert!(rx.try_recv().is_ok());
+}
+
+#[test]
+fn entry_failing_to_apply_every_time_stops_the_node() {
+    let clock = ManualClock::new();
+    let mut node = leader_of_two(&clock);
+
+    let (tx, rx) = channel();
+    node.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(4) }), tx);
+    node.tick();
+    let index = handle(&mut node).entries[0].offset;
+
+    node.step(Pack("sarah".to_string(), "john".to_string(), Persisted(0, index + 1)));
+    node.tick();
+
+    // state machine fails the entry whenever it gets it
+    let mut attempts = 0u;
+
+    loop {
+        let ready = node.ready();
+        assert_eq!(vec![index], ready.committed_entries.iter().map(|entry| entry.offset).collect::< Vec < uint > >());
+
+        node.advance(&ready);
+        attempts += 1;
+
+        match node.apply_failed(io::standard_error(io::OtherIoError)) {
+            Ok(_) => assert!(attempts < MAX_APPLY_ATTEMPTS),
+            Err(_) => break,
+        }
+    }
+
+    assert_eq!(MAX_APPLY_ATTEMPTS, attempts);
+    assert_eq!(index, node.log().committed_offset());
+
+    match rx.try_recv() {
+        Ok(Ok(Committed { applied: Err(_), .. })) => (),
+        x => panic!("Proposer should have been told about the failure, got {}", x),
+    }
+}
+
+#[test]
+fn membership_change_resolves_once_joint_configuration_commits() {
+    let clock = ManualClock::new();
+    let mut node = leader_of_two(&clock);
+
+    let (tx, rx) = channel();
+    node.change_membership(AddVoter("alice".to_string()), tx);
+    tick(&mut node);
+    assert!(rx.try_recv().is_err());
+
+    // joint configuration needs sarah in both the old and the new one
+    let index = node.log().len() - 1;
+    node.step(Pack("sarah".to_string(), "john".to_string(), Persisted(0, index + 1)));
+    tick(&mut node);
+
+    match rx.try_recv() {
+        Ok(Ok(Committed { index: committed, .. })) => assert_eq!(index, committed),
+        x => panic!("Change should have been committed, got {}", x),
+    }
+}
+
+#[test]
+fn membership_change_fails_when_leadership_is_lost_before_it_reaches_the_log() {
+    let clock = ManualClock::new();
+    let mut node = leader_of_two(&clock);
+
+    let (tx, rx) = channel();
+    node.change_membership(AddVoter("alice".to_string()), tx);
+
+    node.step(Pack("sarah".to_string(), "john".to_string(), Heartbeat(1, 0)));
+    tick(&mut node);
+
+    assert_eq!(Ok(Err(LeadershipLost)), rx.try_recv());
+}
+
+#[test]
+fn first_append_log_of_new_leader_carries_the_noop() {
+    let clock = ManualClock::new();
+    let mut node = raw_node("john", 150, &clock);
+    node.step(Pack("sarah".to_string(), "john".to_string(), Ack));
+
+    clock.advance(Duration::milliseconds(300));
+    tick(&mut node);
+    tick(&mut node);
+
+    node.step(Pack("john".to_string(), "john".to_string(), Vote(1)));
+    node.step(Pack("sarah".to_string(), "john".to_string(), Vote(1)));
+    assert_eq!(Leader, node.state());
+
+    let first = tick(&mut node).into_iter().filter_map(|(recipient, details)| match details {
+        AppendQuery(log) if recipient.as_slice() == "sarah" => Some(log),
+        _ => None,
+    }).next().expect("Leader should have sent an AppendLog");
+
+    assert_eq!(0, first.prev_log_index);
+    assert_eq!(NoopEntry, first.entries[0].entry);
+}
+
+#[test]
+fn election_timeout_is_between_one_and_two_timeouts() {
+    for seed in range(0, 20u32) {
+        let clock = ManualClock::new();
+        let mut node = raw_node("john", 150, &clock);
+        node.set_seed(seed);
+
+        clock.advance(Duration::milliseconds(150));
+        node.tick();
+        assert_eq!(Follower, node.state());
+
+        clock.advance(Duration::milliseconds(150));
+        node.tick();
+        assert_eq!(Candidate, node.state());
+    }
+}
+
+fn milliseconds_until_election(seed: u32) -> uint {
+    let clock = ManualClock::new();
+    let mut node = raw_node("john", 150, &clock);
+    node.set_seed(seed);
+
+    let mut passed = 0u;
+
+    while node.state() == Follower {
+        clock.advance(Duration::milliseconds(1));
+        node.tick();
+        passed += 1;
+    }
+
+    passed
+}
+
+#[test]
+fn same_seed_replays_election_timeout() {
+    assert_eq!(milliseconds_until_election(7), milliseconds_until_election(7));
+
+    let timeouts: Vec < uint > = range(0, 10u32).map(milliseconds_until_election).collect();
+    assert!(timeouts.iter().any(|x| *x != timeouts[0]));
+}
+
+#[test]
+fn leader_does_not_answer_read_before_majority_confirms_leadership() {
+    let clock = ManualClock::new();
+    let mut node = leader_of_two(&clock);
+
+    let (tx, rx) = channel();
+    node.query(DefaultQuery, tx);
+    assert!(latest_heartbeat_round(tick(&mut node)).is_some());
+    tick(&mut node);
+
+    drop(node);
+    assert!(rx.recv_opt().is_err());
+}
+
+#[test]
+fn leader_answers_read_once_majority_confirms_leadership() {
+    let clock = ManualClock::new();
+    let mut node = leader_of_two(&clock);
+
+    let (tx, rx) = channel();
+    node.query(DefaultQuery, tx);
+    let round = latest_heartbeat_round(tick(&mut node)).expect("Leader should have sent a heartbeat round");
+
+    node.step(Pack("sarah".to_string(), "john".to_string(), HeartbeatResponse(0, round)));
+    tick(&mut node);
+
+    drop(node);
+    assert_eq!(Ok(ReceivableInt(0)), rx.recv_opt());
+}
+
+#[test]
+fn follower_answers_read_once_it_applied_read_index() {
+    let clock = ManualClock::new();
+    let mut node = raw_node("john", 150, &clock);
+    node.force_follow(Some(NodeHost { host: "leader".to_string() }));
+    tick(&mut node);
+
+    let (tx, rx) = channel();
+    node.query(DefaultQuery, tx);
+    let requests = read_index_requests(tick(&mut node));
+    assert_eq!(vec![("leader".to_string(), 0u)], requests);
+
+    node.step(Pack("leader".to_string(), "john".to_string(), ReadIndexResponse(0, 1)));
+    tick(&mut node);
+
+    node.step(Pack("leader".to_string(), "john".to_string(), AppendQuery(AppendLog {
+        term: 0,
+        prev_log_index: 0,
+        prev_log_term: 0,
+        committed_offset: 1,
+        entries: vec![AppendLogEntry {
+            offset: 0,
+            term: 0,
+            entry: CommandEntry(DefaultCommandContainer { command: TestSet(4) }),
+        }],
+    })));
+    tick(&mut node);
+
+    // state machine is asked after the entry was handed to it
+    drop(node);
+    assert_eq!(Ok(ReceivableInt(4)), rx.recv_opt());
+}
+
+#[test]
+fn lost_heartbeat_round_is_sent_again_while_read_is_pending() {
+    let clock = ManualClock::new();
+    let mut node = leader_of_two(&clock);
+
+    let (tx, rx) = channel();
+    node.query(DefaultQuery, tx);
+    let lost_round = latest_heartbeat_round(tick(&mut node)).expect("Leader should have sent a heartbeat round");
+
+    clock.advance(Duration::milliseconds(80));
+    let round = latest_heartbeat_round(tick(&mut node)).expect("Leader should have sent another heartbeat round");
+    assert!(round > lost_round);
+
+    node.step(Pack("sarah".to_string(), "john".to_string(), HeartbeatResponse(0, round)));
+    tick(&mut node);
+
+    // read that was not served goes away with the node and its sender
+    drop(node);
+    assert_eq!(Ok(ReceivableInt(0)), rx.recv_opt());
+}
+
+#[test]
+fn forwarded_read_is_sent_again_and_dropped_on_leader_change() {
+    let clock = ManualClock::new();
+    let mut node = raw_node("sarah", 150, &clock);
+
+    node.step(Pack("john".to_string(), "sarah".to_string(), Heartbeat(0, 0)));
+    tick(&mut node);
+
+    let (tx, rx) = channel();
+    node.query(DefaultQuery, tx);
+    let requests = read_index_requests(tick(&mut node));
+    assert_eq!(1, requests.len());
+
+    clock.advance(Duration::milliseconds(100));
+    node.step(Pack("john".to_string(), "sarah".to_string(), Heartbeat(0, 0)));
+    assert!(read_index_requests(tick(&mut node)).is_empty());
+
+    // leader is alive but has not answered for an election timeout
+    clock.advance(Duration::milliseconds(100));
+    assert_eq!(requests, read_index_requests(tick(&mut node)));
+
+    node.step(Pack("alice".to_string(), "sarah".to_string(), Heartbeat(1, 0)));
+    tick(&mut node);
+
+    let (_, read_id) = requests[0].clone();
+    node.step(Pack("john".to_string(), "sarah".to_string(), ReadIndexResponse(read_id, 0)));
+    tick(&mut node);
+
+    drop(node);
+    assert!(rx.recv_opt().is_err());
+}
+
+#[test]
+fn leader_has_no_read_lease_during_leadership_transfer() {
+    let clock = ManualClock::new();
+    let mut node = leader_of_two(&clock);
+    take_read_lease(&mut node, &clock);
+
+    let (tx, _rx) = channel();
+    node.query(DefaultQuery, tx);
+    assert_eq!(None, latest_heartbeat_round(tick(&mut node)));
+
+    let (transfer_tx, _transfer_rx) = channel();
+    node.transfer_leadership("sarah".to_string(), transfer_tx);
+
+    // target may win an election any moment, leadership has to be confirmed
+    let (tx, rx) = channel();
+    node.query(DefaultQuery, tx);
+    assert!(latest_heartbeat_round(tick(&mut node)).is_some());
+
+    drop(node);
+    assert!(rx.recv_opt().is_err());
+}
+
+fn proposal_ids(messages: Vec < (String, PackageDetails < DefaultCommandContainer > ) >) -> Vec < uint > {
+    messages.into_iter().filter_map(|(_, details)| match details {
+        Propose(proposal_id, _) => Some(proposal_id),
+        _ => None,
+    }).collect()
+}
+
+#[test]
+fn forwarded_proposal_rejected_by_leader_fails_with_its_reason() {
+    let clock = ManualClock::new();
+    let mut node = raw_node("sarah", 150, &clock);
+    node.set_forward_proposals(true);
+
+    node.step(Pack("john".to_string(), "sarah".to_string(), Heartbeat(0, 0)));
+    tick(&mut node);
+
+    let (dropped_tx, dropped_rx) = channel();
+    node.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(4) }), dropped_tx);
+    let (moved_tx, moved_rx) = channel();
+    node.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(5) }), moved_tx);
+
+    let ids = proposal_ids(tick(&mut node));
+    assert_eq!(2, ids.len());
+
+    // leader could not store the first one, and is not the leader any more
+    // by the time the second one arrives
+    node.step(Pack("john".to_string(), "sarah".to_string(), ProposalRejected(ids[0], Some("john".to_string()))));
+    node.step(Pack("john".to_string(), "sarah".to_string(), ProposalRejected(ids[1], Some("alice".to_string()))));
+
+    assert_eq!(Ok(Err(ProposalDropped)), dropped_rx.try_recv());
+    assert_eq!(Ok(Err(NotLeader(Some(NodeHost { host: "alice".to_string() })))), moved_rx.try_recv());
+}
+
+#[test]
+fn follower_forgets_leader_of_older_term() {
+    let clock = ManualClock::new();
+    let mut node = forwarding_follower(&clock);
+    assert_eq!(Some(NodeHost { host: "leader".to_string() }), node.leader());
+
+    clock.advance(Duration::milliseconds(300));
+    node.step(Pack("candidate".to_string(), "john".to_string(), RequestVote(1, 0, 0, false)));
+    tick(&mut node);
+
+    let (tx, rx) = channel();
+    node.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(4) }), tx);
+
+    assert!(proposal_ids(tick(&mut node)).is_empty());
+    assert_eq!(Ok(Err(NotLeader(None))), rx.try_recv());
+}
+
+#[test]
+fn leader_with_lease_answers_without_heartbeat_round() {
+    let clock = ManualClock::new();
+    let mut node = leader_of_two(&clock);
+    take_read_lease(&mut node, &clock);
+
+    clock.advance(Duration::milliseconds(50));
+
+    let (tx, rx) = channel();
+    node.query(DefaultQuery, tx);
+    assert_eq!(None, latest_heartbeat_round(tick(&mut node)));
+
+    drop(node);
+    assert_eq!(Ok(ReceivableInt(0)), rx.recv_opt());
+}
+
+#[test]
+fn lease_expires_within_drift_bound_of_election_timeout() {
+    let clock = ManualClock::new();
+    let mut node = leader_of_two(&clock);
+    take_read_lease(&mut node, &clock);
+
+    // leader's clock lags behind: followers may already be electing a new
+    // leader. Regular heartbeat goes out first.
+    clock.advance(Duration::milliseconds(110));
+    tick(&mut node);
+
+    let (tx, rx) = channel();
+    node.query(DefaultQuery, tx);
+    let round = latest_heartbeat_round(tick(&mut node)).expect("Leader should have fallen back to read index");
+
+    node.step(Pack("sarah".to_string(), "john".to_string(), HeartbeatResponse(0, round)));
+    tick(&mut node);
+
+    drop(node);
+    assert_eq!(Ok(ReceivableInt(0)), rx.recv_opt());
+}
+
+#[test]
+fn follower_reports_not_leader_with_leader_hint() {
+    let clock = ManualClock::new();
+    let mut node = raw_node("sarah", 150, &clock);
+    node.force_follow(Some(NodeHost { host: "leader".to_string() }));
+
+    let (tx, rx) = channel();
+    node.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(4) }), tx);
+
+    assert_eq!(Ok(Err(NotLeader(Some(NodeHost { host: "leader".to_string() })))), rx.try_recv());
+}
+
+#[test]
+fn node_without_leader_reports_not_leader() {
+    let clock = ManualClock::new();
+    let mut node = raw_node("sarah", 150, &clock);
+    node.set_forward_proposals(true);
+
+    let (tx, rx) = channel();
+    node.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(4) }), tx);
+
+    assert_eq!(Ok(Err(NotLeader(None))), rx.try_recv());
+}
+
+#[test]
+fn follower_forwards_proposal_to_leader() {
+    let clock = ManualClock::new();
+    let hosts = ["john", "sarah", "alice"];
+    let mut nodes = elected_cluster(&hosts, &clock);
+    nodes.get_mut(1).set_forward_proposals(true);
+
+    let (tx, rx) = channel();
+    nodes.get_mut(1).enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(4) }), tx);
+    settle(&mut nodes, &hosts);
+
+    assert!(rx.try_recv().unwrap().is_ok());
+
+    let (tx, rx) = channel();
+    nodes.get_mut(0).query(DefaultQuery, tx);
+    settle(&mut nodes, &hosts);
+
+    drop(nodes);
+    assert_eq!(Ok(ReceivableInt(4)), rx.recv_opt());
+}
+
+#[test]
+fn proposal_resolves_once_committed_and_applied() {
+    let clock = ManualClock::new();
+    let hosts = ["john", "sarah", "alice"];
+    let mut nodes = elected_cluster(&hosts, &clock);
+
+    let (tx, rx) = channel();
+    nodes.get_mut(0).enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(4) }), tx);
+    settle(&mut nodes, &hosts);
+
+    match rx.try_recv() {
+        Ok(Ok(Committed { term, applied, .. })) => {
+            assert_eq!(1, term);
+            assert_eq!(Ok(Some(ReceivableInt(4))), applied);
+        },
+        x => panic!("Should have been committed, got {}", x),
+    }
+
+    let (tx, rx) = channel();
+    nodes.get_mut(0).query(DefaultQuery, tx);
+    settle(&mut nodes, &hosts);
+
+    drop(nodes);
+    assert_eq!(Ok(ReceivableInt(4)), rx.recv_opt());
+}
+
+#[test]
+fn proposal_fails_when_leadership_is_lost() {
+    let clock = ManualClock::new();
+    let mut node = forwarding_follower(&clock);
+
+    let (tx, rx) = channel();
+    node.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(4) }), tx);
+    let ids = proposal_ids(tick(&mut node));
+
+    node.step(Pack("leader".to_string(), "john".to_string(), ProposalAccepted(ids[0], 0, 0)));
+    tick(&mut node);
+    assert!(rx.try_recv().is_err());
+
+    // new leader shows up before the entry got committed
+    node.step(Pack("leader".to_string(), "john".to_string(), AppendQuery(AppendLog {
+        term: 1,
+        prev_log_index: 0,
+        prev_log_term: 0,
+        committed_offset: 0,
+        entries: vec![],
+    })));
+    tick(&mut node);
+
+    assert_eq!(Ok(Err(LeadershipLost)), rx.try_recv());
+}
+
+#[test]
+fn proposal_fails_when_entry_is_overwritten() {
+    let clock = ManualClock::new();
+    let mut node = forwarding_follower(&clock);
+
+    let (tx, rx) = channel();
+    node.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(4) }), tx);
+    let ids = proposal_ids(tick(&mut node));
+
+    node.step(Pack("leader".to_string(), "john".to_string(), ProposalAccepted(ids[0], 0, 0)));
+    tick(&mut node);
+
+    node.step(Pack("leader".to_string(), "john".to_string(), AppendQuery(AppendLog {
+        term: 1,
+        prev_log_index: 0,
+        prev_log_term: 0,
+        committed_offset: 0,
+        entries: vec![AppendLogEntry {
+            offset: 0,
+            term: 1,
+            entry: CommandEntry(DefaultCommandContainer { command: TestSet(7) }),
+        }],
+    })));
+    tick(&mut node);
+
+    assert_eq!(Ok(Err(EntryOverwritten)), rx.try_recv());
+}
+
+#[test]
+fn retried_command_is_applied_once() {
+    let clock = ManualClock::new();
+    let hosts = ["john", "sarah", "alice"];
+    let mut nodes = elected_cluster(&hosts, &clock);
+
+    let (tx, rx) = channel();
+    nodes.get_mut(0).enqueue(RegisterSessionEntry(0), tx);
+    settle(&mut nodes, &hosts);
+
+    let client_id = match rx.try_recv() {
+        Ok(Ok(Committed { index, .. })) => index,
+        x => panic!("Session should have been registered, got {}", x),
+    };
+
+    let (first_tx, first_rx) = channel();
+    nodes.get_mut(0).enqueue(SessionCommandEntry(session_command(client_id, 1, DefaultCommandContainer { command: TestAdd(5) })), first_tx);
+    settle(&mut nodes, &hosts);
+
+    let (retry_tx, retry_rx) = channel();
+    nodes.get_mut(0).enqueue(SessionCommandEntry(session_command(client_id, 1, DefaultCommandContainer { command: TestAdd(5) })), retry_tx);
+    settle(&mut nodes, &hosts);
+
+    match (first_rx.try_recv(), retry_rx.try_recv()) {
+        (Ok(Ok(first)), Ok(Ok(retry))) => assert_eq!(first.index, retry.index),
+        x => panic!("Both should have been committed, got {}", x),
+    }
+
+    let (tx, rx) = channel();
+    nodes.get_mut(0).query(DefaultQuery, tx);
+    settle(&mut nodes, &hosts);
+
+    drop(nodes);
+    assert_eq!(Ok(ReceivableInt(5)), rx.recv_opt());
+}
+
+#[test]
+fn retried_command_gets_answer_of_first_application() {
+    let clock = ManualClock::new();
+    let hosts = ["john", "sarah", "alice"];
+    let mut nodes = elected_cluster(&hosts, &clock);
+
+    let (tx, rx) = channel();
+    nodes.get_mut(0).enqueue(RegisterSessionEntry(0), tx);
+    settle(&mut nodes, &hosts);
+
+    let client_id = match rx.try_recv() {
+        Ok(Ok(Committed { index, .. })) => index,
+        x => panic!("Session should have been registered, got {}", x),
+    };
+
+    let (first_tx, first_rx) = channel();
+    nodes.get_mut(0).enqueue(SessionCommandEntry(session_command(client_id, 1, DefaultCommandContainer { command: TestAdd(5) })), first_tx);
+    settle(&mut nodes, &hosts);
+
+    let (tx, _rx) = channel();
+    nodes.get_mut(0).enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(3) }), tx);
+    settle(&mut nodes, &hosts);
+
+    let (retry_tx, retry_rx) = channel();
+    nodes.get_mut(0).enqueue(SessionCommandEntry(session_command(client_id, 1, DefaultCommandContainer { command: TestAdd(5) })), retry_tx);
+    settle(&mut nodes, &hosts);
+
+    match (first_rx.try_recv(), retry_rx.try_recv()) {
+        (Ok(Ok(first)), Ok(Ok(retry))) => {
+            assert_eq!(Ok(Some(ReceivableInt(5))), first.applied);
+            assert_eq!(Ok(Some(ReceivableInt(5))), retry.applied);
+        },
+        x => panic!("Both should have been committed, got {}", x),
+    }
+}
+
+#[test]
+fn command_of_unknown_session_is_not_applied() {
+    let clock = ManualClock::new();
+    let hosts = ["john", "sarah", "alice"];
+    let mut nodes = elected_cluster(&hosts, &clock);
+
+    let (tx, rx) = channel();
+    nodes.get_mut(0).enqueue(SessionCommandEntry(session_command(42, 1, DefaultCommandContainer { command: TestAdd(5) })), tx);
+    settle(&mut nodes, &hosts);
+
+    assert_eq!(Ok(Err(SessionExpired)), rx.try_recv());
+}
+
+fn log_with_three_entries_of_term(term: uint) -> DefaultReplicationLog {
+    let mut log: DefaultReplicationLog = ReplicationLog::new();
+
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(2) }), term).unwrap();
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(3) }), term).unwrap();
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(9) }), term).unwrap();
+
+    log
+}
+
+fn votes_sent_to(candidate: &str, messages: Vec < (String, PackageDetails < DefaultCommandContainer > ) >) -> Vec < uint > {
+    messages.into_iter().filter_map(|(recipient, details)| match details {
+        Vote(term) if recipient.as_slice() == candidate => Some(term),
+        _ => None,
+    }).collect()
+}
+
+#[test]
+fn refuses_to_vote_for_candidate_with_shorter_log() {
+    let clock = ManualClock::new();
+    let mut node = raw_node_with_log("john", 150, log_with_three_entries_of_term(1), &clock);
+
+    node.step(Pack("candidate".to_string(), "john".to_string(), RequestVote(5, 1, 1, false)));
+
+    assert!(votes_sent_to("candidate", tick(&mut node)).is_empty());
+}
+
+#[test]
+fn refuses_to_vote_for_candidate_with_older_last_term() {
+    let clock = ManualClock::new();
+    let mut node = raw_node_with_log("john", 150, log_with_three_entries_of_term(2), &clock);
+
+    node.step(Pack("candidate".to_string(), "john".to_string(), RequestVote(5, 10, 1, false)));
+
+    assert!(votes_sent_to("candidate", tick(&mut node)).is_empty());
+}
+
+#[test]
+fn votes_for_candidate_with_up_to_date_log() {
+    let clock = ManualClock::new();
+    let mut node = raw_node_with_log("john", 150, log_with_three_entries_of_term(1), &clock);
+
+    node.step(Pack("candidate".to_string(), "john".to_string(), RequestVote(5, 3, 1, false)));
+
+    assert_eq!(vec![5u], votes_sent_to("candidate", tick(&mut node)));
+}
+
+#[test]
+fn lagging_node_never_becomes_leader() {
+    let clock = ManualClock::new();
+    let mut nodes = elected_cluster(&["leader", "sarah", "john"], &clock);
+
+    // john is cut off while leader and sarah commit two more entries
+    let mut john = nodes.pop().unwrap();
+
+    nodes.get_mut(0).propose(CommandEntry(DefaultCommandContainer { command: TestSet(2) })).unwrap();
+    let index = nodes.get_mut(0).propose(CommandEntry(DefaultCommandContainer { command: TestAdd(3) })).unwrap();
+    let committed = settle(&mut nodes, &["leader", "sarah"]);
+
+    assert!(committed[1].iter().any(|entry| entry.offset == index));
+    assert!(john.log().len() < index);
+
+    // leader is gone for good, john gets back and times out first
+    nodes.remove(0);
+    let mut sarah = nodes.pop().unwrap();
+
+    while john.state() != Candidate {
+        clock.advance(Duration::milliseconds(1));
+        tick(&mut john);
+    }
+
+    for (recipient, details) in tick(&mut john).into_iter() {
+        if recipient.as_slice() == "sarah" {
+            sarah.step(Pack("john".to_string(), recipient, details));
+        }
+    }
+
+    assert!(votes_sent_to("john", tick(&mut sarah)).is_empty());
+
+    let hosts = ["sarah", "john"];
+    let mut nodes = vec![sarah, john];
+
+    for _ in range(0, 100u) {
+        clock.advance(Duration::milliseconds(10));
+        settle(&mut nodes, &hosts);
+
+        assert!(nodes[1].state() != Leader);
+
+        if nodes[0].state() == Leader {
+            break;
+        }
+    }
+
+    assert_eq!(Leader, nodes[0].state());
+}
+
+// Node sleeps for time_to_next_tick, but never longer than this
+static MAX_WAIT_MS: i64 = 100;
+
+// Plays the driver of every node for the given time: each one sleeps until
+// its next tick or until a package arrives. Returns how many times every node
+// woke up.
+fn count_wakeups(nodes: &mut Vec < TestNode >, hosts: &[&str], clock: &ManualClock, period: Duration) -> Vec < uint > {
+    let max_wait = Duration::milliseconds(MAX_WAIT_MS);
+    let mut wakeups: Vec < uint > = hosts.iter().map(|_| 0u).collect();
+    let mut passed = Duration::zero();
+    let mut wake_at: Vec < Duration > = nodes.iter().map(|node| cmp::min(node.time_to_next_tick(), max_wait)).collect();
+
+    loop {
+        let next = *wake_at.iter().min().unwrap();
+        if next > period {
+            break;
+        }
+
+        clock.advance(next - passed);
+        passed = next;
+
+        let mut due: Vec < uint > = range(0, nodes.len()).filter(|i| wake_at[*i] == next).collect();
+        for i in due.iter() {
+            *wakeups.get_mut(*i) += 1;
+        }
+
+        while !due.is_empty() {
+            let mut packages = vec![];
+
+            for i in due.iter() {
+                let node = nodes.get_mut(*i);
+                node.tick();
+
+                let (messages, _) = drain(node);
+                for (recipient, details) in messages.into_iter() {
+                    packages.push(Pack(hosts[*i].to_string(), recipient, details));
+                }
+
+                *wake_at.get_mut(*i) = passed + cmp::min(node.time_to_next_tick(), max_wait);
+            }
+
+            due = vec![];
+
+            // every package wakes its recipient
+            for package in packages.into_iter() {
+                let recipient = match package {
+                    Pack(_, ref to, _) => hosts.iter().position(|host| *host == to.as_slice()),
+                };
+
+                match recipient {
+                    Some(i) => {
+                        *wakeups.get_mut(i) += 1;
+                        nodes.get_mut(i).step(package);
+
+                        if !due.contains(&i) {
+                            due.push(i);
+                        }
+                    },
+                    None => (),
+                }
+            }
+        }
+    }
+
+    wakeups
+}
+
+// Idle nodes wake up for heartbeats, their answers and election timers only.
+// A node polling every few milliseconds would wake up hundreds of times.
+#[test]
+fn idle_cluster_wakes_up_for_heartbeats_only() {
+    let clock = ManualClock::new();
+    let hosts = ["john", "sarah", "alice"];
+    let mut nodes = elected_cluster(&hosts, &clock);
+
+    let period = Duration::seconds(1);
+    let wakeups = count_wakeups(&mut nodes, &hosts, &clock, period);
+
+    let config: NodeConfig = Default::default();
+    let rounds = (period.num_milliseconds() / config.heartbeat_interval.num_milliseconds()) as uint + 1;
+    let timeouts = (period.num_milliseconds() / MAX_WAIT_MS) as uint + 1;
+
+    for (host, count) in hosts.iter().zip(wakeups.iter()) {
+        assert!(*count > 0, "{} never woke up", host);
+        assert!(*count <= rounds * hosts.len() + timeouts, "idle {} woke up {} times in {} ms", host, count, period.num_milliseconds());
+    }
+
+    assert_eq!(Leader, nodes[0].state());
+}
+
+fn append_queries_to(follower: &str, messages: Vec < (String, PackageDetails < DefaultCommandContainer > ) >) -> Vec < AppendLog < DefaultCommandContainer > > {
+    messages.into_iter().filter_map(|(recipient, details)| match details {
+        AppendQuery(log) if recipient.as_slice() == follower => Some(log),
+        _ => None,
+    }).collect()
+}
+
+#[test]
+fn leader_backs_off_until_logs_match() {
+    let clock = ManualClock::new();
+    let mut leader = raw_node("leader", 150, &clock);
+    leader.force_state(Leader);
+    tick(&mut leader);
+
+    for i in range(0, 3i) {
+        leader.propose(CommandEntry(DefaultCommandContainer { command: TestSet(i) })).unwrap();
+    }
+    tick(&mut leader);
+
+    // configuration adding john goes to offset 4, after the initial one and
+    // three commands
+    let _change = change_membership(&mut leader, AddVoter("john".to_string()));
+    tick(&mut leader);
+
+    // first heartbeat probes at the end of leader's log
+    clock.advance(Duration::milliseconds(80));
+    let probe = append_queries_to("john", tick(&mut leader)).into_iter().map(|log| log.prev_log_index).last();
+    assert_eq!(Some(5), probe);
+
+    leader.step(Pack("john".to_string(), "leader".to_string(), AppendRejected(0, 5, 1)));
+
+    let probe = append_queries_to("john", tick(&mut leader)).into_iter()
+        .filter(|log| !log.entries.is_empty())
+        .map(|log| (log.prev_log_index, log.entries.iter().map(|x| x.offset).collect()))
+        .last();
+    assert_eq!(Some((1u, vec![1u, 2, 3, 4])), probe);
+}
+
+#[test]
+fn lagging_follower_is_brought_up_to_date() {
+    let clock = ManualClock::new();
+    let hosts = ["leader", "john"];
+    let mut nodes = vec![raw_node("leader", 150, &clock), raw_node("john", 300, &clock)];
+
+    nodes.get_mut(0).force_state(Leader);
+
+    for command in vec![TestSet(2), TestAdd(3), TestSet(9)].into_iter() {
+        nodes.get_mut(0).propose(CommandEntry(DefaultCommandContainer { command: command })).unwrap();
+    }
+    settle(&mut nodes, &hosts);
+
+    nodes.get_mut(1).introduce("leader".to_string());
+    let _change = change_membership(nodes.get_mut(0), AddVoter("john".to_string()));
+    settle(&mut nodes, &hosts);
+
+    let (tx, rx) = channel();
+    nodes.get_mut(1).query(DefaultQuery, tx);
+    settle(&mut nodes, &hosts);
+
+    // state machine is asked after the entries were handed to it
+    drop(nodes);
+    assert_eq!(Ok(ReceivableInt(9)), rx.recv_opt());
+}
+
+// Starts the change on the node, the receiver tells what became of it
+fn change_membership(node: &mut TestNode, change: MembershipChange) -> Receiver < Result < Committed < DefaultReceivable >, ProposalError > > {
+    let (tx, rx) = channel();
+    node.change_membership(change, tx);
+    rx
+}
+
+fn sorted_hosts(nodes: Vec < NodeHost >) -> Vec < String > {
+    let mut hosts: Vec < String > = nodes.into_iter().map(|x| x.host).collect();
+    hosts.sort();
+    hosts
+}
+
+fn leaders(nodes: &[TestNode]) -> uint {
+    nodes.iter().filter(|node| node.state() == Leader).count()
+}
+
+// Lets the time pass in steps of 10ms, nodes hear each other meanwhile
+fn run_for(nodes: &mut Vec < TestNode >, hosts: &[&str], clock: &ManualClock, ms: uint) {
+    for _ in range(0, ms / 10) {
+        clock.advance(Duration::milliseconds(10));
+        settle(nodes, hosts);
+    }
+}
+
+#[test]
+fn voter_is_added_through_the_log() {
+    let clock = ManualClock::new();
+    let hosts = ["leader", "sarah", "james"];
+    let mut nodes = elected_cluster(hosts.slice_to(2), &clock);
+    nodes.push(raw_node("james", 300, &clock));
+
+    let _change = change_membership(nodes.get_mut(0), AddVoter("james".to_string()));
+    run_for(&mut nodes, &hosts, &clock, 100);
+
+    let expected = vec!["james".to_string(), "leader".to_string(), "sarah".to_string()];
+    for node in nodes.iter() {
+        assert_eq!(expected, sorted_hosts(node.nodes()));
+    }
+    assert_eq!(Follower, nodes[2].state());
+}
+
+#[test]
+fn follower_rejects_change_with_leader_hint() {
+    let clock = ManualClock::new();
+    let mut nodes = elected_cluster(&["leader", "sarah"], &clock);
+
+    let hint = Some(NodeHost { host: "leader".to_string() });
+    assert_eq!(Ok(Err(NotLeader(hint.clone()))), change_membership(nodes.get_mut(1), AddVoter("james".to_string())).try_recv());
+    assert_eq!(Ok(Err(NotLeader(hint))), change_membership(nodes.get_mut(1), RemoveVoter("leader".to_string())).try_recv());
+
+    let expected = vec!["leader".to_string(), "sarah".to_string()];
+    assert_eq!(expected, sorted_hosts(nodes[0].nodes()));
+}
+
+#[test]
+fn removed_node_does_not_disrupt_the_cluster() {
+    let clock = ManualClock::new();
+    let hosts = ["leader", "sarah", "john"];
+    let mut nodes = elected_cluster(&hosts, &clock);
+
+    let _change = change_membership(nodes.get_mut(0), RemoveVoter("john".to_string()));
+    run_for(&mut nodes, &hosts, &clock, 100);
+
+    let expected = vec!["leader".to_string(), "sarah".to_string()];
+    assert_eq!(expected, sorted_hosts(nodes[0].nodes()));
+    assert_eq!(expected, sorted_hosts(nodes[1].nodes()));
+
+    // john hears nothing anymore and stands for election again and again
+    for _ in range(0, 10u) {
+        run_for(&mut nodes, &hosts, &clock, 100);
+
+        assert_eq!(Leader, nodes[0].state());
+        assert!(nodes[2].state() != Leader);
+    }
+}
+
+#[test]
+fn removed_leader_steps_down() {
+    let clock = ManualClock::new();
+    let hosts = ["leader", "sarah", "john"];
+    let mut nodes = elected_cluster(&hosts, &clock);
+
+    let _change = change_membership(nodes.get_mut(0), RemoveVoter("leader".to_string()));
+    run_for(&mut nodes, &hosts, &clock, 1000);
+
+    assert_eq!(Follower, nodes[0].state());
+    assert_eq!(1, leaders(nodes.slice_from(1)));
+
+    let expected = vec!["john".to_string(), "sarah".to_string()];
+    assert_eq!(expected, sorted_hosts(nodes[1].nodes()));
+    assert_eq!(expected, sorted_hosts(nodes[2].nodes()));
+}
+
+#[test]
+fn cluster_agrees_on_configuration_after_leader_crashes_mid_change() {
+    let clock = ManualClock::new();
+    let mut nodes = elected_cluster(&["leader", "sarah", "john", "james"], &clock);
+
+    // joint configuration reaches sarah only before the leader crashes
+    let _change = change_membership(nodes.get_mut(0), RemoveVoter("james".to_string()));
+    let mut leader = nodes.remove(0).unwrap();
+
+    for (recipient, details) in tick(&mut leader).into_iter() {
+        if recipient.as_slice() == "sarah" {
+            nodes.get_mut(0).step(Pack("leader".to_string(), recipient, details));
+        }
+    }
+
+    let hosts = ["sarah", "john", "james"];
+    run_for(&mut nodes, &hosts, &clock, 1500);
+
+    assert_eq!(1, leaders(nodes.as_slice()));
+    assert_eq!(sorted_hosts(nodes[0].nodes()), sorted_hosts(nodes[1].nodes()));
+}
+
+#[test]
+fn leadership_moves_to_target() {
+    let clock = ManualClock::new();
+    let hosts = ["leader", "sarah", "john"];
+    let mut nodes = elected_cluster(&hosts, &clock);
+
+    let (tx, rx) = channel();
+    nodes.get_mut(0).transfer_leadership("sarah".to_string(), tx);
+    settle(&mut nodes, &hosts);
+
+    assert_eq!(Ok(Transferred), rx.try_recv());
+    assert_eq!(Leader, nodes[1].state());
+    assert_eq!(Follower, nodes[0].state());
+    assert_eq!(Some(NodeHost { host: "sarah".to_string() }), nodes[0].leader());
+}
+
+#[test]
+fn transfer_to_unreachable_target_times_out() {
+    let clock = ManualClock::new();
+    let hosts = ["leader", "sarah"];
+    let mut nodes = elected_cluster(&["leader", "sarah", "john"], &clock);
+    nodes.pop();
+
+    let (tx, rx) = channel();
+    nodes.get_mut(0).transfer_leadership("john".to_string(), tx);
+    run_for(&mut nodes, &hosts, &clock, 500);
+
+    assert_eq!(Ok(TransferTimedOut), rx.try_recv());
+    assert_eq!(Leader, nodes[0].state());
+}
+
+#[test]
+fn only_leader_transfers_leadership() {
+    let clock = ManualClock::new();
+    let mut nodes = elected_cluster(&["leader", "sarah"], &clock);
+
+    let (tx, rx) = channel();
+    nodes.get_mut(1).transfer_leadership("leader".to_string(), tx.clone());
+    assert_eq!(Ok(TransferRejected), rx.try_recv());
+
+    nodes.get_mut(0).transfer_leadership("stranger".to_string(), tx);
+    assert_eq!(Ok(TransferRejected), rx.try_recv());
+}
+
+#[test]
+fn leader_cut_off_from_majority_steps_down() {
+    let clock = ManualClock::new();
+    let mut nodes = elected_cluster(&["leader", "sarah", "john"], &clock);
+    nodes.truncate(1);
+
+    let (tx, rx) = channel();
+    nodes.get_mut(0).set_check_quorum(true);
+    nodes.get_mut(0).observe_state(tx);
+
+    run_for(&mut nodes, &["leader"], &clock, 800);
+
+    assert!(nodes[0].state() != Leader);
+    assert_eq!(Ok(Follower), rx.try_recv());
+}
+
+#[test]
+fn leader_hearing_from_majority_stays_leader() {
+    let clock = ManualClock::new();
+    let hosts = ["leader", "sarah"];
+    let mut nodes = elected_cluster(&["leader", "sarah", "john"], &clock);
+    nodes.pop();
+
+    nodes.get_mut(0).set_check_quorum(true);
+
+    for _ in range(0, 16u) {
+        run_for(&mut nodes, &hosts, &clock, 50);
+        assert_eq!(Leader, nodes[0].state());
+    }
+}
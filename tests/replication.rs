@@ -1,6 +1,7 @@
 extern crate raft_rs;
 
-use raft_rs::replication::{DefaultCommandContainer, DefaultPersistence, DefaultReplicationLog, ReplicationLog, TestAdd, TestSet, Committable, LogPersistence};
+use raft_rs::replication::{DefaultCommandContainer, DefaultPersistence, DefaultReplicationLog, ReplicationLog, TestAdd, TestSet, ReceivableInt, Committable, LogPersistence, SnapshotPersistence, Snapshot, CommandEntry, RegisterSessionEntry, SessionCommandEntry};
+use raft_rs::session::SessionCommand;
 
 #[test]
 fn default_persistance_implements_commit() {
@@ -20,9 +21,9 @@ fn default_persistance_implements_commit() {
 fn enqueue_and_commit_command() {
     let mut log: DefaultReplicationLog = ReplicationLog::new();
 
-    log.enqueue(DefaultCommandContainer { command: TestSet(3) });
-    log.enqueue(DefaultCommandContainer { command: TestAdd(5) });
-    log.enqueue(DefaultCommandContainer { command: TestSet(21) });
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(3) }), 0);
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), 0);
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(21) }), 0);
 
     assert_eq!(3, log.len());
     assert_eq!(0, log.committed_offset());
@@ -38,9 +39,9 @@ fn enqueue_and_commit_command() {
 fn enqueue_and_discard_commands() {
     let mut log: DefaultReplicationLog = ReplicationLog::new();
 
-    log.enqueue(DefaultCommandContainer { command: TestSet(3) });
-    log.enqueue(DefaultCommandContainer { command: TestAdd(5) });
-    log.enqueue(DefaultCommandContainer { command: TestSet(21) });
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(3) }), 0);
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), 0);
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(21) }), 0);
 
     assert_eq!(3, log.len());
     assert_eq!(0, log.committed_offset());
@@ -55,3 +56,155 @@ fn enqueue_and_discard_commands() {
     assert_eq!(1, log.committed_offset());
     assert_eq!(3, log.persistence.rx.recv());
 }
+
+#[test]
+fn enqueue_remembers_term_of_the_last_entry() {
+    let mut log: DefaultReplicationLog = ReplicationLog::new();
+
+    assert_eq!(0, log.last_term());
+
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(3) }), 1);
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), 1);
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(21) }), 2);
+
+    assert_eq!(2, log.last_term());
+
+    log.discard_downto(2);
+
+    assert_eq!(1, log.last_term());
+}
+
+#[test]
+fn term_at_returns_term_of_the_entry() {
+    let mut log: DefaultReplicationLog = ReplicationLog::new();
+
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(3) }), 1);
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), 2);
+
+    assert_eq!(Some(1), log.term_at(0));
+    assert_eq!(Some(2), log.term_at(1));
+    assert_eq!(None, log.term_at(2));
+}
+
+#[test]
+fn default_persistance_takes_and_restores_snapshots() {
+    let persistence = DefaultPersistence::start();
+
+    persistence.commit(DefaultCommandContainer { command: TestSet(3) });
+    assert_eq!(3, persistence.rx.recv());
+
+    let snapshot = persistence.snapshot().unwrap();
+
+    persistence.commit(DefaultCommandContainer { command: TestAdd(5) });
+    assert_eq!(8, persistence.rx.recv());
+
+    persistence.restore(snapshot.as_slice()).unwrap();
+    assert_eq!(3, persistence.rx.recv());
+}
+
+#[test]
+fn compaction_drops_committed_prefix_and_keeps_offsets() {
+    let mut log: DefaultReplicationLog = ReplicationLog::new();
+
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(3) }), 1);
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), 1);
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(21) }), 2);
+
+    log.commit_upto(2);
+    log.compact().unwrap();
+
+    assert_eq!(3, log.len());
+    assert_eq!(2, log.first_offset());
+    assert_eq!(None, log.term_at(0));
+    assert_eq!(Some(1), log.term_at(1));
+    assert_eq!(Some(2), log.term_at(2));
+
+    let snapshot = log.snapshot().unwrap();
+    assert_eq!(2, snapshot.last_included_index);
+    assert_eq!(1, snapshot.last_included_term);
+}
+
+#[test]
+fn installing_snapshot_restores_state_and_replaces_log() {
+    let mut log: DefaultReplicationLog = ReplicationLog::new();
+
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(3) }), 1);
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(5) }), 1);
+    log.commit_upto(2);
+    log.compact().unwrap();
+
+    let snapshot: Snapshot = log.snapshot().unwrap();
+
+    let mut other: DefaultReplicationLog = ReplicationLog::new();
+    other.enqueue(CommandEntry(DefaultCommandContainer { command: TestSet(42) }), 0);
+
+    other.install_snapshot(snapshot).unwrap();
+
+    assert_eq!(8, other.persistence.rx.recv());
+    assert_eq!(2, other.len());
+    assert_eq!(2, other.first_offset());
+    assert_eq!(2, other.committed_offset());
+    assert_eq!(1, other.last_term());
+
+    other.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(2) }), 3);
+    other.commit_upto(3);
+
+    assert_eq!(10, other.persistence.rx.recv());
+}
+
+#[test]
+fn session_command_is_applied_once() {
+    let mut log: DefaultReplicationLog = ReplicationLog::new();
+
+    let command = SessionCommand {
+        client_id: 0,
+        sequence: 1,
+        timestamp: 10,
+        command: DefaultCommandContainer { command: TestAdd(5) },
+    };
+
+    log.enqueue(RegisterSessionEntry(0), 1);
+    log.enqueue(SessionCommandEntry(command.clone()), 1);
+    log.enqueue(SessionCommandEntry(command.clone()), 1);
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(1) }), 1);
+    log.commit_upto(4);
+
+    assert_eq!(5, log.persistence.rx.recv());
+    assert_eq!(6, log.persistence.rx.recv());
+    assert_eq!(1, log.sessions().response(0, 1).unwrap().index);
+}
+
+#[test]
+fn retried_session_command_gets_cached_response() {
+    let mut log: DefaultReplicationLog = ReplicationLog::new();
+
+    let command = SessionCommand {
+        client_id: 0,
+        sequence: 1,
+        timestamp: 10,
+        command: DefaultCommandContainer { command: TestAdd(5) },
+    };
+
+    log.enqueue(RegisterSessionEntry(0), 1);
+    log.enqueue(SessionCommandEntry(command.clone()), 1);
+    log.enqueue(CommandEntry(DefaultCommandContainer { command: TestAdd(1) }), 1);
+    log.enqueue(SessionCommandEntry(command.clone()), 1);
+    log.commit_upto(4);
+
+    assert_eq!(vec![(1, ReceivableInt(5)), (2, ReceivableInt(6)), (3, ReceivableInt(5))], log.take_responses());
+    assert!(log.take_responses().is_empty());
+}
+
+#[test]
+fn installing_snapshot_restores_sessions() {
+    let mut log: DefaultReplicationLog = ReplicationLog::new();
+
+    log.enqueue(RegisterSessionEntry(0), 1);
+    log.commit_upto(1);
+    log.compact().unwrap();
+
+    let mut other: DefaultReplicationLog = ReplicationLog::new();
+    other.install_snapshot(log.snapshot().unwrap()).unwrap();
+
+    assert!(other.sessions().contains(0));
+}
@@ -0,0 +1,82 @@
+extern crate raft_rs;
+
+use raft_rs::session::{Sessions, CachedResponse, FreshRequest, DuplicateRequest, UnknownSession};
+use raft_rs::session::{encode_response, decode_response};
+
+#[test]
+fn command_of_unregistered_client_is_unknown() {
+    let mut sessions = Sessions::new(1000);
+
+    assert_eq!(UnknownSession, sessions.check(3, 1, 0));
+}
+
+#[test]
+fn applied_sequence_is_a_duplicate() {
+    let mut sessions = Sessions::new(1000);
+    sessions.register(3, 0);
+
+    assert_eq!(FreshRequest, sessions.check(3, 1, 10));
+    sessions.applied(3, 1, 7, 2, vec![]);
+
+    assert_eq!(DuplicateRequest, sessions.check(3, 1, 20));
+    assert_eq!(FreshRequest, sessions.check(3, 2, 20));
+}
+
+#[test]
+fn sequences_may_be_applied_out_of_order() {
+    let mut sessions = Sessions::new(1000);
+    sessions.register(3, 0);
+
+    // second command of a pipelining client gets to the log first
+    assert_eq!(FreshRequest, sessions.check(3, 2, 10));
+    sessions.applied(3, 2, 7, 2, vec![]);
+
+    assert_eq!(FreshRequest, sessions.check(3, 1, 20));
+    sessions.applied(3, 1, 8, 2, vec![]);
+
+    assert_eq!(DuplicateRequest, sessions.check(3, 1, 30));
+    assert_eq!(DuplicateRequest, sessions.check(3, 2, 30));
+}
+
+#[test]
+fn sequences_stay_applied_once_their_responses_are_forgotten() {
+    let mut sessions = Sessions::new(1000);
+    sessions.register(3, 0);
+
+    for sequence in range(1u, 18) {
+        sessions.check(3, sequence, 10);
+        sessions.applied(3, sequence, sequence, 1, vec![]);
+    }
+
+    assert_eq!(None, sessions.response(3, 1));
+    assert_eq!(DuplicateRequest, sessions.check(3, 1, 20));
+    assert!(sessions.response(3, 2).is_some());
+    assert_eq!(FreshRequest, sessions.check(3, 18, 20));
+}
+
+#[test]
+fn response_of_applied_command_is_cached() {
+    let mut sessions = Sessions::new(1000);
+    sessions.register(3, 0);
+
+    sessions.check(3, 1, 10);
+    sessions.applied(3, 1, 7, 2, encode_response(&42i));
+
+    assert_eq!(Some(CachedResponse { sequence: 1, index: 7, term: 2, response: encode_response(&42i) }), sessions.response(3, 1));
+    assert_eq!(None, sessions.response(3, 2));
+
+    let response: Option < int > = decode_response(sessions.response(3, 1).unwrap().response.as_slice());
+    assert_eq!(Some(42), response);
+}
+
+#[test]
+fn idle_sessions_expire_by_entry_timestamps() {
+    let mut sessions = Sessions::new(1000);
+    sessions.register(3, 0);
+    sessions.register(5, 600);
+
+    assert_eq!(FreshRequest, sessions.check(5, 1, 1500));
+
+    assert!(!sessions.contains(3));
+    assert_eq!(UnknownSession, sessions.check(3, 1, 1500));
+}
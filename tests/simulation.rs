@@ -0,0 +1,162 @@
+extern crate raft_rs;
+
+use raft_rs::intercommunication::{Intercommunication, DefaultIntercommunication, Endpoint, Pack, Vote, start};
+use raft_rs::simulation::SimulatedIntercommunication;
+use raft_rs::replication::DefaultCommandContainer;
+
+use std::io::timer::sleep;
+use std::time::duration::Duration;
+
+type Comm = SimulatedIntercommunication < DefaultCommandContainer, DefaultIntercommunication < DefaultCommandContainer > >;
+
+// terms of every Vote received within the time
+fn votes(endpoint: &Endpoint < DefaultCommandContainer >, ms: uint) -> Vec < uint > {
+    let mut votes = vec![];
+
+    for _ in range(0, ms / 2) {
+        loop {
+            match endpoint.listen() {
+                Some(Pack(_, _, Vote(term))) => votes.push(term),
+                Some(_) => (),
+                None => break,
+            }
+        }
+
+        sleep(Duration::milliseconds(2));
+    }
+
+    votes
+}
+
+fn send_votes(endpoint: &Endpoint < DefaultCommandContainer >, to: &str, count: uint) {
+    for term in range(0, count) {
+        endpoint.send(to.to_string(), Vote(term));
+    }
+}
+
+#[test]
+fn reliable_by_default() {
+    let mut comm: Comm = Intercommunication::new();
+    let john = comm.register("john".to_string());
+    let sarah = comm.register("sarah".to_string());
+    let sig = start(comm);
+
+    send_votes(&john, "sarah", 10);
+
+    assert_eq!(range(0, 10u).collect::< Vec < uint > >(), votes(&sarah, 100));
+
+    sig.send(0);
+}
+
+#[test]
+fn drops_packages() {
+    let mut comm: Comm = Intercommunication::new();
+    let john = comm.register("john".to_string());
+    let sarah = comm.register("sarah".to_string());
+    comm.network.set_drop_rate(1.0);
+    let sig = start(comm);
+
+    send_votes(&john, "sarah", 10);
+
+    assert_eq!(0, votes(&sarah, 100).len());
+
+    sig.send(0);
+}
+
+#[test]
+fn duplicates_packages() {
+    let mut comm: Comm = Intercommunication::new();
+    let john = comm.register("john".to_string());
+    let sarah = comm.register("sarah".to_string());
+    comm.network.set_duplicate_rate(1.0);
+    let sig = start(comm);
+
+    send_votes(&john, "sarah", 10);
+
+    assert_eq!(20, votes(&sarah, 100).len());
+
+    sig.send(0);
+}
+
+#[test]
+fn reorders_packages_with_varying_latency() {
+    let mut comm: Comm = Intercommunication::new();
+    let john = comm.register("john".to_string());
+    let sarah = comm.register("sarah".to_string());
+    comm.network.set_latency(Duration::zero(), Duration::milliseconds(50));
+    let sig = start(comm);
+
+    send_votes(&john, "sarah", 20);
+
+    let mut received = votes(&sarah, 200);
+    assert!(received != range(0, 20u).collect::< Vec < uint > >());
+
+    received.sort();
+    assert_eq!(range(0, 20u).collect::< Vec < uint > >(), received);
+
+    sig.send(0);
+}
+
+#[test]
+fn blocks_one_direction_only() {
+    let mut comm: Comm = Intercommunication::new();
+    let john = comm.register("john".to_string());
+    let sarah = comm.register("sarah".to_string());
+    comm.network.block("john", "sarah");
+    let sig = start(comm);
+
+    send_votes(&john, "sarah", 3);
+    send_votes(&sarah, "john", 3);
+
+    assert_eq!(0, votes(&sarah, 50).len());
+    assert_eq!(3, votes(&john, 50).len());
+
+    sig.send(0);
+}
+
+#[test]
+fn partition_heals() {
+    let mut comm: Comm = Intercommunication::new();
+    let john = comm.register("john".to_string());
+    let sarah = comm.register("sarah".to_string());
+    let james = comm.register("james".to_string());
+    let network = comm.network.clone();
+    network.partition(&["john"], &["sarah", "james"]);
+    let sig = start(comm);
+
+    send_votes(&john, "sarah", 3);
+    send_votes(&james, "john", 3);
+    send_votes(&james, "sarah", 3);
+
+    assert_eq!(3, votes(&sarah, 50).len());
+    assert_eq!(0, votes(&john, 50).len());
+
+    network.heal();
+
+    send_votes(&john, "sarah", 3);
+    assert_eq!(3, votes(&sarah, 50).len());
+
+    sig.send(0);
+}
+
+#[test]
+fn same_seed_loses_same_packages() {
+    let run = |seed: u32| -> Vec < uint > {
+        let mut comm: Comm = SimulatedIntercommunication::with_seed(seed);
+        let john = comm.register("john".to_string());
+        let sarah = comm.register("sarah".to_string());
+        comm.network.set_drop_rate(0.5);
+        let sig = start(comm);
+
+        send_votes(&john, "sarah", 50);
+        let received = votes(&sarah, 100);
+
+        sig.send(0);
+        received
+    };
+
+    let first = run(42);
+
+    assert!(first.len() > 0 && first.len() < 50);
+    assert_eq!(first, run(42));
+}
@@ -0,0 +1,48 @@
+extern crate raft_rs;
+
+use std::io::TempDir;
+use std::io::fs::PathExtensions;
+
+use raft_rs::storage::{HardState, HardStateStorage, FileHardStateStorage, MemoryHardStateStorage};
+
+#[test]
+fn memory_storage_starts_with_empty_hard_state() {
+    let mut storage = MemoryHardStateStorage::new();
+
+    assert_eq!(HardState::new(), storage.load().unwrap());
+}
+
+#[test]
+fn memory_storage_is_shared_between_clones() {
+    let mut storage = MemoryHardStateStorage::new();
+    let mut restarted = storage.clone();
+
+    storage.save(&HardState { term: 3, voted_for: Some("john".to_string()), commit: 7 }).unwrap();
+
+    assert_eq!(HardState { term: 3, voted_for: Some("john".to_string()), commit: 7 }, restarted.load().unwrap());
+}
+
+#[test]
+fn file_storage_starts_with_empty_hard_state() {
+    let dir = TempDir::new("raft-rs-storage").unwrap();
+    let mut storage = FileHardStateStorage::new(&dir.path().join("hard_state"));
+
+    assert_eq!(HardState::new(), storage.load().unwrap());
+}
+
+#[test]
+fn file_storage_survives_restart() {
+    let dir = TempDir::new("raft-rs-storage").unwrap();
+    let path = dir.path().join("hard_state");
+
+    {
+        let mut storage = FileHardStateStorage::new(&path);
+        storage.save(&HardState { term: 2, voted_for: Some("sarah".to_string()), commit: 1 }).unwrap();
+        storage.save(&HardState { term: 4, voted_for: None, commit: 5 }).unwrap();
+    }
+
+    let mut storage = FileHardStateStorage::new(&path);
+
+    assert_eq!(HardState { term: 4, voted_for: None, commit: 5 }, storage.load().unwrap());
+    assert!(!path.with_extension("tmp").exists());
+}